@@ -0,0 +1,275 @@
+//! 管道客户端鉴权策略
+//!
+//! 管道本身的 DACL 只允许 SYSTEM 和管理员打开，连接建立后服务端再根据客户端进程的
+//! 令牌 SID 和镜像路径做一次校验。这里只做纯粹的判断，取 SID、取路径、取目录的部分在 [`crate::pipe`] 中。
+
+use crate::ProtocolError;
+
+/// LocalSystem
+pub const SYSTEM_SID: &str = "S-1-5-18";
+
+/// BUILTIN\Administrators
+pub const ADMINISTRATORS_SID: &str = "S-1-5-32-544";
+
+/// 管道安全描述符：禁止继承，只给 SYSTEM 和管理员完全控制权
+pub const PIPE_SDDL: &str = "D:P(A;;GA;;;SY)(A;;GA;;;BA)";
+
+/// Unlock 服务
+pub const SERVER_EXE: &str = "FaceWinUnlock-Server.exe";
+
+/// 界面程序
+pub const UI_EXE: &str = "facewinunlock-tauri.exe";
+
+/// 加载凭据提供程序 DLL 的系统进程
+pub const LOGON_UI_EXE: &str = "LogonUI.exe";
+
+/// UAC 提权对话框，以 SYSTEM 身份在安全桌面上加载凭据提供程序 DLL
+pub const CONSENT_EXE: &str = "consent.exe";
+
+/// 客户端程序所在的目录
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientDir {
+    /// %SystemRoot%\System32
+    System32,
+    /// 软件安装目录
+    Install,
+}
+
+/// 允许连接的一个程序：所在目录加文件名，只认完整路径，同名程序放在其他目录不能通过
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllowedClient {
+    pub dir: ClientDir,
+    pub image: &'static str,
+}
+
+impl AllowedClient {
+    pub const fn system(image: &'static str) -> Self {
+        Self {
+            dir: ClientDir::System32,
+            image,
+        }
+    }
+
+    pub const fn install(image: &'static str) -> Self {
+        Self {
+            dir: ClientDir::Install,
+            image,
+        }
+    }
+}
+
+/// 校验时使用的目录，由各端在运行时取得
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientDirs {
+    /// 系统目录，如 `C:\Windows\System32`
+    pub system32: String,
+    /// 软件安装目录，未初始化时为 None，安装目录中的程序一律拒绝
+    pub install: Option<String>,
+}
+
+impl ClientDirs {
+    fn get(&self, dir: ClientDir) -> Option<&str> {
+        match dir {
+            ClientDir::System32 => Some(self.system32.as_str()),
+            ClientDir::Install => self.install.as_deref(),
+        }
+    }
+}
+
+/// 允许连接 DLL 管道的程序：Unlock 服务发送凭据，UI 检测 DLL 是否加载
+pub const SERVER_PIPE_CLIENTS: &[AllowedClient] = &[
+    AllowedClient::install(SERVER_EXE),
+    AllowedClient::install(UI_EXE),
+];
+
+/// 允许连接 Unlock 管道的程序：DLL（锁屏界面或 UAC 对话框）通知开始识别，UI 发送控制指令
+pub const UNLOCK_PIPE_CLIENTS: &[AllowedClient] = &[
+    AllowedClient::system(LOGON_UI_EXE),
+    AllowedClient::system(CONSENT_EXE),
+    AllowedClient::install(UI_EXE),
+];
+
+/// 允许订阅识别事件的程序：只有 UI
+pub const EVENT_PIPE_CLIENTS: &[AllowedClient] = &[AllowedClient::install(UI_EXE)];
+
+/// 统一路径的写法用于比较：去掉 `\\?\` 前缀和结尾的分隔符，`/` 换成 `\`，不区分大小写
+///
+/// 带 `..`、`.` 的路径不做解析，直接返回 None
+pub fn normalize_path(path: &str) -> Option<String> {
+    let path = path.trim();
+    let path = path
+        .strip_prefix(r"\\?\")
+        .unwrap_or(path)
+        .replace('/', "\\");
+    let path = path.trim_end_matches('\\');
+    if path.is_empty() || path.split('\\').any(|part| part == ".." || part == ".") {
+        return None;
+    }
+    Some(path.to_lowercase())
+}
+
+/// 判断客户端是否允许连接
+///
+/// `sids` 是客户端令牌的用户 SID 和所有已启用的组 SID，必须包含 SYSTEM 或 Administrators；
+/// `image_path` 是客户端进程的完整镜像路径，必须正好是 `allowed` 中某个程序在 `dirs` 对应目录下的路径（不区分大小写）。
+pub fn authorize_caller<S: AsRef<str>>(
+    sids: &[S],
+    image_path: &str,
+    allowed: &[AllowedClient],
+    dirs: &ClientDirs,
+) -> Result<(), ProtocolError> {
+    let privileged = sids.iter().any(|sid| {
+        let sid = sid.as_ref();
+        sid.eq_ignore_ascii_case(SYSTEM_SID) || sid.eq_ignore_ascii_case(ADMINISTRATORS_SID)
+    });
+    if !privileged {
+        return Err(ProtocolError::Unauthorized(String::from(
+            "客户端不是 SYSTEM 或管理员",
+        )));
+    }
+
+    // 网络路径上的程序一律拒绝
+    let image = normalize_path(image_path)
+        .filter(|path| !path.starts_with(r"\\") && !path.starts_with(r"unc\"))
+        .ok_or_else(|| {
+            ProtocolError::Unauthorized(format!("客户端程序路径不合法：{}", image_path))
+        })?;
+
+    let matched = allowed.iter().any(|client| {
+        dirs.get(client.dir)
+            .and_then(normalize_path)
+            .is_some_and(|dir| image == format!("{}\\{}", dir, client.image.to_lowercase()))
+    });
+    if !matched {
+        return Err(ProtocolError::Unauthorized(format!(
+            "客户端程序不在允许列表中：{}",
+            image_path
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dirs() -> ClientDirs {
+        ClientDirs {
+            system32: String::from(r"C:\Windows\System32"),
+            install: Some(String::from(r"C:\Program Files\FaceWinUnlock")),
+        }
+    }
+
+    #[test]
+    fn allowed_sid_in_allowed_dir_passes() {
+        let dirs = dirs();
+        assert!(
+            authorize_caller(
+                &[SYSTEM_SID],
+                r"C:\Windows\System32\LogonUI.exe",
+                UNLOCK_PIPE_CLIENTS,
+                &dirs
+            )
+            .is_ok()
+        );
+        assert!(
+            authorize_caller(
+                &[ADMINISTRATORS_SID],
+                r"C:\Program Files\FaceWinUnlock\facewinunlock-tauri.exe",
+                EVENT_PIPE_CLIENTS,
+                &dirs
+            )
+            .is_ok()
+        );
+        // 大小写、`/` 和 `\\?\` 前缀不影响结果
+        assert!(
+            authorize_caller(
+                &["s-1-5-18"],
+                r"\\?\c:/windows/system32/CONSENT.EXE",
+                UNLOCK_PIPE_CLIENTS,
+                &dirs
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn allowed_sid_in_wrong_dir_is_rejected() {
+        let dirs = dirs();
+        for path in [
+            r"C:\Users\Public\consent.exe",
+            r"C:\Windows\System32\sub\LogonUI.exe",
+            r"C:\Windows\FaceWinUnlock-Server.exe",
+            r"C:\Windows\System32\FaceWinUnlock-Server.exe",
+            r"C:\Program Files\FaceWinUnlock\..\FaceWinUnlock\FaceWinUnlock-Server.exe",
+            r"\\server\share\FaceWinUnlock-Server.exe",
+            r"\\?\UNC\server\share\FaceWinUnlock-Server.exe",
+        ] {
+            let result = authorize_caller(&[SYSTEM_SID], path, SERVER_PIPE_CLIENTS, &dirs);
+            assert!(
+                matches!(result, Err(ProtocolError::Unauthorized(_))),
+                "{}",
+                path
+            );
+        }
+
+        // 程序在允许的目录里，但不在这个管道的允许列表中
+        let result = authorize_caller(
+            &[SYSTEM_SID],
+            r"C:\Windows\System32\LogonUI.exe",
+            SERVER_PIPE_CLIENTS,
+            &dirs,
+        );
+        assert!(matches!(result, Err(ProtocolError::Unauthorized(_))));
+    }
+
+    #[test]
+    fn disallowed_sid_is_rejected() {
+        let result = authorize_caller(
+            &["S-1-5-21-1-2-3-1001", "S-1-5-32-545"],
+            r"C:\Program Files\FaceWinUnlock\facewinunlock-tauri.exe",
+            EVENT_PIPE_CLIENTS,
+            &dirs(),
+        );
+        assert!(matches!(result, Err(ProtocolError::Unauthorized(_))));
+    }
+
+    #[test]
+    fn missing_install_dir_rejects_install_clients() {
+        let dirs = ClientDirs {
+            install: None,
+            ..dirs()
+        };
+        let result = authorize_caller(
+            &[SYSTEM_SID],
+            r"C:\Program Files\FaceWinUnlock\FaceWinUnlock-Server.exe",
+            SERVER_PIPE_CLIENTS,
+            &dirs,
+        );
+        assert!(matches!(result, Err(ProtocolError::Unauthorized(_))));
+        assert!(
+            authorize_caller(
+                &[SYSTEM_SID],
+                r"C:\Windows\System32\LogonUI.exe",
+                UNLOCK_PIPE_CLIENTS,
+                &dirs
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn normalize_path_forms() {
+        assert_eq!(
+            normalize_path(r"\\?\C:\Dir\"),
+            Some(String::from(r"c:\dir"))
+        );
+        assert_eq!(
+            normalize_path("C:/Dir/a.exe"),
+            Some(String::from(r"c:\dir\a.exe"))
+        );
+        assert_eq!(normalize_path(r"C:\Dir\.\a.exe"), None);
+        assert_eq!(normalize_path("  "), None);
+    }
+}
@@ -0,0 +1,307 @@
+//! 管道加密层
+//!
+//! 每条连接先交换一次 X25519 临时公钥，再用 HKDF-SHA256 把共享密钥和预共享密钥（PSK）
+//! 派生成两个方向各自的 ChaCha20-Poly1305 密钥。
+//! PSK 只有 SYSTEM 和管理员能读到，没有 PSK 的进程算不出相同的密钥，消息解不开也伪造不了。
+//! 每条消息带一个递增计数器作为 nonce，接收端只接受下一个计数器，重放的消息会被拒绝。
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{frame::{decode_message, encode_message}, Message, ProtocolError};
+
+/// 密钥长度（PSK、公钥、会话密钥都是 32 字节）
+pub const KEY_LEN: usize = 32;
+/// 计数器长度，放在每条密文的开头
+const COUNTER_LEN: usize = 8;
+/// HKDF 的 info 前缀，协议改动时一并修改
+const KDF_INFO: &[u8] = b"FaceWinUnlock pipe v1";
+
+pub type PipeKey = [u8; KEY_LEN];
+
+/// 连接中的角色，决定使用哪个方向的密钥
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// 生成一个新的随机 PSK
+pub fn generate_key() -> Result<PipeKey, ProtocolError> {
+    let mut key = [0u8; KEY_LEN];
+    getrandom::getrandom(&mut key).map_err(|_| ProtocolError::Crypto("获取随机数失败"))?;
+    Ok(key)
+}
+
+/// PSK 转为十六进制字符串，用于写入注册表
+pub fn key_to_hex(key: &PipeKey) -> String {
+    key.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 从十六进制字符串解析 PSK
+pub fn key_from_hex(hex: &str) -> Result<PipeKey, ProtocolError> {
+    let hex = hex.trim();
+    if hex.len() != KEY_LEN * 2 || !hex.is_ascii() {
+        return Err(ProtocolError::Crypto("PSK 格式错误"));
+    }
+
+    let mut key = [0u8; KEY_LEN];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| ProtocolError::Crypto("PSK 格式错误"))?;
+    }
+    Ok(key)
+}
+
+/// 握手状态，发送自己的公钥后，用对方公钥完成握手
+pub struct Handshake {
+    role: Role,
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl Handshake {
+    pub fn new(role: Role) -> Result<Self, ProtocolError> {
+        let mut bytes = [0u8; KEY_LEN];
+        getrandom::getrandom(&mut bytes).map_err(|_| ProtocolError::Crypto("获取随机数失败"))?;
+        let secret = StaticSecret::from(bytes);
+        let public = PublicKey::from(&secret);
+        Ok(Self { role, secret, public })
+    }
+
+    /// 需要发给对方的公钥
+    pub fn public_key(&self) -> [u8; KEY_LEN] {
+        self.public.to_bytes()
+    }
+
+    /// 用对方公钥和 PSK 派生会话密钥
+    pub fn finish(self, peer_public: &[u8], psk: &PipeKey) -> Result<SecureChannel, ProtocolError> {
+        let peer: [u8; KEY_LEN] = peer_public
+            .try_into()
+            .map_err(|_| ProtocolError::Crypto("对方公钥长度错误"))?;
+        let peer = PublicKey::from(peer);
+
+        let shared = self.secret.diffie_hellman(&peer);
+        // 对方发来低阶点时共享密钥是全 0，这种连接直接拒绝
+        if !shared.was_contributory() {
+            return Err(ProtocolError::Crypto("对方公钥无效"));
+        }
+
+        // info 中带上双方公钥，把会话密钥绑定到这次握手
+        let (client_public, server_public) = match self.role {
+            Role::Client => (self.public, peer),
+            Role::Server => (peer, self.public),
+        };
+        let mut info = Vec::with_capacity(KDF_INFO.len() + KEY_LEN * 2);
+        info.extend_from_slice(KDF_INFO);
+        info.extend_from_slice(client_public.as_bytes());
+        info.extend_from_slice(server_public.as_bytes());
+
+        let mut okm = [0u8; KEY_LEN * 2];
+        Hkdf::<Sha256>::new(Some(psk), shared.as_bytes())
+            .expand(&info, &mut okm)
+            .map_err(|_| ProtocolError::Crypto("密钥派生失败"))?;
+
+        let (client_to_server, server_to_client) = okm.split_at(KEY_LEN);
+        let (send_key, recv_key) = match self.role {
+            Role::Client => (client_to_server, server_to_client),
+            Role::Server => (server_to_client, client_to_server),
+        };
+
+        Ok(SecureChannel {
+            sealer: ChaCha20Poly1305::new(Key::from_slice(send_key)),
+            opener: ChaCha20Poly1305::new(Key::from_slice(recv_key)),
+            send_counter: 0,
+            recv_counter: 0,
+        })
+    }
+}
+
+/// 握手完成后的加密通道
+pub struct SecureChannel {
+    sealer: ChaCha20Poly1305,
+    opener: ChaCha20Poly1305,
+    send_counter: u64,
+    recv_counter: u64,
+}
+
+fn nonce_for(counter: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+impl SecureChannel {
+    /// 加密一段数据，输出 计数器 + 密文
+    pub fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        let counter = self.send_counter;
+        self.send_counter = counter
+            .checked_add(1)
+            .ok_or(ProtocolError::Crypto("计数器溢出"))?;
+
+        let counter_bytes = counter.to_le_bytes();
+        let ciphertext = self
+            .sealer
+            .encrypt(&nonce_for(counter), Payload { msg: plaintext, aad: &counter_bytes })
+            .map_err(|_| ProtocolError::Crypto("加密失败"))?;
+
+        let mut sealed = Vec::with_capacity(COUNTER_LEN + ciphertext.len());
+        sealed.extend_from_slice(&counter_bytes);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// 解密一段数据，计数器必须正好是下一个，否则视为重放
+    pub fn open(&mut self, sealed: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        if sealed.len() < COUNTER_LEN {
+            return Err(ProtocolError::Crypto("密文长度错误"));
+        }
+
+        let (counter_bytes, ciphertext) = sealed.split_at(COUNTER_LEN);
+        let mut buf = [0u8; COUNTER_LEN];
+        buf.copy_from_slice(counter_bytes);
+        let counter = u64::from_le_bytes(buf);
+        if counter != self.recv_counter {
+            return Err(ProtocolError::Replay { expected: self.recv_counter, actual: counter });
+        }
+
+        let plaintext = self
+            .opener
+            .decrypt(&nonce_for(counter), Payload { msg: ciphertext, aad: counter_bytes })
+            .map_err(|_| ProtocolError::Crypto("解密失败，密钥不一致或数据被篡改"))?;
+
+        self.recv_counter += 1;
+        Ok(plaintext)
+    }
+
+    /// 加密一条消息
+    pub fn seal_message(&mut self, message: &Message) -> Result<Vec<u8>, ProtocolError> {
+        self.seal(&encode_message(message)?)
+    }
+
+    /// 解密一条消息
+    pub fn open_message(&mut self, sealed: &[u8]) -> Result<Message, ProtocolError> {
+        decode_message(&self.open(sealed)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{
+        encode_frame,
+        transport::{memory_pair, MemoryTransport},
+        Channel, FrameDecoder, MAX_FRAME_LEN,
+    };
+
+    const PSK: PipeKey = [7u8; KEY_LEN];
+
+    // 两端直接握手，不经过传输层
+    fn secure_pair(client_psk: &PipeKey, server_psk: &PipeKey) -> (SecureChannel, SecureChannel) {
+        let client = Handshake::new(Role::Client).unwrap();
+        let server = Handshake::new(Role::Server).unwrap();
+        let client_public = client.public_key();
+        let server_public = server.public_key();
+        (
+            client.finish(&server_public, client_psk).unwrap(),
+            server.finish(&client_public, server_psk).unwrap(),
+        )
+    }
+
+    // 经过内存传输握手，服务端在另一个线程中先收后发
+    fn channel_pair(client_psk: PipeKey, server_psk: PipeKey) -> (Channel<MemoryTransport>, Channel<MemoryTransport>) {
+        let (client_end, server_end) = memory_pair();
+        let server = thread::spawn(move || {
+            let mut channel = Channel::new(server_end);
+            channel.handshake(Role::Server, &server_psk).unwrap();
+            channel
+        });
+        let mut client = Channel::new(client_end);
+        client.handshake(Role::Client, &client_psk).unwrap();
+        (client, server.join().unwrap())
+    }
+
+    #[test]
+    fn handshake_round_trip_over_memory_pair() {
+        let (mut client, mut server) = channel_pair(PSK, PSK);
+        assert!(client.is_secure() && server.is_secure());
+
+        client.write(&Message::Ping).unwrap();
+        assert_eq!(server.read().unwrap(), Message::Ping);
+        server.write(&Message::Exit).unwrap();
+        assert_eq!(client.read().unwrap(), Message::Exit);
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_rejected() {
+        let (mut client, mut server) = secure_pair(&PSK, &PSK);
+        let mut sealed = client.seal(b"hello").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 0x01;
+        assert!(matches!(server.open(&sealed), Err(ProtocolError::Crypto(_))));
+    }
+
+    #[test]
+    fn replayed_counter_is_rejected() {
+        let (mut client, mut server) = secure_pair(&PSK, &PSK);
+        let sealed = client.seal(b"first").unwrap();
+        assert_eq!(server.open(&sealed).unwrap(), b"first");
+        assert!(matches!(server.open(&sealed), Err(ProtocolError::Replay { expected: 1, actual: 0 })));
+    }
+
+    #[test]
+    fn reordered_counter_is_rejected() {
+        let (mut client, mut server) = secure_pair(&PSK, &PSK);
+        let first = client.seal(b"first").unwrap();
+        let second = client.seal(b"second").unwrap();
+        assert!(matches!(server.open(&second), Err(ProtocolError::Replay { expected: 0, actual: 1 })));
+        // 拒绝后计数器不变，按顺序的消息仍然可以解开
+        assert_eq!(server.open(&first).unwrap(), b"first");
+    }
+
+    #[test]
+    fn mismatched_psk_fails() {
+        let other = [8u8; KEY_LEN];
+        let (mut client, mut server) = secure_pair(&PSK, &other);
+        let sealed = client.seal(b"hello").unwrap();
+        assert!(matches!(server.open(&sealed), Err(ProtocolError::Crypto(_))));
+
+        // 经过传输层握手本身会成功，第一条消息就解不开
+        let (mut client, mut server) = channel_pair(PSK, other);
+        client.write(&Message::Ping).unwrap();
+        assert!(matches!(server.read(), Err(ProtocolError::Crypto(_))));
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected() {
+        let payload = vec![0u8; MAX_FRAME_LEN + 1];
+        assert!(matches!(encode_frame(&payload), Err(ProtocolError::FrameTooLarge(len)) if len == MAX_FRAME_LEN + 1));
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&((MAX_FRAME_LEN + 1) as u32).to_le_bytes());
+        assert!(matches!(decoder.next_frame(), Err(ProtocolError::FrameTooLarge(_))));
+    }
+
+    #[test]
+    fn invalid_peer_key_is_rejected() {
+        let handshake = Handshake::new(Role::Client).unwrap();
+        assert!(matches!(handshake.finish(&[0u8; 16], &PSK), Err(ProtocolError::Crypto(_))));
+        // 全 0 是低阶点
+        let handshake = Handshake::new(Role::Client).unwrap();
+        assert!(matches!(handshake.finish(&[0u8; KEY_LEN], &PSK), Err(ProtocolError::Crypto(_))));
+    }
+
+    #[test]
+    fn key_hex_round_trip() {
+        let key = generate_key().unwrap();
+        assert_eq!(key_from_hex(&key_to_hex(&key)).unwrap(), key);
+        assert!(key_from_hex("abcd").is_err());
+    }
+}
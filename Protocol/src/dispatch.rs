@@ -0,0 +1,225 @@
+//! 管道消息的分发逻辑
+//!
+//! 只依赖 [`Transport`]，DLL 和 Unlock 服务通过实现 [`CredentialSink`]、[`UnlockService`]
+//! 接入各自的业务，收发顺序在这里统一维护。
+
+use crate::{transport::Channel, AttemptTimer, Message, ProtocolError, RecognitionEvent, ReloadOutcome, RetryOutcome, ServiceStatus, TileStatus, Transport, UnlockOutcome};
+
+/// 处理完一条消息后，监听循环是否继续
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Exit,
+}
+
+/// Unlock 服务处理来自 DLL 和 UI 的指令
+pub trait UnlockService {
+    /// 锁屏界面有操作，开始面容识别，`attempt` 从 DLL 触发时开始计时
+    fn run(&mut self, attempt: AttemptTimer);
+
+    /// 用户要求重新识别，不受重试间隔限制；开始识别时应立即返回，不等识别结束
+    fn retry(&mut self, attempt: AttemptTimer) -> RetryOutcome;
+
+    /// CredUI 对话框请求验证面容，不要求处于锁屏状态；开始识别时应立即返回
+    fn verify(&mut self, attempt: AttemptTimer) -> RetryOutcome;
+
+    /// 退出服务
+    fn exit(&mut self);
+
+    /// 用指定账户解锁，返回凭据提供程序回复的最终结果
+    fn unlock(&mut self, request_id: &str, user_name: String, password: String) -> UnlockOutcome;
+
+    /// 当前运行状态快照
+    fn status(&mut self) -> ServiceStatus;
+
+    /// 重新读取识别配置，全部合法时整体替换，否则保持原配置
+    fn reload(&mut self) -> ReloadOutcome;
+}
+
+/// DLL 一侧：把凭据交给登录界面，并等待 LSA 的结果
+pub trait CredentialSink {
+    /// 提交凭据，返回 `Accepted` 或 `Rejected`，`attempt.id()` 即请求 ID
+    fn submit(&mut self, attempt: AttemptTimer, user_name: String, password: String) -> UnlockOutcome;
+
+    /// 等待已受理请求的登录结果
+    fn wait_result(&mut self, request_id: &str) -> UnlockOutcome;
+
+    /// 更新磁贴上的识别状态
+    fn show_status(&mut self, status: TileStatus);
+}
+
+/// Unlock 服务：处理一条已握手连接上的消息
+pub fn serve_unlock<T: Transport, S: UnlockService>(
+    channel: &mut Channel<T>,
+    service: &mut S,
+) -> Result<Flow, ProtocolError> {
+    match channel.read()? {
+        Message::Exit => {
+            service.exit();
+            Ok(Flow::Exit)
+        }
+        Message::Run { attempt_id, triggered_at_ms } => {
+            service.run(AttemptTimer::resume(attempt_id, triggered_at_ms));
+            Ok(Flow::Continue)
+        }
+        Message::Retry { attempt_id, triggered_at_ms } => {
+            let outcome = service.retry(AttemptTimer::resume(attempt_id, triggered_at_ms));
+            channel.write(&Message::RetryResult { outcome })?;
+            Ok(Flow::Continue)
+        }
+        Message::Verify { attempt_id, triggered_at_ms } => {
+            let outcome = service.verify(AttemptTimer::resume(attempt_id, triggered_at_ms));
+            channel.write(&Message::RetryResult { outcome })?;
+            Ok(Flow::Continue)
+        }
+        Message::UnlockFromClient { request_id, user_name, password } => {
+            let outcome = service.unlock(&request_id, user_name, password);
+            // 把结果转告 UI
+            channel.write(&Message::UnlockResult { request_id, outcome })?;
+            Ok(Flow::Continue)
+        }
+        Message::Status => {
+            let status = service.status();
+            channel.write(&Message::StatusReport { status })?;
+            Ok(Flow::Continue)
+        }
+        Message::Reload => {
+            let outcome = service.reload();
+            channel.write(&Message::ReloadResult { outcome })?;
+            Ok(Flow::Continue)
+        }
+        // 其他消息（如 Ping）只用来探测管道，不处理
+        _ => Ok(Flow::Continue),
+    }
+}
+
+/// DLL：处理一条已握手连接上的消息，收到凭据时先回复是否受理，受理后再回复登录结果
+pub fn serve_credentials<T: Transport, S: CredentialSink>(
+    channel: &mut Channel<T>,
+    sink: &mut S,
+) -> Result<(), ProtocolError> {
+    match channel.read()? {
+        Message::Credentials { request_id, triggered_at_ms, user_name, password } => {
+            let outcome = sink.submit(AttemptTimer::resume(request_id.clone(), triggered_at_ms), user_name, password);
+            let accepted = outcome == UnlockOutcome::Accepted;
+            channel.write(&Message::UnlockResult { request_id: request_id.clone(), outcome })?;
+
+            if accepted {
+                let outcome = sink.wait_result(&request_id);
+                channel.write(&Message::UnlockResult { request_id, outcome })?;
+            }
+        }
+        Message::SetTileStatus { status } => sink.show_status(status),
+        // 其他消息（如 Ping）只用来探测管道，不处理
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Unlock 服务 -> DLL：发送凭据并等待最终结果，尝试 ID 作为请求 ID
+pub fn request_credentials<T: Transport>(
+    channel: &mut Channel<T>,
+    attempt: &AttemptTimer,
+    user_name: String,
+    password: String,
+) -> Result<UnlockOutcome, ProtocolError> {
+    channel.write(&Message::Credentials {
+        request_id: attempt.id().to_string(),
+        triggered_at_ms: attempt.triggered_at_ms(),
+        user_name,
+        password,
+    })?;
+    read_final_outcome(channel, attempt.id())
+}
+
+/// Unlock 服务 -> DLL：更新磁贴上的识别状态，不等待回复
+pub fn send_tile_status<T: Transport>(channel: &mut Channel<T>, status: TileStatus) -> Result<(), ProtocolError> {
+    channel.write(&Message::SetTileStatus { status })
+}
+
+/// DLL -> Unlock 服务：请求重新识别，返回服务是否开始识别
+pub fn request_retry<T: Transport>(channel: &mut Channel<T>, attempt: &AttemptTimer) -> Result<RetryOutcome, ProtocolError> {
+    channel.write(&Message::Retry { attempt_id: attempt.id().to_string(), triggered_at_ms: attempt.triggered_at_ms() })?;
+    read_retry_outcome(channel)
+}
+
+/// DLL -> Unlock 服务：CredUI 对话框请求验证面容，返回服务是否开始识别
+pub fn request_verify<T: Transport>(channel: &mut Channel<T>, attempt: &AttemptTimer) -> Result<RetryOutcome, ProtocolError> {
+    channel.write(&Message::Verify { attempt_id: attempt.id().to_string(), triggered_at_ms: attempt.triggered_at_ms() })?;
+    read_retry_outcome(channel)
+}
+
+/// UI -> Unlock 服务：发送解锁请求并等待最终结果
+pub fn request_unlock<T: Transport>(
+    channel: &mut Channel<T>,
+    request_id: &str,
+    user_name: String,
+    password: String,
+) -> Result<UnlockOutcome, ProtocolError> {
+    channel.write(&Message::UnlockFromClient { request_id: request_id.to_string(), user_name, password })?;
+    read_final_outcome(channel, request_id)
+}
+
+/// UI -> Unlock 服务：查询运行状态
+pub fn request_status<T: Transport>(channel: &mut Channel<T>) -> Result<ServiceStatus, ProtocolError> {
+    channel.write(&Message::Status)?;
+    match channel.read()? {
+        Message::StatusReport { status } => Ok(status),
+        _ => Err(ProtocolError::Unexpected("收到了与状态查询不对应的回复")),
+    }
+}
+
+/// UI -> Unlock 服务：让服务重新读取识别配置
+pub fn request_reload<T: Transport>(channel: &mut Channel<T>) -> Result<ReloadOutcome, ProtocolError> {
+    channel.write(&Message::Reload)?;
+    match channel.read()? {
+        Message::ReloadResult { outcome } => Ok(outcome),
+        _ => Err(ProtocolError::Unexpected("收到了与重新加载配置不对应的回复")),
+    }
+}
+
+/// UI -> Unlock 服务：订阅识别事件
+///
+/// 每收到一个事件调用一次 `on_event`，返回 false 时结束订阅；管道断开时返回错误。
+pub fn subscribe_events<T: Transport>(
+    channel: &mut Channel<T>,
+    mut on_event: impl FnMut(RecognitionEvent) -> bool,
+) -> Result<(), ProtocolError> {
+    channel.write(&Message::Subscribe)?;
+    loop {
+        match channel.read()? {
+            Message::Event { event } => {
+                if !on_event(event) {
+                    return Ok(());
+                }
+            }
+            _ => return Err(ProtocolError::Unexpected("事件管道上收到了非事件消息")),
+        }
+    }
+}
+
+// 读取重新识别、验证请求的回复
+fn read_retry_outcome<T: Transport>(channel: &mut Channel<T>) -> Result<RetryOutcome, ProtocolError> {
+    match channel.read()? {
+        Message::RetryResult { outcome } => Ok(outcome),
+        _ => Err(ProtocolError::Unexpected("收到了与识别请求不对应的回复")),
+    }
+}
+
+// 读取指定请求的结果，`Accepted` 之后还会有一次最终结果
+fn read_final_outcome<T: Transport>(
+    channel: &mut Channel<T>,
+    request_id: &str,
+) -> Result<UnlockOutcome, ProtocolError> {
+    loop {
+        match channel.read()? {
+            Message::UnlockResult { request_id: id, outcome } if id == request_id => {
+                if outcome != UnlockOutcome::Accepted {
+                    return Ok(outcome);
+                }
+            }
+            _ => return Err(ProtocolError::Unexpected("收到了与解锁请求不对应的回复")),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// 帧长度超过 MAX_FRAME_LEN
+    FrameTooLarge(usize),
+    /// 对端协议版本与本端不一致
+    VersionMismatch { expected: u16, actual: u16 },
+    /// JSON 编解码失败
+    Codec(serde_json::Error),
+    /// 握手或加解密失败
+    Crypto(&'static str),
+    /// 收到的计数器不是下一个，可能是重放的消息
+    Replay { expected: u64, actual: u64 },
+    /// 管道客户端未通过鉴权
+    Unauthorized(String),
+    /// 底层传输读写失败
+    Io(std::io::Error),
+    /// 收到了不符合流程的消息
+    Unexpected(&'static str),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::FrameTooLarge(len) => write!(f, "帧长度 {} 超过上限", len),
+            ProtocolError::VersionMismatch { expected, actual } => {
+                write!(f, "协议版本不匹配，期望 {}，实际 {}", expected, actual)
+            }
+            ProtocolError::Codec(e) => write!(f, "消息编解码失败: {}", e),
+            ProtocolError::Crypto(msg) => write!(f, "加密通道错误: {}", msg),
+            ProtocolError::Replay { expected, actual } => {
+                write!(f, "消息计数器异常，期望 {}，实际 {}，已拒绝", expected, actual)
+            }
+            ProtocolError::Unauthorized(msg) => write!(f, "管道客户端鉴权失败: {}", msg),
+            ProtocolError::Io(e) => write!(f, "管道读写失败: {}", e),
+            ProtocolError::Unexpected(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<serde_json::Error> for ProtocolError {
+    fn from(e: serde_json::Error) -> Self {
+        ProtocolError::Codec(e)
+    }
+}
+
+impl From<std::io::Error> for ProtocolError {
+    fn from(e: std::io::Error) -> Self {
+        ProtocolError::Io(e)
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Span;
+
+/// 某个面容模板在当前帧上的匹配得分
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateScore {
+    /// faces 表中的 id
+    pub face_id: i32,
+    pub alias: String,
+    /// 余弦相似度，0 ~ 1
+    pub score: f32,
+    /// 该面容设置的匹配阈值，0 ~ 100
+    pub threshold: f32,
+    pub matched: bool,
+}
+
+/// 活体检测结果，`score` 和 `threshold` 都是 logit（真实 - 伪造）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LivenessScore {
+    pub score: f32,
+    pub threshold: f32,
+    pub passed: bool,
+}
+
+/// Unlock 服务在识别过程中推送给订阅者的事件
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum RecognitionEvent {
+    /// 摄像头已打开，开始逐帧识别
+    Started { attempt_id: String, camera_index: i32 },
+    /// 到达某个阶段，UI 据此展示本次尝试的时间线
+    Timing { span: Span },
+    /// 当前帧没有检测到人脸，`count` 达到 `limit` 后停止识别
+    NoFace { count: u32, limit: u32 },
+    /// 当前帧的识别结果，活体检测未启用时 `liveness` 为 None
+    Frame {
+        scores: Vec<TemplateScore>,
+        liveness: Option<LivenessScore>,
+        /// 连续匹配成功 / 失败的帧数
+        success_count: u32,
+        fail_count: u32,
+    },
+    /// 本轮识别结束
+    Finished { matched: bool, message: String },
+}
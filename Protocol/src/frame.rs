@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Message, ProtocolError, MAX_FRAME_LEN, PROTOCOL_VERSION};
+
+/// 长度头的字节数
+const HEADER_LEN: usize = 4;
+
+/// 帧内容，带协议版本号
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Frame {
+    pub version: u16,
+    pub message: Message,
+}
+
+// 只解析版本号，版本不一致时不去解析消息体，避免新版本的消息类型报成“未知变体”
+#[derive(Deserialize)]
+struct VersionProbe {
+    version: u16,
+}
+
+/// 给一段数据加上长度头
+pub fn encode_frame(payload: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(ProtocolError::FrameTooLarge(payload.len()));
+    }
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(payload);
+    Ok(bytes)
+}
+
+/// 将消息编码为带版本号的 JSON
+pub fn encode_message(message: &Message) -> Result<Vec<u8>, ProtocolError> {
+    let frame = Frame { version: PROTOCOL_VERSION, message: message.clone() };
+    Ok(serde_json::to_vec(&frame)?)
+}
+
+/// 解析带版本号的 JSON 消息
+pub fn decode_message(payload: &[u8]) -> Result<Message, ProtocolError> {
+    let probe: VersionProbe = serde_json::from_slice(payload)?;
+    if probe.version != PROTOCOL_VERSION {
+        return Err(ProtocolError::VersionMismatch {
+            expected: PROTOCOL_VERSION,
+            actual: probe.version,
+        });
+    }
+
+    let frame: Frame = serde_json::from_slice(payload)?;
+    Ok(frame.message)
+}
+
+/// 帧重组器
+/// 一帧可能需要多次 ReadFile 才能读完，一次 ReadFile 也可能读到多帧，
+/// 把每次读到的字节 push 进来，凑够一整帧后 next_frame 才会返回数据，多出来的部分留给下一次
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 追加读到的数据
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// 取出一帧完整数据（不含长度头），数据不够时返回 Ok(None)
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, ProtocolError> {
+        if self.buffer.len() < HEADER_LEN {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; HEADER_LEN];
+        header.copy_from_slice(&self.buffer[..HEADER_LEN]);
+        let len = u32::from_le_bytes(header) as usize;
+        if len > MAX_FRAME_LEN {
+            // 长度头都不可信了，后面的数据也没法再对齐，直接丢弃
+            self.buffer.clear();
+            return Err(ProtocolError::FrameTooLarge(len));
+        }
+
+        if self.buffer.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+
+        Ok(Some(self.buffer.drain(..HEADER_LEN + len).skip(HEADER_LEN).collect()))
+    }
+}
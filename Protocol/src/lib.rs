@@ -0,0 +1,68 @@
+//! DLL、Unlock 服务、UI 三者之间共用的管道通信协议
+//!
+//! 所有经过命名管道的数据都是一个帧：4 字节小端长度 + 数据。
+//! 连接建立后先交换公钥完成握手（见 [`crypto`]），之后每一帧都是加密后的 JSON [`Frame`]，
+//! 帧内带有协议版本号，版本不一致的消息直接拒绝，避免新旧组件混用时误解析。
+//! 管道只允许 SYSTEM 和管理员打开，客户端进程还要通过 [`auth`] 中的校验。
+
+pub mod auth;
+pub mod crypto;
+pub mod dispatch;
+pub mod error;
+pub mod event;
+pub mod frame;
+pub mod message;
+pub mod options;
+#[cfg(windows)]
+pub mod pipe;
+pub mod status;
+pub mod trace;
+pub mod transport;
+
+pub use auth::authorize_caller;
+pub use error::ProtocolError;
+pub use event::{LivenessScore, RecognitionEvent, TemplateScore};
+pub use crypto::{Handshake, PipeKey, Role, SecureChannel};
+pub use frame::{decode_message, encode_frame, encode_message, Frame, FrameDecoder};
+pub use message::{Message, RetryOutcome, TileStatus, UnlockOutcome};
+pub use options::{OptionError, RecognitionOptions, ReloadOutcome};
+pub use status::{DbPoolStatus, Health, ServiceStatus};
+pub use trace::{AttemptTimer, Span, Stage};
+pub use transport::{Channel, Transport};
+
+/// 当前协议版本，消息格式有不兼容的改动时 +1
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// 单帧最大长度（不含长度头），超过这个长度判定为非法数据
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// DLL（winlogon）监听的管道，接收解锁凭据
+pub const SERVER_PIPE_NAME: &str = r"\\.\pipe\MansonWindowsUnlockRustServer";
+
+/// Unlock 服务监听的管道，接收 run / exit / 来自 UI 的解锁请求
+pub const UNLOCK_PIPE_NAME: &str = r"\\.\pipe\MansonWindowsUnlockRustUnlock";
+
+/// Unlock 服务推送识别事件的管道，UI 订阅后实时查看识别过程
+pub const EVENT_PIPE_NAME: &str = r"\\.\pipe\MansonWindowsUnlockRustEvents";
+
+/// 软件配置的注册表子项（HKLM），UI 写入，DLL 和 Unlock 服务读取
+pub const APP_REG_PATH: &str = "SOFTWARE\\facewinunlock-tauri";
+
+/// 存放 PSK 的注册表子项（HKLM），只允许 SYSTEM 和管理员访问
+pub const SECURE_REG_PATH: &str = "SOFTWARE\\facewinunlock-tauri\\Secure";
+
+/// PSK 在注册表中的值名
+pub const PIPE_KEY_NAME: &str = "PIPE_KEY";
+
+/// DLL 记录各账户 LSA 连续登录失败次数的注册表子项（HKLM），值名为 `域名\用户名`（小写，本地账户的域名为计算机名），winlogon 重启后仍然有效
+///
+/// Unlock 服务在用户手动登录成功后删除整个子项
+pub const LSA_FAILURE_REG_PATH: &str = "SOFTWARE\\facewinunlock-tauri\\LsaFailures";
+
+/// DLL 崩溃保护的注册表子项（HKLM），记录登录界面是否正常退出和连续异常退出的次数
+///
+/// UI 保存 DLL 配置时删除整个子项，重新启用面容解锁
+pub const CRASH_GUARD_REG_PATH: &str = "SOFTWARE\\facewinunlock-tauri\\CrashGuard";
+
+/// DLL 等待 LSA 登录结果的最长时间（毫秒），超时后回复 `UnlockOutcome::Timeout`
+pub const LOGON_RESULT_TIMEOUT_MS: u64 = 30_000;
@@ -0,0 +1,125 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{RecognitionEvent, ReloadOutcome, ServiceStatus};
+
+/// 管道上传输的所有消息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Message {
+    /// 连通性探测，只用来确认管道另一端存在
+    Ping,
+    /// DLL -> Unlock 服务：锁屏界面有操作，开始面容识别
+    ///
+    /// `attempt_id` 在钩子触发时生成，之后的凭据、结果、日志都用它关联（见 [`crate::trace`]）
+    Run { attempt_id: String, triggered_at_ms: u64 },
+    /// DLL -> Unlock 服务：用户点击了磁贴上的“重新识别”，不受操作触发的重试间隔限制，但仍受失败次数上限约束
+    Retry { attempt_id: String, triggered_at_ms: u64 },
+    /// DLL -> Unlock 服务：UAC 提权等 CredUI 对话框中出现了已录入面容的账户，请求验证面容
+    ///
+    /// 与锁屏无关，不要求处于锁屏状态，也不受识别方式（操作触发 / 延迟）影响
+    Verify { attempt_id: String, triggered_at_ms: u64 },
+    /// Unlock 服务 -> DLL：是否开始识别（`Retry`、`Verify` 的回复）
+    RetryResult { outcome: RetryOutcome },
+    /// UI -> Unlock 服务：退出服务
+    Exit,
+    /// UI -> Unlock 服务：用指定账户解锁（初始化时测试 WinLogon 使用）
+    UnlockFromClient { request_id: String, user_name: String, password: String },
+    /// Unlock 服务 -> DLL：提交给 LSA 的用户名和密码
+    ///
+    /// `request_id` 即尝试 ID，`triggered_at_ms` 是这次尝试的触发时间，DLL 据此记录各阶段耗时
+    Credentials { request_id: String, triggered_at_ms: u64, user_name: String, password: String },
+    /// DLL -> Unlock 服务 -> UI：解锁请求的处理结果
+    ///
+    /// 同一个请求会先收到 `Accepted` 或 `Rejected`，受理后再收到一次最终结果
+    UnlockResult { request_id: String, outcome: UnlockOutcome },
+    /// UI -> Unlock 服务：查询服务运行状态
+    Status,
+    /// Unlock 服务 -> UI：运行状态快照
+    StatusReport { status: ServiceStatus },
+    /// UI -> Unlock 服务（事件管道）：订阅识别事件，之后这条连接只用来接收 `Event`
+    Subscribe,
+    /// Unlock 服务 -> UI：识别过程中的实时事件
+    Event { event: RecognitionEvent },
+    /// Unlock 服务 -> DLL：更新锁屏磁贴上的识别状态
+    SetTileStatus { status: TileStatus },
+    /// UI -> Unlock 服务：重新读取识别配置，不用等到下次锁屏
+    Reload,
+    /// Unlock 服务 -> UI：重新加载配置的结果
+    ReloadResult { outcome: ReloadOutcome },
+}
+
+/// 锁屏磁贴上显示的识别状态，文字由 DLL 决定
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "camelCase")]
+pub enum TileStatus {
+    /// 不显示状态
+    Idle,
+    /// 摄像头已打开，正在识别
+    Scanning,
+    /// 长时间未检测到人脸，本轮识别停止
+    NoFace,
+    /// 活体检测未通过
+    LivenessFailed,
+    /// 面容不匹配，`retries_left` 为剩余的识别次数
+    NotMatched { retries_left: u32 },
+    /// 面容匹配成功，正在登录
+    Unlocking,
+    /// 失败次数达到上限，本次锁屏不再自动识别
+    Paused,
+}
+
+/// 重新识别、验证请求的处理结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum RetryOutcome {
+    /// 已开始识别
+    Started,
+    /// 正在识别中
+    Busy,
+    /// 失败次数达到上限，本次锁屏不再识别
+    LockedOut { max_retry: u32 },
+    /// 服务当前无法识别（未锁屏、识别配置加载失败等）
+    Unavailable { reason: String },
+}
+
+/// 解锁请求的处理结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum UnlockOutcome {
+    /// 凭据提供程序已收下凭据，正在等待 LSA 登录
+    Accepted,
+    /// 凭据提供程序拒绝了这次请求
+    Rejected { reason: String },
+    /// LSA 返回的登录结果（NTSTATUS），0 表示登录成功
+    Logon { ntstatus: i32 },
+    /// 凭据已提交，但在限定时间内没有收到登录结果
+    Timeout,
+    /// LSA 登录失败，且该账户连续失败次数达到上限，DLL 不再自动提交它的凭据
+    ///
+    /// Unlock 服务收到后暂停该账户的面容，直到用户手动登录成功
+    Suspended { ntstatus: i32, failures: u32 },
+}
+
+impl UnlockOutcome {
+    /// 是否真正登录成功
+    pub fn is_success(&self) -> bool {
+        matches!(self, UnlockOutcome::Logon { ntstatus: 0 })
+    }
+}
+
+/// 写入 unlock_log 的文本形式
+impl fmt::Display for UnlockOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnlockOutcome::Accepted => write!(f, "accepted"),
+            UnlockOutcome::Rejected { reason } => write!(f, "rejected: {}", reason),
+            UnlockOutcome::Logon { ntstatus } => write!(f, "ntstatus 0x{:08X}", *ntstatus as u32),
+            UnlockOutcome::Timeout => write!(f, "timeout"),
+            UnlockOutcome::Suspended { ntstatus, failures } => {
+                write!(f, "suspended after {} failures, ntstatus 0x{:08X}", failures, *ntstatus as u32)
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Unlock 服务使用的识别配置，对应 UI options 表中的几项
+//!
+//! 解析只依赖一个按键名取值的函数，不关心值来自数据库还是别处。
+//! 没有保存过的项使用默认值，保存了但不合法的项报告给调用方，不会悄悄换成默认值。
+
+use serde::{Deserialize, Serialize};
+
+/// 识别配置快照，Unlock 服务整体替换，不会出现新旧配置混用
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecognitionOptions {
+    /// faceRecogType：operation（用户操作触发）/ delay（锁屏后延迟）
+    pub recognition_mode: String,
+    /// faceRecogDelay：delay 模式下锁屏后多久开始识别
+    pub recognition_delay_ms: u32,
+    /// camera：摄像头索引
+    pub camera_index: i32,
+    /// retryDelay：面容不匹配后多久允许重试
+    pub retry_delay_ms: u32,
+    /// notFaceDelay：连续多少次（每次 500 毫秒）未检测到人脸后停止识别
+    pub not_face_limit: u32,
+    /// livenessEnabled
+    pub liveness_enabled: bool,
+    /// livenessThreshold：活体阈值，百分比
+    pub liveness_threshold: u32,
+    /// faceAlignedType：default / model
+    pub aligned_mode: String,
+}
+
+impl Default for RecognitionOptions {
+    fn default() -> Self {
+        Self {
+            recognition_mode: String::from("operation"),
+            recognition_delay_ms: 10_000,
+            camera_index: 0,
+            retry_delay_ms: 10_000,
+            not_face_limit: 6,
+            liveness_enabled: false,
+            liveness_threshold: 50,
+            aligned_mode: String::from("default"),
+        }
+    }
+}
+
+/// 一项不合法的配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OptionError {
+    pub key: String,
+    pub value: String,
+    pub reason: String,
+}
+
+impl OptionError {
+    fn new(key: &str, value: &str, reason: &str) -> Self {
+        Self { key: key.to_string(), value: value.to_string(), reason: reason.to_string() }
+    }
+}
+
+// 秒数转毫秒，限定在 [min, max] 秒之间
+fn parse_seconds(key: &str, value: &str, min: f32, max: f32) -> Result<u32, OptionError> {
+    let seconds: f32 = value.trim().parse().map_err(|_| OptionError::new(key, value, "不是数字"))?;
+    if !(min..=max).contains(&seconds) {
+        return Err(OptionError::new(key, value, &format!("应在 {} 到 {} 秒之间", min, max)));
+    }
+    Ok((seconds * 1000.0).round() as u32)
+}
+
+fn parse_choice(key: &str, value: &str, choices: &[&str]) -> Result<String, OptionError> {
+    if choices.contains(&value) {
+        Ok(value.to_string())
+    } else {
+        Err(OptionError::new(key, value, &format!("只能是 {}", choices.join(" / "))))
+    }
+}
+
+/// 解析识别配置，`get` 按 options 表的键名取值，取不到时使用默认值
+pub fn parse_options(get: impl Fn(&str) -> Option<String>) -> Result<RecognitionOptions, Vec<OptionError>> {
+    let mut options = RecognitionOptions::default();
+    let mut errors = Vec::new();
+
+    let mut check = |key: &str, apply: &mut dyn FnMut(&str) -> Result<(), OptionError>| {
+        if let Some(value) = get(key)
+            && let Err(e) = apply(&value)
+        {
+            errors.push(e);
+        }
+    };
+
+    check("faceRecogType", &mut |value| {
+        options.recognition_mode = parse_choice("faceRecogType", value, &["operation", "delay"])?;
+        Ok(())
+    });
+    check("faceRecogDelay", &mut |value| {
+        options.recognition_delay_ms = parse_seconds("faceRecogDelay", value, 0.1, 120.0)?;
+        Ok(())
+    });
+    check("camera", &mut |value| {
+        options.camera_index = match value.trim().parse::<i32>() {
+            Ok(index) if index >= 0 => index,
+            _ => return Err(OptionError::new("camera", value, "应为非负整数")),
+        };
+        Ok(())
+    });
+    check("retryDelay", &mut |value| {
+        options.retry_delay_ms = parse_seconds("retryDelay", value, 1.0, 120.0)?;
+        Ok(())
+    });
+    check("notFaceDelay", &mut |value| {
+        // 每 500 毫秒检测一次，秒数 * 2 即为次数
+        options.not_face_limit = match value.trim().parse::<u32>() {
+            Ok(seconds) if (1..=120).contains(&seconds) => seconds * 2,
+            _ => return Err(OptionError::new("notFaceDelay", value, "应为 1 到 120 之间的整数")),
+        };
+        Ok(())
+    });
+    check("livenessEnabled", &mut |value| {
+        options.liveness_enabled = match value {
+            "true" => true,
+            "false" => false,
+            _ => return Err(OptionError::new("livenessEnabled", value, "只能是 true / false")),
+        };
+        Ok(())
+    });
+    check("livenessThreshold", &mut |value| {
+        options.liveness_threshold = match value.trim().parse::<f32>() {
+            Ok(threshold) if threshold > 0.0 && threshold < 1.0 => (threshold * 100.0).round() as u32,
+            _ => return Err(OptionError::new("livenessThreshold", value, "应在 0 到 1 之间")),
+        };
+        Ok(())
+    });
+    check("faceAlignedType", &mut |value| {
+        options.aligned_mode = parse_choice("faceAlignedType", value, &["default", "model"])?;
+        Ok(())
+    });
+
+    if errors.is_empty() { Ok(options) } else { Err(errors) }
+}
+
+/// 重新加载配置的结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum ReloadOutcome {
+    /// 新配置已生效
+    Applied { options: RecognitionOptions },
+    /// 有不合法的配置，服务继续使用原来的配置
+    Invalid { errors: Vec<OptionError> },
+    /// 无法读取配置（如数据库未初始化），服务继续使用原来的配置
+    Failed { reason: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn parse(values: &[(&str, &str)]) -> Result<RecognitionOptions, Vec<OptionError>> {
+        let map: HashMap<&str, &str> = values.iter().copied().collect();
+        parse_options(|key| map.get(key).map(|value| value.to_string()))
+    }
+
+    fn error_keys(values: &[(&str, &str)]) -> Vec<String> {
+        parse(values).unwrap_err().into_iter().map(|e| e.key).collect()
+    }
+
+    #[test]
+    fn missing_keys_use_defaults() {
+        assert_eq!(parse(&[]).unwrap(), RecognitionOptions::default());
+
+        // 只保存了部分配置，其余仍为默认值
+        let options = parse(&[("retryDelay", "5"), ("livenessEnabled", "true")]).unwrap();
+        assert_eq!(options.retry_delay_ms, 5_000);
+        assert!(options.liveness_enabled);
+        assert_eq!(options.not_face_limit, RecognitionOptions::default().not_face_limit);
+    }
+
+    #[test]
+    fn valid_values_are_applied() {
+        let options = parse(&[
+            ("faceRecogType", "delay"),
+            ("faceRecogDelay", "2.5"),
+            ("camera", "1"),
+            ("retryDelay", "120"),
+            ("notFaceDelay", "3"),
+            ("livenessThreshold", "0.75"),
+            ("faceAlignedType", "model"),
+        ])
+        .unwrap();
+        assert_eq!(options.recognition_mode, "delay");
+        assert_eq!(options.recognition_delay_ms, 2_500);
+        assert_eq!(options.camera_index, 1);
+        assert_eq!(options.retry_delay_ms, 120_000);
+        assert_eq!(options.not_face_limit, 6);
+        assert_eq!(options.liveness_threshold, 75);
+        assert_eq!(options.aligned_mode, "model");
+    }
+
+    #[test]
+    fn out_of_range_values_are_reported() {
+        for value in ["0", "1", "-0.5", "1.5"] {
+            assert_eq!(error_keys(&[("livenessThreshold", value)]), vec!["livenessThreshold"], "{}", value);
+        }
+        for value in ["0.5", "0", "120.5"] {
+            assert_eq!(error_keys(&[("retryDelay", value)]), vec!["retryDelay"], "{}", value);
+        }
+        for value in ["0", "121", "-1", "1.5"] {
+            assert_eq!(error_keys(&[("notFaceDelay", value)]), vec!["notFaceDelay"], "{}", value);
+        }
+    }
+
+    #[test]
+    fn non_numeric_values_are_reported() {
+        let errors = parse(&[("retryDelay", "abc")]).unwrap_err();
+        assert_eq!(errors, vec![OptionError::new("retryDelay", "abc", "不是数字")]);
+
+        for key in ["faceRecogDelay", "camera", "notFaceDelay", "livenessThreshold"] {
+            assert_eq!(error_keys(&[(key, "")]), vec![key]);
+        }
+        assert_eq!(error_keys(&[("livenessEnabled", "yes")]), vec!["livenessEnabled"]);
+        assert_eq!(error_keys(&[("faceRecogType", "manual")]), vec!["faceRecogType"]);
+    }
+
+    #[test]
+    fn all_errors_are_collected() {
+        let errors = parse(&[
+            ("faceRecogType", "delay"),
+            ("retryDelay", "0"),
+            ("notFaceDelay", "x"),
+            ("livenessThreshold", "2"),
+            ("faceAlignedType", "model"),
+        ])
+        .unwrap_err();
+
+        // 合法的项不会出现在错误中，不合法的值原样保留
+        let keys: Vec<&str> = errors.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, vec!["retryDelay", "notFaceDelay", "livenessThreshold"]);
+        assert_eq!(errors[1].value, "x");
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use windows::{
+    Win32::{
+        Foundation::{CloseHandle, ERROR_PIPE_BUSY, GENERIC_READ, GENERIC_WRITE},
+        Storage::FileSystem::{CreateFileW, FILE_FLAG_OVERLAPPED, FILE_SHARE_MODE, OPEN_EXISTING},
+        System::Pipes::{PIPE_READMODE_MESSAGE, SetNamedPipeHandleState, WaitNamedPipeW},
+    },
+    core::HSTRING,
+};
+
+use super::{PIPE_CONNECT_TIMEOUT_MS, PIPE_IO_TIMEOUT_MS, PipeStream, Shutdown, shut_down, timed_out};
+use crate::{Channel, LOGON_RESULT_TIMEOUT_MS, ProtocolError, Role};
+
+/// 连接管道，不握手
+///
+/// 管道不存在时立即返回错误；所有实例都忙时最多等待 `PIPE_CONNECT_TIMEOUT_MS`。
+/// 读写超时默认要能等到 DLL 回复登录结果，订阅事件之类的长连接可以用 `set_timeout(None)` 取消。
+pub fn open(pipe_name: &str, shutdown: Option<&Shutdown>) -> Result<PipeStream, ProtocolError> {
+    let name = HSTRING::from(pipe_name);
+    let deadline = Instant::now() + Duration::from_millis(PIPE_CONNECT_TIMEOUT_MS);
+
+    let handle = loop {
+        let result = unsafe {
+            CreateFileW(
+                &name,
+                GENERIC_READ.0 | GENERIC_WRITE.0,
+                FILE_SHARE_MODE(0),
+                None,
+                OPEN_EXISTING,
+                FILE_FLAG_OVERLAPPED,
+                None,
+            )
+        };
+
+        match result {
+            Ok(handle) => break handle,
+            Err(e) if e.code() == ERROR_PIPE_BUSY.to_hresult() => {
+                if shutdown.is_some_and(|shutdown| shutdown.is_signaled()) {
+                    return Err(shut_down().into());
+                }
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(timed_out().into());
+                }
+                // 分小段等待，期间可以响应关闭信号
+                let _ = unsafe { WaitNamedPipeW(&name, remaining.as_millis().min(100) as u32) };
+            }
+            Err(e) => return Err(io::Error::from(e).into()),
+        }
+    };
+
+    let mode = PIPE_READMODE_MESSAGE;
+    if let Err(e) = unsafe { SetNamedPipeHandleState(handle, Some(&mode), None, None) } {
+        let _ = unsafe { CloseHandle(handle) };
+        return Err(io::Error::from(e).into());
+    }
+
+    let event = match PipeStream::create_event() {
+        Ok(event) => event,
+        Err(e) => {
+            let _ = unsafe { CloseHandle(handle) };
+            return Err(e.into());
+        }
+    };
+
+    let timeout = Duration::from_millis(LOGON_RESULT_TIMEOUT_MS + PIPE_IO_TIMEOUT_MS);
+    Ok(PipeStream::new(handle, event, shutdown.cloned(), timeout, None))
+}
+
+/// 连接管道并完成握手
+pub fn connect(pipe_name: &str, shutdown: Option<&Shutdown>) -> Result<Channel<PipeStream>, ProtocolError> {
+    open(pipe_name, shutdown)?.handshake(Role::Client)
+}
@@ -0,0 +1,99 @@
+use std::{io, path::Path};
+
+use windows::{
+    Win32::{
+        Foundation::{CloseHandle, HANDLE, HLOCAL, LocalFree},
+        Security::{
+            Authorization::ConvertSidToStringSidW, GetTokenInformation, PSID, TOKEN_GROUPS, TOKEN_INFORMATION_CLASS,
+            TOKEN_QUERY, TOKEN_USER, TokenGroups, TokenUser,
+        },
+        System::{
+            Pipes::GetNamedPipeClientProcessId,
+            SystemInformation::GetSystemDirectoryW,
+            SystemServices::SE_GROUP_ENABLED,
+            Threading::{OpenProcess, OpenProcessToken, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION, QueryFullProcessImageNameW},
+        },
+    },
+    core::PWSTR,
+};
+
+use super::read_hklm_string;
+use crate::{APP_REG_PATH, auth::ClientDirs};
+
+// 读取令牌信息，缓冲区按 8 字节对齐，方便直接转换成结构体
+fn token_information(token: HANDLE, class: TOKEN_INFORMATION_CLASS) -> io::Result<Vec<u64>> {
+    let mut len = 0;
+    // 第一次调用只为拿到需要的长度，必然失败
+    let _ = unsafe { GetTokenInformation(token, class, None, 0, &mut len) };
+    let mut buf = vec![0u64; (len as usize).div_ceil(8)];
+    unsafe { GetTokenInformation(token, class, Some(buf.as_mut_ptr() as *mut _), len, &mut len) }?;
+    Ok(buf)
+}
+
+// SID 转为 S-1-5-18 这样的字符串
+fn sid_to_string(sid: PSID) -> io::Result<String> {
+    let mut string_sid = PWSTR::null();
+    unsafe { ConvertSidToStringSidW(sid, &mut string_sid) }?;
+    let result = unsafe { string_sid.to_string() };
+    unsafe { LocalFree(Some(HLOCAL(string_sid.0 as _))) };
+    result.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "SID 转换失败"))
+}
+
+// 令牌的用户 SID 和已启用的组 SID
+// 未提权的管理员令牌里 Administrators 是 deny-only，不会被算进去
+fn token_sids(token: HANDLE) -> io::Result<Vec<String>> {
+    let mut sids = Vec::new();
+
+    let user = token_information(token, TokenUser)?;
+    let user = unsafe { &*(user.as_ptr() as *const TOKEN_USER) };
+    sids.push(sid_to_string(user.User.Sid)?);
+
+    let groups = token_information(token, TokenGroups)?;
+    let groups = unsafe { &*(groups.as_ptr() as *const TOKEN_GROUPS) };
+    let entries = unsafe { std::slice::from_raw_parts(groups.Groups.as_ptr(), groups.GroupCount as usize) };
+    for group in entries {
+        if group.Attributes & SE_GROUP_ENABLED as u32 != 0 {
+            sids.push(sid_to_string(group.Sid)?);
+        }
+    }
+
+    Ok(sids)
+}
+
+/// 获取管道客户端进程的 SID 列表和镜像路径
+pub(crate) fn client_identity(handle: HANDLE) -> io::Result<(Vec<String>, String)> {
+    let mut pid = 0;
+    unsafe { GetNamedPipeClientProcessId(handle, &mut pid) }?;
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }?;
+
+    let identity = (|| -> io::Result<(Vec<String>, String)> {
+        let mut buf = [0u16; 1024];
+        let mut size = buf.len() as u32;
+        unsafe { QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, PWSTR(buf.as_mut_ptr()), &mut size) }?;
+        let image_path = String::from_utf16_lossy(&buf[..size as usize]);
+
+        let mut token = HANDLE::default();
+        unsafe { OpenProcessToken(process, TOKEN_QUERY, &mut token) }?;
+        let sids = token_sids(token);
+        let _ = unsafe { CloseHandle(token) };
+
+        Ok((sids?, image_path))
+    })();
+
+    let _ = unsafe { CloseHandle(process) };
+    identity
+}
+
+/// 校验客户端路径时使用的系统目录和安装目录，每次连接都重新读取，重新初始化后不用重启
+pub(crate) fn client_dirs() -> ClientDirs {
+    let mut buf = [0u16; 260];
+    let len = unsafe { GetSystemDirectoryW(Some(&mut buf)) } as usize;
+    let system32 = String::from_utf16_lossy(&buf[..len.min(buf.len())]);
+
+    // DLL_LOG_PATH 是初始化时写入的安装目录下的 logs 文件夹
+    let install = read_hklm_string(APP_REG_PATH, "DLL_LOG_PATH")
+        .ok()
+        .and_then(|logs| Path::new(&logs).parent().map(|dir| dir.to_string_lossy().into_owned()));
+
+    ClientDirs { system32, install }
+}
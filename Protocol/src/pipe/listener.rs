@@ -0,0 +1,330 @@
+use std::{
+    io,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use windows::{
+    Win32::{
+        Foundation::{
+            CloseHandle, ERROR_IO_PENDING, ERROR_PIPE_BUSY, ERROR_PIPE_CONNECTED, HANDLE, HLOCAL, LocalFree, WAIT_OBJECT_0,
+            WAIT_TIMEOUT,
+        },
+        Security::{
+            Authorization::{ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1},
+            PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES,
+        },
+        Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, PIPE_ACCESS_DUPLEX},
+        System::{
+            IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED},
+            Pipes::{
+                ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_MESSAGE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_MESSAGE,
+                PIPE_WAIT,
+            },
+            Threading::{CreateEventW, INFINITE, SetEvent, WaitForMultipleObjects},
+        },
+    },
+    core::HSTRING,
+};
+
+use super::{PIPE_INSTANCES, PIPE_IO_TIMEOUT_MS, PipeStream, Shutdown, shut_down, shutdown::EventHandle};
+use crate::{MAX_FRAME_LEN, ProtocolError, auth::PIPE_SDDL, dispatch::Flow};
+
+// 所有实例都被占用、又创建失败时，隔多久重试一次（毫秒）
+const RETRY_CREATE_MS: u32 = 100;
+
+// 已交给处理线程、还没释放的连接数
+//
+// 已连接的实例同样计入 CreateNamedPipeW 的实例上限，补充等待实例时要扣掉这部分。
+// 连接释放时触发 freed（自动重置事件），唤醒等待空位的监听线程。
+struct Slots {
+    live: AtomicU32,
+    freed: EventHandle,
+}
+
+/// 一个已连接实例占用的名额，随 [`PipeStream`] 一起释放
+pub(crate) struct Slot(Arc<Slots>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.live.fetch_sub(1, Ordering::SeqCst);
+        let _ = unsafe { SetEvent(self.0.freed.0) };
+    }
+}
+
+// 一个正在等待客户端连接的管道实例
+struct Instance {
+    handle: HANDLE,
+    // 重叠操作进行中时地址不能变，单独放在堆上
+    overlapped: Box<OVERLAPPED>,
+    connected: bool,
+}
+
+/// 命名管道服务端，同时挂起多个实例等待连接
+pub struct PipeListener {
+    name: HSTRING,
+    shutdown: Shutdown,
+    instances: Vec<Instance>,
+    max_instances: u32,
+    slots: Arc<Slots>,
+}
+
+// 句柄只在监听线程中使用
+unsafe impl Send for PipeListener {}
+
+impl PipeListener {
+    /// 创建管道，只有 SYSTEM 和管理员能打开
+    ///
+    /// 第一个实例带 FILE_FLAG_FIRST_PIPE_INSTANCE，管道已被其他进程抢先创建时直接失败。
+    pub fn bind(pipe_name: &str, shutdown: &Shutdown) -> Result<Self, ProtocolError> {
+        let freed = EventHandle(unsafe { CreateEventW(None, false, false, None) }.map_err(io::Error::from)?);
+        let mut listener = Self {
+            name: HSTRING::from(pipe_name),
+            shutdown: shutdown.clone(),
+            instances: Vec::new(),
+            max_instances: PIPE_INSTANCES,
+            slots: Arc::new(Slots { live: AtomicU32::new(0), freed }),
+        };
+        let instance = listener.create_instance(true)?;
+        listener.instances.push(instance);
+        Ok(listener)
+    }
+
+    // 创建一个管道实例并开始等待连接
+    fn create_instance(&self, first: bool) -> io::Result<Instance> {
+        let mut security_descriptor = PSECURITY_DESCRIPTOR::default();
+        unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                &HSTRING::from(PIPE_SDDL),
+                SDDL_REVISION_1,
+                &mut security_descriptor,
+                None,
+            )
+        }?;
+
+        let attributes = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: security_descriptor.0,
+            bInheritHandle: false.into(),
+        };
+
+        let mut open_mode = PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED;
+        if first {
+            open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+        }
+
+        let handle = unsafe {
+            CreateNamedPipeW(
+                &self.name,
+                open_mode,
+                PIPE_TYPE_MESSAGE | PIPE_READMODE_MESSAGE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                self.max_instances,
+                MAX_FRAME_LEN as u32,
+                MAX_FRAME_LEN as u32,
+                0,
+                Some(&attributes),
+            )
+        };
+
+        unsafe { LocalFree(Some(HLOCAL(security_descriptor.0))) };
+
+        if handle.is_invalid() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let event = match PipeStream::create_event() {
+            Ok(event) => event,
+            Err(e) => {
+                let _ = unsafe { CloseHandle(handle) };
+                return Err(e);
+            }
+        };
+
+        let mut instance = Instance {
+            handle,
+            overlapped: Box::new(OVERLAPPED { hEvent: event, ..Default::default() }),
+            connected: false,
+        };
+
+        // 重叠模式下 ConnectNamedPipe 总是立即返回
+        if let Err(e) = unsafe { ConnectNamedPipe(handle, Some(&mut *instance.overlapped)) } {
+            if e.code() == ERROR_PIPE_CONNECTED.to_hresult() {
+                // 客户端在 CreateNamedPipeW 和 ConnectNamedPipe 之间就连上了
+                instance.connected = true;
+                let _ = unsafe { SetEvent(event) };
+            } else if e.code() != ERROR_IO_PENDING.to_hresult() {
+                close_instance(instance);
+                return Err(e.into());
+            }
+        }
+
+        Ok(instance)
+    }
+
+    /// 等待下一个客户端连接，只有触发关闭信号或出现无法恢复的错误时才返回错误
+    ///
+    /// 返回的连接还没有鉴权和握手，交给处理线程完成，避免慢客户端阻塞监听。
+    /// 所有实例都被已交出的连接占用时，等到其中一个释放后再继续接受连接。
+    pub fn accept(&mut self) -> Result<PipeStream, ProtocolError> {
+        loop {
+            // 补足等待连接的实例，已交出的连接也占用实例名额
+            let live = self.slots.live.load(Ordering::SeqCst);
+            while (self.instances.len() as u32) + live < self.max_instances {
+                match self.create_instance(false) {
+                    Ok(instance) => self.instances.push(instance),
+                    // 刚释放的实例可能还没关闭完，稍后重试；没有任何实例和连接时的其他错误无法恢复
+                    Err(e)
+                        if self.instances.is_empty()
+                            && live == 0
+                            && e.raw_os_error() != Some(ERROR_PIPE_BUSY.0 as i32) =>
+                    {
+                        return Err(e.into());
+                    }
+                    Err(_) => break,
+                }
+            }
+
+            if let Some(index) = self.instances.iter().position(|instance| instance.connected) {
+                return Ok(self.take(index));
+            }
+
+            let mut handles: Vec<HANDLE> = self.instances.iter().map(|instance| instance.overlapped.hEvent).collect();
+            handles.push(self.shutdown.handle());
+            handles.push(self.slots.freed.0);
+
+            // 没有等待中的实例也没有可以等的连接时，只能定时重试创建
+            let timeout = if self.instances.is_empty() && live == 0 { RETRY_CREATE_MS } else { INFINITE };
+            let result = unsafe { WaitForMultipleObjects(&handles, false, timeout) };
+            if result == WAIT_TIMEOUT {
+                continue;
+            }
+
+            let index = result.0.wrapping_sub(WAIT_OBJECT_0.0) as usize;
+            if index == self.instances.len() {
+                return Err(shut_down().into());
+            }
+            if index == self.instances.len() + 1 {
+                // 有连接释放了，下一轮补充实例
+                continue;
+            }
+            if index > self.instances.len() {
+                return Err(io::Error::last_os_error().into());
+            }
+
+            let instance = &mut self.instances[index];
+            let mut transferred = 0;
+            if unsafe { GetOverlappedResult(instance.handle, &*instance.overlapped, &mut transferred, false) }.is_ok() {
+                instance.connected = true;
+            } else {
+                // 客户端连上后又立即断开，丢弃这个实例，下一轮重新创建
+                close_instance(self.instances.remove(index));
+            }
+        }
+    }
+
+    // 把已连接的实例交给 PipeStream，事件句柄一并转交，同时占用一个名额
+    fn take(&mut self, index: usize) -> PipeStream {
+        let instance = self.instances.remove(index);
+        self.slots.live.fetch_add(1, Ordering::SeqCst);
+        let slot = Slot(self.slots.clone());
+        let timeout = Duration::from_millis(PIPE_IO_TIMEOUT_MS);
+        PipeStream::new(instance.handle, instance.overlapped.hEvent, Some(self.shutdown.clone()), timeout, Some(slot))
+    }
+
+    /// 循环接受连接，每个连接在单独的线程中交给 `handler` 处理
+    ///
+    /// `handler` 返回 `Flow::Exit` 时触发关闭信号。关闭后等所有处理线程结束再返回。
+    pub fn serve<F>(mut self, handler: F) -> Result<(), ProtocolError>
+    where
+        F: Fn(PipeStream) -> Flow + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        let mut workers: Vec<JoinHandle<()>> = Vec::new();
+
+        let result = loop {
+            let stream = match self.accept() {
+                Ok(stream) => stream,
+                Err(_) if self.shutdown.is_signaled() => break Ok(()),
+                Err(e) => break Err(e),
+            };
+
+            workers.retain(|worker| !worker.is_finished());
+
+            let handler = handler.clone();
+            let shutdown = self.shutdown.clone();
+            workers.push(thread::spawn(move || {
+                if handler(stream) == Flow::Exit {
+                    shutdown.signal();
+                }
+            }));
+        };
+
+        for worker in workers {
+            let _ = worker.join();
+        }
+        result
+    }
+}
+
+// 取消等待中的连接并关闭实例
+fn close_instance(instance: Instance) {
+    let mut transferred = 0;
+    unsafe {
+        if !instance.connected {
+            let _ = CancelIoEx(instance.handle, Some(&*instance.overlapped));
+            let _ = GetOverlappedResult(instance.handle, &*instance.overlapped, &mut transferred, true);
+        }
+        let _ = CloseHandle(instance.handle);
+        let _ = CloseHandle(instance.overlapped.hEvent);
+    }
+}
+
+impl Drop for PipeListener {
+    fn drop(&mut self) {
+        for instance in self.instances.drain(..) {
+            close_instance(instance);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Transport, pipe::open};
+
+    #[test]
+    fn slot_is_reused_after_a_connection_closes() {
+        let name = format!(r"\\.\pipe\FaceWinUnlockListenerTest{}", std::process::id());
+        let shutdown = Shutdown::new().unwrap();
+        let listener = PipeListener::bind(&name, &shutdown).unwrap();
+
+        // 每个连接一直占着，直到客户端断开
+        let server = thread::spawn(move || {
+            listener.serve(|mut stream| {
+                stream.set_timeout(None);
+                let mut buf = [0u8; 16];
+                let _ = stream.read_bytes(&mut buf);
+                Flow::Continue
+            })
+        });
+
+        let mut clients: Vec<PipeStream> =
+            (0..PIPE_INSTANCES).map(|_| open(&name, Some(&shutdown)).unwrap()).collect();
+
+        // 实例全部被占用，等不到空闲实例
+        assert!(open(&name, Some(&shutdown)).is_err());
+
+        // 断开一个之后，新的客户端能连上，监听线程没有退出
+        drop(clients.remove(0));
+        clients.push(open(&name, Some(&shutdown)).unwrap());
+        assert!(!server.is_finished());
+
+        shutdown.signal();
+        drop(clients);
+        assert!(server.join().unwrap().is_ok());
+    }
+}
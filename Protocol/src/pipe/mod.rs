@@ -0,0 +1,72 @@
+//! 基于重叠 I/O 的命名管道运行时，DLL、Unlock 服务和 UI 共用
+//!
+//! 等待连接、读、写都同时等待 [`Shutdown`] 事件和超时：关闭时直接触发事件即可让所有阻塞的操作返回，
+//! 不需要再连接自己来唤醒 `ConnectNamedPipe`。服务端同时挂起多个管道实例，每个连接在独立线程中处理，
+//! 一个迟迟不发数据的客户端只会让自己超时，不会卡住其他连接。
+
+mod client;
+mod identity;
+mod listener;
+mod shutdown;
+mod stream;
+
+use std::io;
+
+use windows::{
+    Win32::System::Registry::{HKEY_LOCAL_MACHINE, RRF_RT_REG_SZ, RegGetValueW},
+    core::HSTRING,
+};
+
+use crate::{PIPE_KEY_NAME, PipeKey, ProtocolError, SECURE_REG_PATH, crypto::key_from_hex};
+
+pub use client::{connect, open};
+pub use listener::PipeListener;
+pub use shutdown::Shutdown;
+pub use stream::PipeStream;
+
+/// 服务端同时挂起的管道实例数
+pub const PIPE_INSTANCES: u32 = 4;
+
+/// 单次读写的默认超时（毫秒），服务端等待客户端消息、握手都使用这个时间
+pub const PIPE_IO_TIMEOUT_MS: u64 = 5_000;
+
+/// 客户端等待空闲管道实例的最长时间（毫秒）
+pub const PIPE_CONNECT_TIMEOUT_MS: u64 = 2_000;
+
+/// 读取管道 PSK，每次握手都重新读取，UI 重新生成密钥后不用重启
+pub fn load_pipe_key() -> Result<PipeKey, ProtocolError> {
+    key_from_hex(&read_hklm_string(SECURE_REG_PATH, PIPE_KEY_NAME)?)
+}
+
+// 读取 HKLM 下的字符串值
+fn read_hklm_string(path: &str, name: &str) -> io::Result<String> {
+    let mut buf = [0u16; 1024];
+    let mut size = std::mem::size_of_val(&buf) as u32;
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            &HSTRING::from(path),
+            &HSTRING::from(name),
+            RRF_RT_REG_SZ,
+            None,
+            Some(buf.as_mut_ptr() as *mut _),
+            Some(&mut size),
+        )
+    };
+    if status.is_err() {
+        return Err(io::Error::from_raw_os_error(status.0 as i32));
+    }
+
+    // 去掉结尾的 0
+    let len = (size as usize / 2).saturating_sub(1);
+    Ok(String::from_utf16_lossy(&buf[..len]))
+}
+
+// 超时和关闭都转换为 io 错误，经过 Channel 后统一成为 ProtocolError::Io
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "管道操作超时")
+}
+
+fn shut_down() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "管道已关闭")
+}
@@ -0,0 +1,50 @@
+use std::{io, sync::Arc, time::Duration};
+
+use windows::Win32::{
+    Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0},
+    System::Threading::{CreateEventW, SetEvent, WaitForSingleObject},
+};
+
+pub(super) struct EventHandle(pub(super) HANDLE);
+
+// 事件句柄可以在线程间共享
+unsafe impl Send for EventHandle {}
+unsafe impl Sync for EventHandle {}
+
+impl Drop for EventHandle {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// 关闭信号（手动重置事件），克隆后共享同一个事件
+///
+/// 触发后所有等待连接、读写中的管道操作都会立即返回错误。
+#[derive(Clone)]
+pub struct Shutdown(Arc<EventHandle>);
+
+impl Shutdown {
+    pub fn new() -> io::Result<Self> {
+        let event = unsafe { CreateEventW(None, true, false, None) }?;
+        Ok(Self(Arc::new(EventHandle(event))))
+    }
+
+    /// 触发关闭信号
+    pub fn signal(&self) {
+        let _ = unsafe { SetEvent(self.0.0) };
+    }
+
+    pub fn is_signaled(&self) -> bool {
+        self.wait(Duration::ZERO)
+    }
+
+    /// 最多等待 `timeout`，期间触发了关闭信号返回 true，可以代替 sleep 使用
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let millis = timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+        unsafe { WaitForSingleObject(self.0.0, millis) == WAIT_OBJECT_0 }
+    }
+
+    pub(crate) fn handle(&self) -> HANDLE {
+        self.0.0
+    }
+}
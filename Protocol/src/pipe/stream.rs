@@ -0,0 +1,155 @@
+use std::{io, time::Duration};
+
+use windows::{
+    Win32::{
+        Foundation::{CloseHandle, ERROR_IO_PENDING, ERROR_MORE_DATA, HANDLE, WAIT_OBJECT_0, WAIT_TIMEOUT},
+        Storage::FileSystem::{ReadFile, WriteFile},
+        System::{
+            IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED},
+            Pipes::DisconnectNamedPipe,
+            Threading::{CreateEventW, INFINITE, WaitForMultipleObjects},
+        },
+    },
+    core::Result as WinResult,
+};
+
+use super::{Shutdown, identity::{client_dirs, client_identity}, listener::Slot, load_pipe_key, shut_down, timed_out};
+use crate::{Channel, ProtocolError, Role, Transport, auth::AllowedClient, authorize_caller};
+
+/// 等待一个重叠操作的事件，同时等待关闭信号和超时
+pub(crate) fn wait_event(event: HANDLE, shutdown: Option<&Shutdown>, timeout: Option<Duration>) -> io::Result<()> {
+    let mut handles = vec![event];
+    if let Some(shutdown) = shutdown {
+        handles.push(shutdown.handle());
+    }
+    let millis = match timeout {
+        Some(timeout) => timeout.as_millis().min(INFINITE as u128 - 1) as u32,
+        None => INFINITE,
+    };
+
+    let result = unsafe { WaitForMultipleObjects(&handles, false, millis) };
+    if result == WAIT_OBJECT_0 {
+        Ok(())
+    } else if result.0 == WAIT_OBJECT_0.0 + 1 {
+        Err(shut_down())
+    } else if result == WAIT_TIMEOUT {
+        Err(timed_out())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// 已连接的管道一端，读写都是带超时、可取消的重叠 I/O
+///
+/// 服务端的实例在 drop 时断开并关闭，客户端直接关闭句柄。
+pub struct PipeStream {
+    handle: HANDLE,
+    event: HANDLE,
+    shutdown: Option<Shutdown>,
+    timeout: Option<Duration>,
+    server: bool,
+    // 服务端连接占用的实例名额，在句柄关闭之后释放
+    _slot: Option<Slot>,
+}
+
+// 句柄只在持有者的线程中使用
+unsafe impl Send for PipeStream {}
+
+impl PipeStream {
+    /// `event` 为重叠操作使用的手动重置事件，所有权转交给 PipeStream
+    pub(crate) fn new(
+        handle: HANDLE,
+        event: HANDLE,
+        shutdown: Option<Shutdown>,
+        timeout: Duration,
+        slot: Option<Slot>,
+    ) -> Self {
+        let server = slot.is_some();
+        Self { handle, event, shutdown, timeout: Some(timeout), server, _slot: slot }
+    }
+
+    /// 创建一个重叠操作使用的事件
+    pub(crate) fn create_event() -> io::Result<HANDLE> {
+        Ok(unsafe { CreateEventW(None, true, false, None) }?)
+    }
+
+    pub fn handle(&self) -> HANDLE {
+        self.handle
+    }
+
+    /// 修改单次读写的超时，None 表示一直等待（仍然会响应关闭信号）
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// 服务端：校验客户端进程，`allowed_clients` 是允许连接的程序及其所在目录
+    pub fn authorize(&self, allowed_clients: &[AllowedClient]) -> Result<(), ProtocolError> {
+        let (sids, image_path) = client_identity(self.handle)?;
+        authorize_caller(&sids, &image_path, allowed_clients, &client_dirs())
+    }
+
+    /// 完成握手，之后只能通过返回的 Channel 收发消息
+    pub fn handshake(self, role: Role) -> Result<Channel<PipeStream>, ProtocolError> {
+        let key = load_pipe_key()?;
+        let mut channel = Channel::new(self);
+        channel.handshake(role, &key)?;
+        Ok(channel)
+    }
+
+    // 发起一个重叠操作并等待完成，超时或关闭时取消该操作
+    fn overlapped(&mut self, start: impl FnOnce(HANDLE, *mut OVERLAPPED) -> WinResult<()>) -> io::Result<u32> {
+        let mut overlapped = OVERLAPPED { hEvent: self.event, ..Default::default() };
+
+        if let Err(e) = start(self.handle, &mut overlapped) {
+            // 消息模式下，消息比缓冲区长时返回 ERROR_MORE_DATA，剩余部分下次继续读
+            if e.code() != ERROR_IO_PENDING.to_hresult() && e.code() != ERROR_MORE_DATA.to_hresult() {
+                return Err(e.into());
+            }
+        }
+
+        if let Err(e) = wait_event(self.event, self.shutdown.as_ref(), self.timeout) {
+            // 取消后必须等操作真正结束，OVERLAPPED 才能释放
+            let mut transferred = 0;
+            unsafe {
+                let _ = CancelIoEx(self.handle, Some(&overlapped));
+                let _ = GetOverlappedResult(self.handle, &overlapped, &mut transferred, true);
+            }
+            return Err(e);
+        }
+
+        let mut transferred = 0;
+        if let Err(e) = unsafe { GetOverlappedResult(self.handle, &overlapped, &mut transferred, false) }
+            && e.code() != ERROR_MORE_DATA.to_hresult()
+        {
+            return Err(e.into());
+        }
+        Ok(transferred)
+    }
+}
+
+impl Transport for PipeStream {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.overlapped(|handle, overlapped| unsafe { ReadFile(handle, Some(buf), None, Some(overlapped)) })?;
+        Ok(read as usize)
+    }
+
+    fn write_bytes(&mut self, data: &[u8]) -> io::Result<()> {
+        let written = self.overlapped(|handle, overlapped| unsafe { WriteFile(handle, Some(data), None, Some(overlapped)) })?;
+        if written as usize != data.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "管道写入不完整"));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PipeStream {
+    fn drop(&mut self) {
+        unsafe {
+            if self.server {
+                let _ = DisconnectNamedPipe(self.handle);
+            }
+            let _ = CloseHandle(self.handle);
+            let _ = CloseHandle(self.event);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+/// 某个部件是否可用
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Health {
+    pub ok: bool,
+    /// 不可用时的原因，可用时为空或补充说明
+    pub message: String,
+}
+
+impl Health {
+    pub fn ok(message: impl Into<String>) -> Self {
+        Self { ok: true, message: message.into() }
+    }
+
+    pub fn failed(message: impl Into<String>) -> Self {
+        Self { ok: false, message: message.into() }
+    }
+}
+
+/// 数据库连接池状态
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DbPoolStatus {
+    pub initialized: bool,
+    pub connections: u32,
+    pub idle_connections: u32,
+}
+
+/// Unlock 服务运行状态快照，对应 Unlock/src/global.rs 中的全局状态
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    /// Unlock 服务版本
+    pub version: String,
+    pub protocol_version: u16,
+    /// 是否正在进行面容识别
+    pub is_run: bool,
+    /// 是否允许调用面容识别（锁屏时为 true）
+    pub allow_unlock: bool,
+    /// 面容不匹配的次数和上限
+    pub match_fail_count: i32,
+    pub max_retry: i32,
+    /// 识别触发模式：operation / delay
+    pub recognition_mode: String,
+    pub aligned_mode: String,
+    pub camera_index: i32,
+    /// 最近一次打开摄像头的结果，服务启动后还没打开过为 None
+    pub camera: Option<Health>,
+    /// 模型文件是否齐全
+    pub models: Health,
+    pub db_pool: DbPoolStatus,
+    /// 最近一次识别结束的时间（Unix 毫秒），还没识别过为 None
+    pub last_attempt_ms: Option<u64>,
+}
@@ -0,0 +1,126 @@
+//! 一次解锁尝试的 ID 和各阶段耗时
+//!
+//! 尝试 ID 在触发识别的地方生成（DLL 的鼠标键盘钩子、Unlock 服务的延迟计时器、UI 的测试解锁），
+//! 随 `Run`、`Credentials`、`UnlockResult` 一路传递，DLL、Unlock 服务、UI 写日志时都带上它。
+//! 按 ID 搜索三份日志，即可还原 按键 → 打开摄像头 → 检测到人脸 → 匹配 → LSA 结果 的时间线。
+
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// 当前时间（Unix 毫秒），三个进程在同一台机器上，可以直接相减
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// 生成一个新的尝试 ID（16 位十六进制），随机数不可用时退化为时间戳
+pub fn new_attempt_id() -> String {
+    let mut bytes = [0u8; 8];
+    if getrandom::getrandom(&mut bytes).is_err() {
+        bytes = now_ms().to_be_bytes();
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 一次尝试经过的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Stage {
+    /// 锁屏界面有操作 / 延迟计时器到期 / UI 发起测试解锁
+    Trigger,
+    /// Unlock 服务收到 Run
+    Received,
+    /// 摄像头已打开
+    CameraOpened,
+    /// 第一次检测到人脸
+    FirstFace,
+    /// 面容匹配成功
+    Matched,
+    /// DLL 已受理凭据
+    Accepted,
+    /// DLL 在 GetSerialization 中把凭据交给 LSA
+    Serialized,
+    /// 收到 LSA 的登录结果
+    LogonResult,
+    /// 本轮识别结束
+    Finished,
+}
+
+impl Stage {
+    /// 写日志时使用的名字
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Trigger => "trigger",
+            Stage::Received => "received",
+            Stage::CameraOpened => "camera_opened",
+            Stage::FirstFace => "first_face",
+            Stage::Matched => "matched",
+            Stage::Accepted => "accepted",
+            Stage::Serialized => "serialized",
+            Stage::LogonResult => "logon_result",
+            Stage::Finished => "finished",
+        }
+    }
+}
+
+/// 某个阶段的耗时
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Span {
+    pub attempt_id: String,
+    pub stage: Stage,
+    /// 距离触发的时间
+    pub elapsed_ms: u64,
+    /// 距离上一个阶段的时间
+    pub delta_ms: u64,
+}
+
+/// 日志格式：`[attempt 1a2b3c4d5e6f7a8b] camera_opened +120ms (+95ms)`
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[attempt {}] {} +{}ms (+{}ms)", self.attempt_id, self.stage.as_str(), self.elapsed_ms, self.delta_ms)
+    }
+}
+
+/// 记录一次尝试各阶段的时间点
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttemptTimer {
+    id: String,
+    triggered_at_ms: u64,
+    last_ms: u64,
+}
+
+impl AttemptTimer {
+    /// 在触发的地方开始一次新的尝试
+    pub fn start() -> Self {
+        Self::resume(new_attempt_id(), now_ms())
+    }
+
+    /// 从管道消息中恢复另一个进程开始的尝试
+    pub fn resume(id: impl Into<String>, triggered_at_ms: u64) -> Self {
+        Self { id: id.into(), triggered_at_ms, last_ms: triggered_at_ms }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn triggered_at_ms(&self) -> u64 {
+        self.triggered_at_ms
+    }
+
+    /// 记录到达某个阶段，返回这个阶段的耗时
+    pub fn mark(&mut self, stage: Stage) -> Span {
+        let now = now_ms().max(self.last_ms);
+        let span = Span {
+            attempt_id: self.id.clone(),
+            stage,
+            elapsed_ms: now.saturating_sub(self.triggered_at_ms),
+            delta_ms: now - self.last_ms,
+        };
+        self.last_ms = now;
+        span
+    }
+}
@@ -0,0 +1,163 @@
+//! 传输层抽象
+//!
+//! [`Transport`] 只负责收发字节，分帧、握手、加解密都在 [`Channel`] 中完成。
+//! Windows 上由 [`crate::pipe`] 中的命名管道实现，Linux 上可以用 [`memory_pair`] 或 `UnixStream`
+//! 跑通完整的请求/回复流程。
+
+use std::io;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::{encode_frame, FrameDecoder, Handshake, Message, PipeKey, ProtocolError, Role, SecureChannel};
+
+/// 一条双向连接
+pub trait Transport {
+    /// 读取一段数据，不保证是一整帧，返回 0 表示对端已关闭
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// 写入一段数据，一次调用写入一整帧
+    fn write_bytes(&mut self, data: &[u8]) -> io::Result<()>;
+}
+
+/// 建立在 [`Transport`] 之上的加密消息通道
+pub struct Channel<T: Transport> {
+    transport: T,
+    decoder: FrameDecoder,
+    secure: Option<SecureChannel>,
+}
+
+impl<T: Transport> Channel<T> {
+    pub fn new(transport: T) -> Self {
+        Self { transport, decoder: FrameDecoder::new(), secure: None }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// 是否已经握手
+    pub fn is_secure(&self) -> bool {
+        self.secure.is_some()
+    }
+
+    /// 丢弃会话密钥和未读完的数据，会话密钥只属于一次连接
+    pub fn reset(&mut self) {
+        self.secure = None;
+        self.decoder = FrameDecoder::new();
+    }
+
+    /// 读取一帧数据
+    pub fn read_frame(&mut self) -> Result<Vec<u8>, ProtocolError> {
+        let mut buf = [0u8; 512];
+
+        loop {
+            // 上一次读取时可能已经多读到了这一帧
+            if let Some(frame) = self.decoder.next_frame()? {
+                return Ok(frame);
+            }
+
+            let read = self.transport.read_bytes(&mut buf)?;
+            if read == 0 {
+                return Err(ProtocolError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "管道已关闭，消息不完整",
+                )));
+            }
+
+            self.decoder.push(&buf[..read]);
+        }
+    }
+
+    /// 写入一帧数据
+    pub fn write_frame(&mut self, payload: &[u8]) -> Result<(), ProtocolError> {
+        let frame = encode_frame(payload)?;
+        self.transport.write_bytes(&frame)?;
+        Ok(())
+    }
+
+    /// 握手：互相发送公钥，用 PSK 派生会话密钥
+    /// 服务端先收后发，客户端先发后收
+    pub fn handshake(&mut self, role: Role, psk: &PipeKey) -> Result<(), ProtocolError> {
+        let handshake = Handshake::new(role)?;
+
+        let peer_public = if role == Role::Server {
+            let peer_public = self.read_frame()?;
+            self.write_frame(&handshake.public_key())?;
+            peer_public
+        } else {
+            self.write_frame(&handshake.public_key())?;
+            self.read_frame()?
+        };
+
+        self.secure = Some(handshake.finish(&peer_public, psk)?);
+        Ok(())
+    }
+
+    /// 读取并解密一条消息
+    pub fn read(&mut self) -> Result<Message, ProtocolError> {
+        let frame = self.read_frame()?;
+        let secure = self.secure.as_mut().ok_or(ProtocolError::Crypto("管道尚未握手"))?;
+        secure.open_message(&frame)
+    }
+
+    /// 加密并写入一条消息
+    pub fn write(&mut self, message: &Message) -> Result<(), ProtocolError> {
+        let secure = self.secure.as_mut().ok_or(ProtocolError::Crypto("管道尚未握手"))?;
+        let sealed = secure.seal_message(message)?;
+        self.write_frame(&sealed)
+    }
+}
+
+/// 进程内的连接，用来在没有命名管道的环境下测试
+pub struct MemoryTransport {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+/// 创建一对互相连接的内存传输，一端写入的数据由另一端读出
+pub fn memory_pair() -> (MemoryTransport, MemoryTransport) {
+    let (a_tx, b_rx) = channel();
+    let (b_tx, a_rx) = channel();
+    (
+        MemoryTransport { tx: a_tx, rx: a_rx, pending: Vec::new() },
+        MemoryTransport { tx: b_tx, rx: b_rx, pending: Vec::new() },
+    )
+}
+
+impl Transport for MemoryTransport {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.rx.recv() {
+                Ok(data) => self.pending = data,
+                // 对端已销毁，当作连接关闭
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+
+    fn write_bytes(&mut self, data: &[u8]) -> io::Result<()> {
+        self.tx
+            .send(data.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "对端已关闭"))
+    }
+}
+
+#[cfg(unix)]
+impl Transport for std::os::unix::net::UnixStream {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(self, buf)
+    }
+
+    fn write_bytes(&mut self, data: &[u8]) -> io::Result<()> {
+        io::Write::write_all(self, data)
+    }
+}
@@ -0,0 +1,250 @@
+//! 用内存传输和 UnixStream 跑通 DLL、Unlock 服务、UI 之间的完整请求/回复流程
+
+use std::thread::{self, JoinHandle};
+
+use protocol::{
+    AttemptTimer, Channel, DbPoolStatus, Handshake, Health, Message, PROTOCOL_VERSION, PipeKey, ProtocolError,
+    RecognitionOptions, ReloadOutcome, RetryOutcome, Role, ServiceStatus, TileStatus, Transport, UnlockOutcome,
+    crypto::KEY_LEN,
+    dispatch::{
+        CredentialSink, Flow, UnlockService, request_credentials, request_reload, request_status, request_unlock,
+        send_tile_status, serve_credentials, serve_unlock,
+    },
+    transport::{MemoryTransport, memory_pair},
+};
+
+const PSK: PipeKey = [7u8; KEY_LEN];
+
+// 在线程中运行服务端：握手后交给 `serve`，返回客户端一侧已握手的 Channel
+fn connect<T, R>(
+    (client, server): (T, T),
+    serve: impl FnOnce(&mut Channel<T>) -> R + Send + 'static,
+) -> (Channel<T>, JoinHandle<R>)
+where
+    T: Transport + Send + 'static,
+    R: Send + 'static,
+{
+    let server = thread::spawn(move || {
+        let mut channel = Channel::new(server);
+        channel.handshake(Role::Server, &PSK).unwrap();
+        serve(&mut channel)
+    });
+
+    let mut client = Channel::new(client);
+    client.handshake(Role::Client, &PSK).unwrap();
+    (client, server)
+}
+
+fn status() -> ServiceStatus {
+    ServiceStatus {
+        version: String::from("test"),
+        protocol_version: PROTOCOL_VERSION,
+        is_run: false,
+        allow_unlock: true,
+        match_fail_count: 1,
+        max_retry: 3,
+        recognition_mode: String::from("operation"),
+        aligned_mode: String::from("default"),
+        camera_index: 0,
+        camera: None,
+        models: Health::ok("模型齐全"),
+        db_pool: DbPoolStatus { initialized: true, connections: 1, idle_connections: 1 },
+        last_attempt_ms: None,
+    }
+}
+
+/// 模拟 DLL：记录收到的凭据，受理后返回预设的登录结果
+#[derive(Default)]
+struct FakeSink {
+    reject: bool,
+    submitted: Vec<(String, String, String)>,
+    statuses: Vec<TileStatus>,
+}
+
+impl CredentialSink for FakeSink {
+    fn submit(&mut self, attempt: AttemptTimer, user_name: String, password: String) -> UnlockOutcome {
+        self.submitted.push((attempt.id().to_string(), user_name, password));
+        if self.reject {
+            UnlockOutcome::Rejected { reason: String::from("磁贴未就绪") }
+        } else {
+            UnlockOutcome::Accepted
+        }
+    }
+
+    fn wait_result(&mut self, _request_id: &str) -> UnlockOutcome {
+        UnlockOutcome::Logon { ntstatus: 0 }
+    }
+
+    fn show_status(&mut self, status: TileStatus) {
+        self.statuses.push(status);
+    }
+}
+
+/// 模拟 Unlock 服务：记录收到的指令，解锁请求转发给 DLL
+#[derive(Default)]
+struct FakeService {
+    runs: Vec<String>,
+    reloads: u32,
+    exited: bool,
+    dll: Option<Channel<MemoryTransport>>,
+}
+
+impl UnlockService for FakeService {
+    fn run(&mut self, attempt: AttemptTimer) {
+        self.runs.push(attempt.id().to_string());
+    }
+
+    fn retry(&mut self, _attempt: AttemptTimer) -> RetryOutcome {
+        RetryOutcome::Started
+    }
+
+    fn verify(&mut self, _attempt: AttemptTimer) -> RetryOutcome {
+        RetryOutcome::Busy
+    }
+
+    fn exit(&mut self) {
+        self.exited = true;
+    }
+
+    fn unlock(&mut self, request_id: &str, user_name: String, password: String) -> UnlockOutcome {
+        let dll = self.dll.as_mut().expect("没有连接 DLL");
+        request_credentials(dll, &AttemptTimer::resume(request_id, 0), user_name, password).unwrap()
+    }
+
+    fn status(&mut self) -> ServiceStatus {
+        status()
+    }
+
+    fn reload(&mut self) -> ReloadOutcome {
+        self.reloads += 1;
+        ReloadOutcome::Applied { options: RecognitionOptions::default() }
+    }
+}
+
+#[test]
+fn run_then_credentials_then_unlock_result() {
+    // DLL -> Unlock 服务：开始识别
+    let (mut dll, service) = connect(memory_pair(), |channel| {
+        let mut service = FakeService::default();
+        assert_eq!(serve_unlock(channel, &mut service).unwrap(), Flow::Continue);
+        service
+    });
+    let attempt = AttemptTimer::start();
+    dll.write(&Message::Run { attempt_id: attempt.id().to_string(), triggered_at_ms: attempt.triggered_at_ms() })
+        .unwrap();
+    let service = service.join().unwrap();
+    assert_eq!(service.runs, vec![attempt.id().to_string()]);
+
+    // Unlock 服务 -> DLL：匹配成功后提交凭据，先受理，再收到登录结果
+    let (mut unlock, sink) = connect(memory_pair(), |channel| {
+        let mut sink = FakeSink::default();
+        serve_credentials(channel, &mut sink).unwrap();
+        sink
+    });
+    let outcome = request_credentials(&mut unlock, &attempt, String::from(r".\bob"), String::from("pwd")).unwrap();
+    assert_eq!(outcome, UnlockOutcome::Logon { ntstatus: 0 });
+
+    let sink = sink.join().unwrap();
+    assert_eq!(
+        sink.submitted,
+        vec![(attempt.id().to_string(), String::from(r".\bob"), String::from("pwd"))]
+    );
+}
+
+#[test]
+fn rejected_credentials_return_single_result() {
+    let (mut unlock, sink) = connect(memory_pair(), |channel| {
+        let mut sink = FakeSink { reject: true, ..Default::default() };
+        serve_credentials(channel, &mut sink).unwrap();
+        // 被拒绝时不等待登录结果，之后还能继续收到状态更新
+        serve_credentials(channel, &mut sink).unwrap();
+        sink
+    });
+
+    let outcome =
+        request_credentials(&mut unlock, &AttemptTimer::start(), String::from("bob"), String::from("pwd")).unwrap();
+    assert_eq!(outcome, UnlockOutcome::Rejected { reason: String::from("磁贴未就绪") });
+    send_tile_status(&mut unlock, TileStatus::NotMatched { retries_left: 2 }).unwrap();
+
+    let sink = sink.join().unwrap();
+    assert_eq!(sink.statuses, vec![TileStatus::NotMatched { retries_left: 2 }]);
+}
+
+#[test]
+fn ui_unlock_is_forwarded_to_dll() {
+    // UI -> Unlock 服务 -> DLL，最终结果原样转告 UI
+    let (dll, sink) = connect(memory_pair(), |channel| {
+        let mut sink = FakeSink::default();
+        serve_credentials(channel, &mut sink).unwrap();
+        sink
+    });
+    let (mut ui, service) = connect(memory_pair(), move |channel| {
+        let mut service = FakeService { dll: Some(dll), ..Default::default() };
+        serve_unlock(channel, &mut service).unwrap();
+        service.runs.len()
+    });
+
+    let outcome = request_unlock(&mut ui, "ui-1", String::from("bob"), String::from("pwd")).unwrap();
+    assert_eq!(outcome, UnlockOutcome::Logon { ntstatus: 0 });
+    assert_eq!(service.join().unwrap(), 0);
+    assert_eq!(sink.join().unwrap().submitted[0].0, "ui-1");
+}
+
+#[test]
+fn status_and_reload() {
+    let (mut ui, service) = connect(memory_pair(), |channel| {
+        let mut service = FakeService::default();
+        serve_unlock(channel, &mut service).unwrap();
+        serve_unlock(channel, &mut service).unwrap();
+        assert_eq!(serve_unlock(channel, &mut service).unwrap(), Flow::Exit);
+        service
+    });
+
+    assert_eq!(request_status(&mut ui).unwrap(), status());
+    assert_eq!(request_reload(&mut ui).unwrap(), ReloadOutcome::Applied { options: RecognitionOptions::default() });
+    ui.write(&Message::Exit).unwrap();
+
+    let service = service.join().unwrap();
+    assert_eq!(service.reloads, 1);
+    assert!(service.exited);
+}
+
+#[cfg(unix)]
+#[test]
+fn status_over_unix_stream() {
+    use std::os::unix::net::UnixStream;
+
+    let (mut ui, service) = connect(UnixStream::pair().unwrap(), |channel| {
+        serve_unlock(channel, &mut FakeService::default()).unwrap()
+    });
+
+    assert_eq!(request_status(&mut ui).unwrap(), status());
+    assert_eq!(service.join().unwrap(), Flow::Continue);
+}
+
+#[test]
+fn version_mismatch_is_reported() {
+    let (client, server) = memory_pair();
+    let service = thread::spawn(move || {
+        let mut channel = Channel::new(server);
+        channel.handshake(Role::Server, &PSK).unwrap();
+        serve_unlock(&mut channel, &mut FakeService::default())
+    });
+
+    // 模拟旧版本的客户端：自己握手，发送版本号不同的消息
+    let mut client = Channel::new(client);
+    let handshake = Handshake::new(Role::Client).unwrap();
+    client.write_frame(&handshake.public_key()).unwrap();
+    let peer_public = client.read_frame().unwrap();
+    let mut secure = handshake.finish(&peer_public, &PSK).unwrap();
+    let payload = format!(r#"{{"version":{},"message":{{"type":"status"}}}}"#, PROTOCOL_VERSION - 1);
+    client.write_frame(&secure.seal(payload.as_bytes()).unwrap()).unwrap();
+
+    match service.join().unwrap() {
+        Err(ProtocolError::VersionMismatch { expected, actual }) => {
+            assert_eq!(expected, PROTOCOL_VERSION);
+            assert_eq!(actual, PROTOCOL_VERSION - 1);
+        }
+        other => panic!("应当报告版本不一致：{:?}", other.map(|_| ())),
+    }
+}
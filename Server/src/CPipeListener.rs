@@ -1,27 +1,35 @@
 use std::{
+    ffi::c_void,
     sync::{
-        Arc, Mutex,
-        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
     },
     thread::{self, JoinHandle, sleep},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use windows::Win32::{
-    Foundation::{LPARAM, LRESULT, WPARAM},
+    Foundation::{HANDLE, LPARAM, LRESULT, WPARAM},
+    System::{
+        Power::{PowerSettingRegisterNotification, PowerSettingUnregisterNotification, DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS, HPOWERNOTIFY, POWERBROADCAST_SETTING},
+        SystemServices::GUID_CONSOLE_DISPLAY_STATE,
+    },
     UI::{
         Shell::ICredentialProviderEvents,
         WindowsAndMessaging::{
-            CallNextHookEx, HHOOK, SetWindowsHookExW, UnhookWindowsHookEx, WH_KEYBOARD_LL,
-            WH_MOUSE_LL,
+            CallNextHookEx, HHOOK, KBDLLHOOKSTRUCT, SetWindowsHookExW, UnhookWindowsHookEx, DEVICE_NOTIFY_CALLBACK,
+            PBT_POWERSETTINGCHANGE, WH_KEYBOARD_LL, WH_MOUSE_LL, WM_KEYDOWN, WM_KEYUP, WM_MOUSEMOVE, WM_SYSKEYDOWN, WM_SYSKEYUP,
         },
     },
 };
-use windows_core::HSTRING;
-
-use crate::{
-    read_facewinunlock_registry, Pipe::{read, Client, Server}, SharedCredentials
+use protocol::{
+    auth::SERVER_PIPE_CLIENTS, dispatch::{request_retry, request_verify, serve_credentials, CredentialSink, Flow}, pipe::{self, PipeListener, Shutdown},
+    trace::{new_attempt_id, now_ms}, AttemptTimer, Message, RetryOutcome, Role, Stage, TileStatus, UnlockOutcome, LOGON_RESULT_TIMEOUT_MS, SERVER_PIPE_NAME, UNLOCK_PIPE_NAME,
 };
 
+use windows_core::PCWSTR;
+
+use crate::{failure_budget, logon_state::{AlreadySubmitted, PENDING_TTL}, read_facewinunlock_registry, trigger_policy::{read_trigger_policy, InputEvent, TriggerGate}, strings::{status_message, Text, TileText}, SharedCredentials};
+
 // 包装 COM 接口，使其可以跨线程传输
 #[derive(Clone)]
 struct SendableEvents(pub ICredentialProviderEvents);
@@ -30,10 +38,13 @@ unsafe impl Send for SendableEvents {}
 unsafe impl Sync for SendableEvents {}
 
 pub struct CPipeListener {
-    pub is_unlocked: Arc<AtomicBool>,
     pub running: Arc<AtomicBool>,
+    // 管道关闭信号，触发后阻塞在管道上的操作立即返回
+    shutdown: Option<Shutdown>,
     pub server_thread: Option<JoinHandle<()>>,
     pub client_thread: Option<JoinHandle<()>>,
+    // 亮屏通知，销毁时注销
+    display_notify: Option<DisplayNotify>,
 }
 
 // 2026.01.14
@@ -48,35 +59,268 @@ static IS_KEYBOARD_HOOK_INSTALLED: AtomicBool = AtomicBool::new(false);
 
 // 是否可以发送run
 static IS_SEND_RUN: AtomicBool = AtomicBool::new(false);
+// 钩子第一次触发的时间（Unix 毫秒），作为这次尝试的开始时间
+static TRIGGERED_AT_MS: AtomicU64 = AtomicU64::new(0);
+// 按注册表中的触发策略过滤钩子和亮屏通知收到的输入，监听器启动时创建
+static TRIGGER_GATE: Mutex<Option<TriggerGate>> = Mutex::new(None);
+
+// 唤醒管道Client线程：有需要发送的请求，或监听器正在停止
+static WAKE_PENDING: Mutex<bool> = Mutex::new(false);
+static WAKE: Condvar = Condvar::new();
+// Unlock 服务还没启动时，隔多久再尝试发送 Run
+const RUN_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+fn wake_client() {
+    *WAKE_PENDING.lock().unwrap() = true;
+    WAKE.notify_all();
+}
+
+// 等待唤醒，timeout 为 None 时一直等待
+fn wait_for_wake(timeout: Option<Duration>) {
+    let pending = WAKE_PENDING.lock().unwrap();
+    let mut pending = match timeout {
+        Some(timeout) => WAKE.wait_timeout_while(pending, timeout, |pending| !*pending).unwrap().0,
+        None => WAKE.wait_while(pending, |pending| !*pending).unwrap(),
+    };
+    *pending = false;
+}
+
+// 输入符合触发策略时，标记需要发送 Run，并记下触发时间
+fn on_input(event: InputEvent) {
+    let fire = TRIGGER_GATE.lock().unwrap().as_mut().is_some_and(|gate| gate.on_event(event, Instant::now()));
+    if fire && !IS_SEND_RUN.load(Ordering::SeqCst) {
+        TRIGGERED_AT_MS.store(now_ms(), Ordering::SeqCst);
+        IS_SEND_RUN.store(true, Ordering::SeqCst);
+        wake_client();
+    }
+}
+
+// 用户点击了磁贴上的“重新识别”
+static IS_SEND_RETRY: AtomicBool = AtomicBool::new(false);
+// CredUI 对话框中出现了已录入面容的账户
+static IS_SEND_VERIFY: AtomicBool = AtomicBool::new(false);
+// 点击“重新识别”或 CredUI 请求验证的时间（Unix 毫秒）
+static REQUESTED_AT_MS: AtomicU64 = AtomicU64::new(0);
+
+// 标记需要向 Unlock 服务发送重新识别请求，由管道Client线程发送
+pub fn trigger_retry() {
+    REQUESTED_AT_MS.store(now_ms(), Ordering::SeqCst);
+    IS_SEND_RETRY.store(true, Ordering::SeqCst);
+    wake_client();
+}
+
+// 标记需要请求 Unlock 服务验证面容（CredUI 场景），由管道Client线程发送
+pub fn trigger_verify() {
+    REQUESTED_AT_MS.store(now_ms(), Ordering::SeqCst);
+    IS_SEND_VERIFY.store(true, Ordering::SeqCst);
+    wake_client();
+}
+
 unsafe extern "system" fn hook_fn(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
     if code >= 0 {
-        if !IS_SEND_RUN.load(Ordering::SeqCst) {
-            IS_SEND_RUN.store(true, Ordering::SeqCst);
-        }
+        on_input(if wparam.0 as u32 == WM_MOUSEMOVE { InputEvent::MouseMove } else { InputEvent::MouseButton });
     }
 
     unsafe { CallNextHookEx(Some(MOUSE_HOOK_ID), code, wparam, lparam) }
 }
 
 unsafe extern "system" fn keyboard_hook_fn(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-    if code >= 0 {
-        if !IS_SEND_RUN.load(Ordering::SeqCst) {
-            IS_SEND_RUN.store(true, Ordering::SeqCst);
+    if code >= 0 && lparam.0 != 0 {
+        let vk = unsafe { (*(lparam.0 as *const KBDLLHOOKSTRUCT)).vkCode };
+        match wparam.0 as u32 {
+            WM_KEYDOWN | WM_SYSKEYDOWN => on_input(InputEvent::KeyDown(vk)),
+            WM_KEYUP | WM_SYSKEYUP => on_input(InputEvent::KeyUp(vk)),
+            _ => {}
         }
     }
 
     unsafe { CallNextHookEx(Some(KEYBOARD_HOOK_ID), code, wparam, lparam) }
 }
 
+// 显示器状态变化：0 关闭、1 点亮、2 变暗，注册时会先收到一次当前状态
+unsafe extern "system" fn display_state_fn(_context: *const c_void, r#type: u32, setting: *const c_void) -> u32 {
+    if r#type == PBT_POWERSETTINGCHANGE && !setting.is_null() {
+        let setting = unsafe { &*(setting as *const POWERBROADCAST_SETTING) };
+        if setting.PowerSetting == GUID_CONSOLE_DISPLAY_STATE && setting.DataLength >= 1 {
+            on_input(InputEvent::Display(setting.Data[0] == 1));
+        }
+    }
+    0
+}
+
+// 亮屏通知的注册句柄和它引用的回调参数，注销前参数必须一直有效
+struct DisplayNotify {
+    handle: HPOWERNOTIFY,
+    _params: Box<DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS>,
+}
+// 回调参数只在注册和注销时由系统读取，Context 为空
+unsafe impl Send for DisplayNotify {}
+unsafe impl Sync for DisplayNotify {}
+
+impl Drop for DisplayNotify {
+    fn drop(&mut self) {
+        let _ = unsafe { PowerSettingUnregisterNotification(self.handle) };
+    }
+}
+
+// 注册亮屏通知，回调在系统线程中调用
+fn register_display_notify() -> Option<DisplayNotify> {
+    let params = Box::new(DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS { Callback: Some(display_state_fn), Context: std::ptr::null_mut() });
+    let mut handle = std::ptr::null_mut();
+    let status = unsafe {
+        PowerSettingRegisterNotification(
+            &GUID_CONSOLE_DISPLAY_STATE,
+            DEVICE_NOTIFY_CALLBACK,
+            HANDLE(&*params as *const DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS as *mut c_void),
+            &mut handle,
+        )
+    };
+    if status.is_err() {
+        error!("注册亮屏通知失败！错误码: {:?}", status);
+        return None;
+    }
+    Some(DisplayNotify { handle: HPOWERNOTIFY(handle as isize), _params: params })
+}
+
+// 把管道收到的凭据交给登录界面，每个连接的处理线程各持有一份
+#[derive(Clone)]
+struct LogonUiSink {
+    shared_creds: Arc<Mutex<SharedCredentials>>,
+    running: Arc<AtomicBool>,
+    events: SendableEvents,
+    advise_context: usize,
+}
+
+impl CredentialSink for LogonUiSink {
+    fn submit(&mut self, mut attempt: AttemptTimer, user_name: String, password: String) -> UnlockOutcome {
+        info!("[attempt {}] 收到解锁请求，用户名 {}", attempt.id(), user_name);
+        if user_name.is_empty() {
+            return UnlockOutcome::Rejected { reason: String::from("用户名为空") };
+        }
+        // 连续登录失败次数已达上限，等用户手动登录成功后才恢复自动提交
+        if failure_budget::is_exhausted(&user_name) {
+            warn!("[attempt {}] 账户 {} 连续登录失败次数已达上限，不再自动提交", attempt.id(), user_name);
+            set_status_text(&self.shared_creds, Some(TileText::new(Text::BudgetExhausted)));
+            return UnlockOutcome::Rejected { reason: format!("账户连续 {} 次登录失败，面容解锁已暂停", failure_budget::failures(&user_name)) };
+        }
+
+        let attempt_id = attempt.id().to_string();
+        let span = attempt.mark(Stage::Accepted);
+        {
+            let mut creds = self.shared_creds.lock().unwrap();
+            match creds.logon.receive(attempt, user_name, password, Instant::now()) {
+                Ok(Some(superseded)) => warn!("尝试 {} 尚未完成，被新的解锁请求覆盖", superseded),
+                Ok(None) => {}
+                Err(AlreadySubmitted) => {
+                    // 重复或迟到的消息，凭据只能提交一次
+                    warn!("[attempt {}] 凭据已经提交过，忽略重复的解锁请求", attempt_id);
+                    return UnlockOutcome::Rejected { reason: String::from("该尝试的凭据已经提交过") };
+                }
+            }
+        }
+        info!("{}", span);
+
+        // 触发登录逻辑，登录界面会重新调用 GetCredentialCount 并取走凭据
+        match unsafe { self.events.0.CredentialsChanged(self.advise_context) } {
+            Ok(_) => UnlockOutcome::Accepted,
+            Err(e) => UnlockOutcome::Rejected { reason: format!("通知登录界面失败：{:?}", e) },
+        }
+    }
+
+    fn wait_result(&mut self, request_id: &str) -> UnlockOutcome {
+        let outcome = wait_logon_result(&self.shared_creds, &self.running, request_id);
+        info!("[attempt {}] 解锁结果：{}", request_id, outcome);
+        outcome
+    }
+
+    fn show_status(&mut self, status: TileStatus) {
+        info!("更新磁贴识别状态：{:?}", status);
+        set_status_text(&self.shared_creds, status_message(&status));
+    }
+}
+
+// 更新磁贴上的识别状态文字，每个磁贴按自己的语言显示
+fn set_status_text(shared_creds: &Arc<Mutex<SharedCredentials>>, status: Option<TileText>) {
+    // 先取出凭据列表再通知登录界面，不在持有锁时调用 COM
+    let sinks = {
+        let mut creds = shared_creds.lock().unwrap();
+        creds.status = status.clone();
+        creds.status_sinks.clone()
+    };
+
+    for sink in sinks {
+        let text = status.as_ref().map(|status| status.render(sink.language)).unwrap_or_default();
+        let wide: Vec<u16> = text.encode_utf16().chain(std::iter::once(0)).collect();
+        if let Err(e) = unsafe { sink.events.SetFieldString(&sink.credential, sink.field_id, PCWSTR(wide.as_ptr())) } {
+            warn!("刷新磁贴识别状态失败：{:?}", e);
+        }
+    }
+}
+
+// 向 Unlock 服务发送重新识别（或 CredUI 验证）请求，被拒绝时把原因显示在磁贴上
+fn send_retry(shared_creds: &Arc<Mutex<SharedCredentials>>, shutdown: &Shutdown, verify: bool) {
+    let mut attempt = AttemptTimer::resume(new_attempt_id(), REQUESTED_AT_MS.load(Ordering::SeqCst));
+    let outcome = pipe::connect(UNLOCK_PIPE_NAME, Some(shutdown)).and_then(|mut channel| {
+        if verify { request_verify(&mut channel, &attempt) } else { request_retry(&mut channel, &attempt) }
+    });
+    let message = match outcome {
+        Ok(RetryOutcome::Started) => {
+            info!("{}", attempt.mark(Stage::Trigger));
+            TileText::new(if verify { Text::Verifying } else { Text::Retrying })
+        }
+        Ok(RetryOutcome::Busy) => TileText::new(Text::Busy),
+        Ok(RetryOutcome::LockedOut { max_retry }) => TileText::new(Text::LockedOut).with_arg(max_retry),
+        Ok(RetryOutcome::Unavailable { reason }) => TileText::new(Text::Unavailable).with_arg(reason),
+        Err(e) => {
+            error!("[attempt {}] 发送重新识别请求失败：{:?}", attempt.id(), e);
+            TileText::new(Text::ServiceUnreachable)
+        }
+    };
+    info!("[attempt {}] 请求识别：{:?}", attempt.id(), message);
+    set_status_text(shared_creds, Some(message));
+}
+
+// 等待 ReportResult 写入登录结果，超时或监听器停止时返回 Timeout
+fn wait_logon_result(
+    shared_creds: &Arc<Mutex<SharedCredentials>>,
+    running: &Arc<AtomicBool>,
+    request_id: &str,
+) -> UnlockOutcome {
+    let mut waited = 0;
+    while waited < LOGON_RESULT_TIMEOUT_MS && running.load(Ordering::SeqCst) {
+        {
+            let mut creds = shared_creds.lock().unwrap();
+            if !creds.logon.is_current(request_id) {
+                // 已被新的请求覆盖
+                break;
+            }
+            if let Some((ntstatus, suspended_failures)) = creds.logon.take_result(request_id) {
+                return match suspended_failures {
+                    Some(failures) => UnlockOutcome::Suspended { ntstatus, failures },
+                    None => UnlockOutcome::Logon { ntstatus },
+                };
+            }
+            if creds.logon.expire(Instant::now()) {
+                warn!("[attempt {}] 登录界面没有在 {} 秒内使用凭据，已作废", request_id, PENDING_TTL.as_secs());
+                return UnlockOutcome::Rejected { reason: String::from("凭据已过期，登录界面没有及时使用") };
+            }
+        }
+        sleep(Duration::from_millis(100));
+        waited += 100;
+    }
+
+    shared_creds.lock().unwrap().logon.abandon(request_id);
+    UnlockOutcome::Timeout
+}
+
 impl CPipeListener {
     pub fn stop_and_join(&mut self) {
-        // 通知线程停止运行
+        // 通知线程停止运行，等待连接和读写中的管道操作都会立即返回
         self.running.store(false, Ordering::SeqCst);
-
-        // 连接自己
-        if let Err(e) = Client::new(HSTRING::from(r"\\.\pipe\MansonWindowsUnlockRustServer")) {
-            warn!("安全关闭线程失败: {}", e);
+        if let Some(shutdown) = &self.shutdown {
+            shutdown.signal();
         }
+        wake_client();
 
         // 取出并等待 server 线程
         if let Some(thread) = self.server_thread.take() {
@@ -100,73 +344,97 @@ impl CPipeListener {
     ) -> Arc<Mutex<Self>> {
         info!("CPipeListener::start - 启动管道监听");
 
+        // 只安装触发策略用得到的钩子
+        let policy = read_trigger_policy();
+        info!("CPipeListener::start - 触发策略: {:?}", policy);
+        let source = policy.source;
+        *TRIGGER_GATE.lock().unwrap() = Some(TriggerGate::new(policy));
+        IS_SEND_RUN.store(false, Ordering::SeqCst);
+
         // 注册鼠标钩子
-        let hook_id = unsafe { SetWindowsHookExW(WH_MOUSE_LL, Some(hook_fn), None, 0) };
-        if hook_id.is_err() {
-            error!("设置鼠标钩子失败！错误码: {:?}", hook_id.err());
-        } else {
-            unsafe { MOUSE_HOOK_ID = hook_id.unwrap() };
-            IS_MOUSE_HOOK_INSTALLED.store(true, Ordering::SeqCst);
+        if source.wants_mouse() {
+            let hook_id = unsafe { SetWindowsHookExW(WH_MOUSE_LL, Some(hook_fn), None, 0) };
+            if hook_id.is_err() {
+                error!("设置鼠标钩子失败！错误码: {:?}", hook_id.err());
+            } else {
+                unsafe { MOUSE_HOOK_ID = hook_id.unwrap() };
+                IS_MOUSE_HOOK_INSTALLED.store(true, Ordering::SeqCst);
+            }
         }
 
         // 注册键盘钩子
-        let hook_id = unsafe { SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_hook_fn), None, 0) };
-        if hook_id.is_err() {
-            error!("设置键盘钩子失败！错误码: {:?}", hook_id.err());
-        } else {
-            unsafe { KEYBOARD_HOOK_ID = hook_id.unwrap() };
-            IS_KEYBOARD_HOOK_INSTALLED.store(true, Ordering::SeqCst);
+        if source.wants_keyboard() {
+            let hook_id = unsafe { SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_hook_fn), None, 0) };
+            if hook_id.is_err() {
+                error!("设置键盘钩子失败！错误码: {:?}", hook_id.err());
+            } else {
+                unsafe { KEYBOARD_HOOK_ID = hook_id.unwrap() };
+                IS_KEYBOARD_HOOK_INSTALLED.store(true, Ordering::SeqCst);
+            }
         }
 
+        // 亮屏、从睡眠中唤醒
+        let display_notify = if source.wants_display() { register_display_notify() } else { None };
+
         let running = Arc::new(AtomicBool::new(true));
-        let is_unlocked = Arc::new(AtomicBool::new(false));
-        let running_clone = running.clone();
-        let is_unlocked_clone = is_unlocked.clone();
-        let sendable_events = SendableEvents(provider_events);
+        let mut listener = Self {
+            running: running.clone(),
+            shutdown: None,
+            server_thread: None,
+            client_thread: None,
+            display_notify,
+        };
+
+        let shutdown = match Shutdown::new() {
+            Ok(shutdown) => shutdown,
+            Err(e) => {
+                // 没有关闭信号就无法安全停止管道线程，干脆不启动
+                error!("创建管道关闭事件失败，不启动管道线程：{:?}", e);
+                return Arc::new(Mutex::new(listener));
+            }
+        };
 
+        let client_creds = shared_creds_clone.clone();
+        let sink = LogonUiSink {
+            shared_creds: shared_creds_clone,
+            running: running.clone(),
+            events: SendableEvents(provider_events),
+            advise_context,
+        };
+        let server_shutdown = shutdown.clone();
         let server_thread = thread::spawn(move || {
             info!("CPipeListener::start - 进入管道Server线程");
-            let events_wrapper = sendable_events;
-            let mut server = Server::new(HSTRING::from(r"\\.\pipe\MansonWindowsUnlockRustServer"));
-            unsafe {
-                while running_clone.load(Ordering::SeqCst) {
-                    let f_connected = server.connect();
-                    // 连接失败后退出循环
-                    if f_connected.is_err() {
-                        error!("管道连接失败：{:?}", f_connected.err());
-                    } else {
-                        // 如果运行状态为false，则退出循环
-                        if !running_clone.load(Ordering::SeqCst) {
-                            break;
-                        }
-                        match read(server.handle) {
-                            Ok(content) => {
-                                let parts: Vec<&str> = content.split("::FaceWinUnlock::").collect();
-
-                                if parts.len() == 2 {
-                                    let user_name = parts[0];
-                                    let password = parts[1];
-
-                                    let mut creds = shared_creds_clone.lock().unwrap();
-                                    creds.username = user_name.to_string();
-                                    creds.password = password.to_string();
-                                    creds.is_ready = true;
-
-                                    info!("收到用户名 {}", user_name);
-
-                                    // 触发登录逻辑
-                                    is_unlocked_clone.store(true, Ordering::SeqCst);
-                                    let _ = events_wrapper.0.CredentialsChanged(advise_context);
-                                }
-                            }
-                            Err(_e) => {
-                                // 先不记了
-                                // error!("读取管道数据失败：{:?}", e);
-                            }
-                        }
-                        let _ = server.disconnect();
+            let listener = match PipeListener::bind(SERVER_PIPE_NAME, &server_shutdown) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("管道创建失败：{:?}", e);
+                    return;
+                }
+            };
+
+            // 每个连接在单独的线程中处理，等待登录结果时不影响其他连接
+            let result = listener.serve(move |stream| {
+                // 只接受 Unlock 服务和 UI 的连接
+                if let Err(e) = stream.authorize(SERVER_PIPE_CLIENTS) {
+                    warn!("管道客户端校验失败，已拒绝连接：{:?}", e);
+                    return Flow::Continue;
+                }
+                // 握手失败说明对方没有 PSK，拒绝这个连接
+                let mut channel = match stream.handshake(Role::Server) {
+                    Ok(channel) => channel,
+                    Err(e) => {
+                        warn!("管道握手失败，已拒绝连接：{:?}", e);
+                        return Flow::Continue;
                     }
+                };
+                if let Err(_e) = serve_credentials(&mut channel, &mut sink.clone()) {
+                    // 先不记了，Ping 之类的探测连接读完就断开
+                    // error!("读取管道数据失败：{:?}", e);
                 }
+                Flow::Continue
+            });
+            if let Err(e) = result {
+                error!("管道监听失败：{:?}", e);
             }
 
             info!("管道Server 线程已彻底退出");
@@ -180,52 +448,55 @@ impl CPipeListener {
         } else {
             warn!("注册表配置读取失败!");
         }
-        let running_client = running.clone();
+        let client_shutdown = shutdown.clone();
         let client_thread = thread::spawn(move || {
             info!("CPipeListener::start - 进入管道Client线程");
 
-            if connect_client {
-                while running_client.load(Ordering::SeqCst) {
-                    if let Ok(client_i) =
-                        Client::new(HSTRING::from(r"\\.\pipe\MansonWindowsUnlockRustUnlock"))
-                    {
-                        loop {
-                            // 运行状态为false，则退出循环
-                            if !running_client.load(Ordering::SeqCst) {
-                                break;
-                            }
-
-                            // 如果有数据，则发送
-                            if IS_SEND_RUN.load(Ordering::SeqCst) {
-                                if let Err(e) = crate::Pipe::write(client_i.handle, String::from("run")) {
-                                    error!("向管道写入数据失败：{:?}", e);
-                                }
-                                IS_SEND_RUN.store(false, Ordering::SeqCst);
-                                
-                                // 发送完成
-                                break;
-                            }
-
-                            // 休眠
-                            sleep(Duration::from_millis(500));
-                        }
-                        
+            // 有符合触发策略的输入或点击“重新识别”时才被唤醒并连接 Unlock 服务，不再长时间占用它的管道实例
+            loop {
+                // Run 还没发出去（Unlock 服务未启动）时定时重试，否则一直等到被唤醒
+                let run_waiting = connect_client && IS_SEND_RUN.load(Ordering::SeqCst);
+                wait_for_wake(run_waiting.then_some(RUN_RETRY_INTERVAL));
+                if client_shutdown.is_signaled() {
+                    break;
+                }
+
+                // 用户主动要求的重新识别优先，不受 CONNECT_TO_PIPE 限制，连不上时提示用户
+                if IS_SEND_VERIFY.swap(false, Ordering::SeqCst) {
+                    send_retry(&client_creds, &client_shutdown, true);
+                    IS_SEND_RUN.store(false, Ordering::SeqCst);
+                    continue;
+                }
+                if IS_SEND_RETRY.swap(false, Ordering::SeqCst) {
+                    send_retry(&client_creds, &client_shutdown, false);
+                    IS_SEND_RUN.store(false, Ordering::SeqCst);
+                    continue;
+                }
+
+                if !connect_client || !IS_SEND_RUN.load(Ordering::SeqCst) {
+                    continue;
+                }
+
+                // 连接失败时保留标志，Unlock 服务启动后再发送
+                if let Ok(mut channel) = pipe::connect(UNLOCK_PIPE_NAME, Some(&client_shutdown)) {
+                    // 尝试 ID 在这里生成，耗时从钩子触发时算起
+                    let mut attempt = AttemptTimer::resume(new_attempt_id(), TRIGGERED_AT_MS.load(Ordering::SeqCst));
+                    let run = Message::Run { attempt_id: attempt.id().to_string(), triggered_at_ms: attempt.triggered_at_ms() };
+                    match channel.write(&run) {
+                        Ok(_) => info!("{}", attempt.mark(Stage::Trigger)),
+                        Err(e) => error!("向管道写入数据失败：{:?}", e),
                     }
-                    sleep(Duration::from_millis(500));
+                    IS_SEND_RUN.store(false, Ordering::SeqCst);
                 }
             }
 
             info!("管道Client 线程已彻底退出");
         });
 
-        let listener = Arc::new(Mutex::new(Self {
-            is_unlocked: is_unlocked.clone(),
-            running: running.clone(),
-            server_thread: Some(server_thread),
-            client_thread: Some(client_thread),
-        }));
-
-        listener
+        listener.shutdown = Some(shutdown);
+        listener.server_thread = Some(server_thread);
+        listener.client_thread = Some(client_thread);
+        Arc::new(Mutex::new(listener))
     }
 }
 
@@ -243,5 +514,7 @@ impl Drop for CPipeListener {
             unsafe { KEYBOARD_HOOK_ID = HHOOK(std::ptr::null_mut()) };
             IS_KEYBOARD_HOOK_INSTALLED.store(false, Ordering::SeqCst);
         }
+        // 注销亮屏通知
+        self.display_notify = None;
     }
 }
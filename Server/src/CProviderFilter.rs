@@ -0,0 +1,77 @@
+// 凭据提供程序过滤器：包装模式下隐藏系统的密码凭据提供程序，避免同一个用户出现两个密码磁贴
+use windows::Win32::{
+    Foundation::E_NOTIMPL,
+    System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
+    UI::Shell::{ICredentialProvider, ICredentialProviderFilter, ICredentialProviderFilter_Impl, CPUS_CREDUI, CPUS_LOGON, CPUS_UNLOCK_WORKSTATION, CREDENTIAL_PROVIDER_CREDENTIAL_SERIALIZATION, CREDENTIAL_PROVIDER_USAGE_SCENARIO},
+};
+use windows_core::{implement, BOOL, GUID};
+use crate::{crash_guard, dll_add_ref, dll_release, read_provider_mode, ProviderMode, CLSID_PasswordCredentialProvider};
+
+#[implement(ICredentialProviderFilter)]
+pub struct SampleFilter;
+
+impl SampleFilter {
+    pub fn new() -> Self {
+        info!("SampleFilter::new - 创建凭据提供程序过滤器实例");
+        dll_add_ref();
+        Self
+    }
+}
+
+impl Drop for SampleFilter {
+    fn drop(&mut self) {
+        info!("SampleFilter::drop - 销毁凭据提供程序过滤器实例");
+        dll_release();
+    }
+}
+
+impl ICredentialProviderFilter_Impl for SampleFilter_Impl {
+    /// 在登录界面枚举凭据提供程序之前调用，rgballow 中置为 false 的提供程序不会显示
+    fn Filter(
+        &self,
+        cpus: CREDENTIAL_PROVIDER_USAGE_SCENARIO,
+        _dwflags: u32,
+        rgclsidproviders: *const GUID,
+        rgballow: *mut BOOL,
+        cproviders: u32,
+    ) -> windows_core::Result<()> {
+        // 只有包装模式、且本提供程序参与的场景才隐藏密码提供程序
+        if read_provider_mode() != ProviderMode::Wrap || !matches!(cpus, CPUS_LOGON | CPUS_UNLOCK_WORKSTATION | CPUS_CREDUI) {
+            return Ok(());
+        }
+        if rgclsidproviders.is_null() || rgballow.is_null() {
+            return Ok(());
+        }
+        // 面容解锁停用后只剩系统的密码提供程序可用
+        if crash_guard::is_disabled() {
+            warn!("SampleFilter::Filter - 面容解锁已停用，不隐藏系统的密码凭据提供程序");
+            return Ok(());
+        }
+
+        // 密码提供程序无法创建时本提供程序也会退回到单独的磁贴，此时不能再隐藏它，否则没有地方输入密码
+        let creatable = unsafe { CoCreateInstance::<_, ICredentialProvider>(&CLSID_PasswordCredentialProvider, None, CLSCTX_INPROC_SERVER) }.is_ok();
+        if !creatable {
+            warn!("SampleFilter::Filter - 无法创建系统的密码凭据提供程序，不隐藏它");
+            return Ok(());
+        }
+
+        let providers = unsafe { std::slice::from_raw_parts(rgclsidproviders, cproviders as usize) };
+        let allow = unsafe { std::slice::from_raw_parts_mut(rgballow, cproviders as usize) };
+        for (clsid, allow) in providers.iter().zip(allow.iter_mut()) {
+            if *clsid == CLSID_PasswordCredentialProvider {
+                info!("SampleFilter::Filter - 场景 {:?}，隐藏系统的密码凭据提供程序", cpus);
+                *allow = BOOL::from(false);
+            }
+        }
+        Ok(())
+    }
+
+    /// 远程桌面传入的凭据不做转换，由原来的提供程序处理
+    fn UpdateRemoteCredential(
+        &self,
+        _pcpcsin: *const CREDENTIAL_PROVIDER_CREDENTIAL_SERIALIZATION,
+        _pcpcsout: *mut CREDENTIAL_PROVIDER_CREDENTIAL_SERIALIZATION,
+    ) -> windows_core::Result<()> {
+        Err(E_NOTIMPL.into())
+    }
+}
@@ -1,34 +1,90 @@
 // 引入必要的同步原语和Win32 API
-use std::sync::{Arc, Mutex};
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, time::Instant};
 use windows::Win32::{
-    Foundation::{ERROR_NOT_READY, E_NOTIMPL, STATUS_SUCCESS}, Graphics::Gdi::HBITMAP, Security::Credentials::{CredPackAuthenticationBufferW, CRED_PACK_FLAGS}, System::Com::CoTaskMemAlloc, UI::Shell::{
-        ICredentialProviderCredential, ICredentialProviderCredentialEvents, ICredentialProviderCredential_Impl, CPFIS_NONE, CPFS_DISPLAY_IN_BOTH, CPGSR_RETURN_CREDENTIAL_FINISHED, CPSI_ERROR, CPSI_NONE, CREDENTIAL_PROVIDER_CREDENTIAL_SERIALIZATION, CREDENTIAL_PROVIDER_FIELD_INTERACTIVE_STATE, CREDENTIAL_PROVIDER_FIELD_STATE, CREDENTIAL_PROVIDER_GET_SERIALIZATION_RESPONSE, CREDENTIAL_PROVIDER_STATUS_ICON
+    Foundation::{ERROR_NOT_READY, E_INVALIDARG, E_NOTIMPL, E_OUTOFMEMORY, NTSTATUS, STATUS_SUCCESS, S_FALSE}, Graphics::Gdi::{CreateDIBSection, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS, HBITMAP}, Security::Credentials::{CredPackAuthenticationBufferW, CRED_PACK_FLAGS, CRED_PACK_GENERIC_CREDENTIALS, CRED_PACK_WOW_BUFFER}, System::{Com::CoTaskMemAlloc, WindowsProgramming::GetComputerNameW}, UI::Shell::{
+        ICredentialProviderCredential, ICredentialProviderCredential2, ICredentialProviderCredential2_Impl, ICredentialProviderCredentialEvents, ICredentialProviderCredential_Impl, CPFIS_FOCUSED, CPFIS_NONE, CPFS_DISPLAY_IN_BOTH, CPFS_HIDDEN, CPUS_CREDUI, CPUS_UNLOCK_WORKSTATION, CREDENTIAL_PROVIDER_USAGE_SCENARIO, CPFS_DISPLAY_IN_SELECTED_TILE, CPGSR_RETURN_CREDENTIAL_FINISHED, CPSI_ERROR, CPSI_NONE, CREDENTIAL_PROVIDER_CREDENTIAL_SERIALIZATION, CREDENTIAL_PROVIDER_FIELD_INTERACTIVE_STATE, CREDENTIAL_PROVIDER_FIELD_STATE, CREDENTIAL_PROVIDER_GET_SERIALIZATION_RESPONSE, CREDENTIAL_PROVIDER_STATUS_ICON
     }
 };
-use windows_core::{implement, BOOL, PCWSTR, PWSTR};
-use crate::{CLSID_SampleProvider, SharedCredentials};
+use windows_core::{implement, IUnknownImpl, BOOL, PCWSTR, PWSTR};
+use protocol::Stage;
+use crate::{bitmap::{load_tile_image, Image}, failure_budget, install_dir, strings::{user_language, Language, Text, TileText}, kerb::{pack_unlock_logon, split_account, KerbLogonSubmitType, PointerWidth}, read_facewinunlock_registry, CLSID_SampleProvider, CPipeListener::{trigger_retry, trigger_verify}, SharedCredentials, TileUser};
+
+/// 识别状态字段的ID
+pub const STATUS_FIELD_ID: u32 = 2;
+/// “重新识别”链接字段的ID
+pub const RETRY_FIELD_ID: u32 = 3;
+/// 密码输入框字段的ID，识别失败时可以直接在面容磁贴中输入密码
+pub const PASSWORD_FIELD_ID: u32 = 4;
+/// 提交按钮字段的ID，显示在密码输入框旁边
+pub const SUBMIT_FIELD_ID: u32 = 5;
+
+/// 磁贴图片字段的ID
+pub const TILE_IMAGE_FIELD_ID: u32 = 0;
+// 磁贴图片的边长，与锁屏界面大头像一致，系统会按需缩小
+const TILE_IMAGE_SIZE: u32 = 192;
+
+/// 一个已注册事件通知的凭据，管道线程通过它刷新磁贴上的识别状态
+#[derive(Clone)]
+pub struct StatusSink {
+    pub credential: ICredentialProviderCredential,
+    pub events: ICredentialProviderCredentialEvents,
+    // 状态字段在这个凭据中的ID，包装密码凭据时排在被包装的字段后面
+    pub field_id: u32,
+    // 这个磁贴显示文字的语言
+    pub language: Language,
+}
+// 和 SendableEvents 一样，只在管道线程中调用 SetFieldString
+unsafe impl Send for StatusSink {}
+
+/// 凭据的使用场景和序列化方式，CredUI 场景下由对话框的 CREDUIWIN_* 标志和传入的序列化数据决定
+#[derive(Debug, Clone, Copy)]
+pub struct SerializationTarget {
+    pub usage_scenario: CREDENTIAL_PROVIDER_USAGE_SCENARIO,
+    // 认证包ID
+    pub auth_package_id: u32,
+    // CredPackAuthenticationBufferW 的打包方式
+    pub pack_flags: CRED_PACK_FLAGS,
+}
 
 /// 凭据实现类，代表登录界面上的一个磁贴
 /// 每个凭据对应一个可选择的登录选项
-#[implement(ICredentialProviderCredential)]
+/// 关联了用户时，作为该用户磁贴下的一个登录选项显示（和 Windows Hello 一样），否则是一个单独的磁贴
+#[implement(ICredentialProviderCredential2)]
 pub struct SampleCredential {
     // 用于接收系统事件通知的接口（互斥锁保护线程安全）
     events: Mutex<Option<ICredentialProviderCredentialEvents>>,
     shared_creds: Arc<Mutex<SharedCredentials>>,
-    auth_package_id: u32
+    target: SerializationTarget,
+    // 关联的用户
+    user: Option<TileUser>,
+    // 是否显示密码输入框，由注册表 PASSWORD_FALLBACK 控制，只有关联了用户才知道要登录的账户
+    password_fallback: bool,
+    // 用户在磁贴中输入的密码
+    password: Mutex<String>,
+    // 本次序列化的是输入的密码，ReportResult 时不影响面容凭据
+    password_serialized: AtomicBool,
+    // 磁贴文字的语言，跟随关联用户的显示语言
+    language: Language,
 }
 
 impl SampleCredential {
     /// 创建新的凭据实例
-    pub fn new(shared_creds: Arc<Mutex<SharedCredentials>>, auth_package_id: u32) -> Self {
+    pub fn new(shared_creds: Arc<Mutex<SharedCredentials>>, target: SerializationTarget, user: Option<TileUser>) -> Self {
         info!("SampleCredential::new - 创建凭据实例");
         // 引用计数不在此处管理了
         // 原因是：当 SampleCredential 转换为 ICredentialProviderCredential COM 接口后，它的生命周期由 Windows COM 运行时管理，而不是 Rust
         // 所以 SampleCredential 的Drop永远不会被调用，在new中创建的引用计数也永远不会减少
+        let password_fallback = user.is_some() && read_password_fallback();
+        let language = user_language(user.as_ref().map(|user| user.sid.as_str()));
         Self { 
             events: Mutex::new(None),
             shared_creds: shared_creds,
-            auth_package_id: auth_package_id
+            target,
+            user,
+            password_fallback,
+            password: Mutex::new(String::new()),
+            password_serialized: AtomicBool::new(false),
+            language,
         }
     }
 }
@@ -46,6 +102,15 @@ impl ICredentialProviderCredential_Impl for SampleCredential_Impl {
         info!("SampleCredential::Advise - 注册事件通知");
         let mut events = self.events.lock().unwrap();
         *events = pcpce.clone(); // 保存事件接口
+
+        // 登记到共享状态，Unlock 服务推送识别状态时刷新这个磁贴
+        if let Some(events) = pcpce.clone() {
+            let credential: ICredentialProviderCredential2 = self.to_interface();
+            let credential: ICredentialProviderCredential = credential.into();
+            let mut creds = self.shared_creds.lock().unwrap();
+            creds.status_sinks.retain(|sink| sink.credential != credential);
+            creds.status_sinks.push(StatusSink { credential, events, field_id: STATUS_FIELD_ID, language: self.language });
+        }
         Ok(())
     }
 
@@ -54,6 +119,10 @@ impl ICredentialProviderCredential_Impl for SampleCredential_Impl {
         info!("SampleCredential::UnAdvise - 取消事件通知");
         let mut events = self.events.lock().unwrap();
         *events = None; // 清除事件接口
+
+        let credential: ICredentialProviderCredential2 = self.to_interface();
+        let credential: ICredentialProviderCredential = credential.into();
+        self.shared_creds.lock().unwrap().status_sinks.retain(|sink| sink.credential != credential);
         Ok(())
     }
 
@@ -66,6 +135,15 @@ impl ICredentialProviderCredential_Impl for SampleCredential_Impl {
     /// 当凭据磁贴被取消选中时调用
     fn SetDeselected(&self) -> windows_core::Result<()> {
         info!("SampleCredential::SetDeselected - 磁贴被取消选中");
+        // 离开磁贴时清除已输入的密码
+        if self.password_fallback {
+            self.password.lock().unwrap().clear();
+            if let Some(events) = self.events.lock().unwrap().clone() {
+                let credential: ICredentialProviderCredential2 = self.to_interface();
+                let credential: ICredentialProviderCredential = credential.into();
+                let _ = unsafe { events.SetFieldString(&credential, PASSWORD_FIELD_ID, windows_core::w!("")) };
+            }
+        }
         Ok(())
     }
 
@@ -87,6 +165,16 @@ impl ICredentialProviderCredential_Impl for SampleCredential_Impl {
                     *pcpfs = CPFS_DISPLAY_IN_BOTH; // 在磁贴和详细视图中都显示
                     *pcpfis = CPFIS_NONE;          // 非交互元素（不能点击或编辑）
                 }
+                // 字段2: 识别状态，字段3: 重新识别链接，只在选中的磁贴上显示
+                STATUS_FIELD_ID | RETRY_FIELD_ID => {
+                    *pcpfs = CPFS_DISPLAY_IN_SELECTED_TILE;
+                    *pcpfis = CPFIS_NONE;
+                }
+                // 字段4: 密码输入框，字段5: 提交按钮，未启用密码输入时隐藏
+                PASSWORD_FIELD_ID | SUBMIT_FIELD_ID => {
+                    *pcpfs = if self.password_fallback { CPFS_DISPLAY_IN_SELECTED_TILE } else { CPFS_HIDDEN };
+                    *pcpfis = if dwfieldid == PASSWORD_FIELD_ID { CPFIS_FOCUSED } else { CPFIS_NONE };
+                }
                 _ => {
                     error!("SampleCredential::GetFieldState - 无效的字段ID: {}", dwfieldid);
                     return Err(windows::Win32::Foundation::E_INVALIDARG.into());
@@ -101,32 +189,47 @@ impl ICredentialProviderCredential_Impl for SampleCredential_Impl {
     fn GetStringValue(&self, dwfieldid: u32) -> windows_core::Result<PWSTR> {
         info!("SampleCredential::GetStringValue - 获取字段 {} 的文本内容", dwfieldid);
         let val = match dwfieldid {
-            1 => "FaceWinUnlock-Tauri-请勿点击此磁贴",  // 字段1的文本内容
+            // 字段1的文本内容，用户磁贴下的登录选项显示为面容解锁
+            1 if self.user.is_some() => Text::ProviderLabel.localize(self.language),
+            1 => Text::UnboundTile.localize(self.language),
+            STATUS_FIELD_ID => self.shared_creds.lock().unwrap().status.as_ref().map(|status| status.render(self.language)).unwrap_or_default(),
+            RETRY_FIELD_ID => Text::Retry.localize(self.language),
+            // 密码输入框初始为空，不回显已输入的密码
+            PASSWORD_FIELD_ID => String::new(),
             _ => {
                 warn!("SampleCredential::GetStringValue - 字段 {} 无文本内容", dwfieldid);
-                ""
+                String::new()
             }
         };
         
-        // 分配COM可释放的内存（使用CoTaskMemAlloc）
-        unsafe {
-            let utf16: Vec<u16> = val.encode_utf16().chain(Some(0)).collect(); // 转换为UTF-16并添加终止符
-            let ptr = windows::Win32::System::Com::CoTaskMemAlloc(utf16.len() * 2); // 分配内存
-            if ptr.is_null() {
-                error!("SampleCredential::GetStringValue - 内存分配失败");
-                return Err(windows::Win32::Foundation::E_OUTOFMEMORY.into());
-            }
-            // 复制数据到分配的内存
-            std::ptr::copy_nonoverlapping(utf16.as_ptr(), ptr as *mut u16, utf16.len());
-            Ok(PWSTR(ptr as *mut _))
-        }
+        co_task_string(&val)
     }
 
-    /// 获取图标字段的位图
-    /// _dwfieldid: 字段ID（这里是0）
-    fn GetBitmapValue(&self, _dwfieldid: u32) -> windows_core::Result<HBITMAP> {
+    /// 获取图标字段的位图，位图由登录界面释放
+    /// 用户磁贴下的登录选项显示该用户录入的面容，没有时和单独的磁贴一样显示软件图标
+    /// dwfieldid: 字段ID（这里是0）
+    fn GetBitmapValue(&self, dwfieldid: u32) -> windows_core::Result<HBITMAP> {
         info!("SampleCredential::GetBitmapValue - 获取图标字段的位图");
-        Ok(HBITMAP::default())  // 返回默认图标
+        if dwfieldid != TILE_IMAGE_FIELD_ID {
+            return Err(E_INVALIDARG.into());
+        }
+        let Some(dir) = install_dir() else {
+            warn!("SampleCredential::GetBitmapValue - 未找到软件安装目录，使用默认图标");
+            return Ok(HBITMAP::default());
+        };
+
+        let picture = self.user.as_ref().map(|user| dir.join("tiles").join(format!("{}.bmp", user.account_name())));
+        let path = picture.filter(|path| path.exists()).unwrap_or_else(|| dir.join("resources").join("tile_logo.bmp"));
+        let image = std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| load_tile_image(&bytes, TILE_IMAGE_SIZE));
+        match image {
+            Ok(image) => create_bitmap(&image),
+            Err(e) => {
+                warn!("SampleCredential::GetBitmapValue - 读取 {:?} 失败，使用默认图标：{}", path, e);
+                Ok(HBITMAP::default())
+            }
+        }
     }
 
     /// 获取复选框字段的值（未实现）
@@ -135,10 +238,12 @@ impl ICredentialProviderCredential_Impl for SampleCredential_Impl {
         Err(E_NOTIMPL.into())
     }
 
-    /// 获取提交按钮字段的值（未实现）
-    fn GetSubmitButtonValue(&self, _dwfieldid: u32) -> windows_core::Result<u32> {
-        info!("SampleCredential::GetSubmitButtonValue - 未实现的接口");
-        Err(E_NOTIMPL.into())
+    /// 获取提交按钮相邻的字段，提交按钮显示在密码输入框旁边
+    fn GetSubmitButtonValue(&self, dwfieldid: u32) -> windows_core::Result<u32> {
+        if dwfieldid != SUBMIT_FIELD_ID {
+            return Err(E_INVALIDARG.into());
+        }
+        Ok(PASSWORD_FIELD_ID)
     }
 
     /// 获取下拉框字段的选项数量（未实现）
@@ -153,10 +258,13 @@ impl ICredentialProviderCredential_Impl for SampleCredential_Impl {
         Err(E_NOTIMPL.into())
     }
 
-    /// 设置文本字段的值（未实现）
-    fn SetStringValue(&self, _dwfieldid: u32, _psz: &windows_core::PCWSTR) -> windows_core::Result<()> {
-        info!("SampleCredential::SetStringValue - 未实现的接口");
-        Err(E_NOTIMPL.into())
+    /// 用户在密码输入框中输入时调用，不写日志，避免密码长度等信息落盘
+    fn SetStringValue(&self, dwfieldid: u32, psz: &windows_core::PCWSTR) -> windows_core::Result<()> {
+        if dwfieldid != PASSWORD_FIELD_ID || !self.password_fallback {
+            return Err(E_INVALIDARG.into());
+        }
+        *self.password.lock().unwrap() = if psz.is_null() { String::new() } else { unsafe { psz.to_string() }.unwrap_or_default() };
+        Ok(())
     }
 
     /// 设置复选框字段的值（未实现）
@@ -172,9 +280,19 @@ impl ICredentialProviderCredential_Impl for SampleCredential_Impl {
     }
 
     /// 命令链接被点击（未实现）
-    fn CommandLinkClicked(&self, _dwfieldid: u32) -> windows_core::Result<()> {
-        info!("SampleCredential::CommandLinkClicked - 未实现的接口");
-        Err(E_NOTIMPL.into())
+    fn CommandLinkClicked(&self, dwfieldid: u32) -> windows_core::Result<()> {
+        info!("SampleCredential::CommandLinkClicked - 点击了字段 {}", dwfieldid);
+        if dwfieldid != RETRY_FIELD_ID {
+            return Err(E_INVALIDARG.into());
+        }
+        // 只做标记，由管道线程向 Unlock 服务发送，不阻塞登录界面
+        // CredUI 对话框不在锁屏状态，重新识别走验证请求
+        if self.target.usage_scenario == CPUS_CREDUI {
+            trigger_verify();
+        } else {
+            trigger_retry();
+        }
+        Ok(())
     }
 
     /// 序列化凭据信息（登录时调用）
@@ -186,70 +304,20 @@ impl ICredentialProviderCredential_Impl for SampleCredential_Impl {
         _pcpsioptionalstatusicon: *mut CREDENTIAL_PROVIDER_STATUS_ICON
     ) -> windows_core::Result<()> {
         info!("SampleCredential::GetSerialization - 序列化凭据");
-        unsafe {
-            let creds = self.shared_creds.lock().unwrap();
-            if !creds.is_ready {
-                error!("SampleCredential::GetSerialization - 凭据未就绪");
-                return Err(ERROR_NOT_READY.into());
+        // 面容凭据就绪时优先使用，否则使用磁贴中输入的密码
+        let face_ready = self.shared_creds.lock().unwrap().logon.pending_user(Instant::now()).is_some();
+        let password = std::mem::take(&mut *self.password.lock().unwrap());
+        match &self.user {
+            Some(user) if !face_ready && !password.is_empty() => {
+                info!("SampleCredential::GetSerialization - 使用输入的密码，用户: {}", user.qualified_name);
+                self.password_serialized.store(true, Ordering::SeqCst);
+                unsafe { serialize_credentials(&user.qualified_name, &password, &self.target, pcpgsr, pcpcs) }
+            }
+            _ => {
+                self.password_serialized.store(false, Ordering::SeqCst);
+                unsafe { serialize_face_credentials(&self.shared_creds, &self.target, pcpgsr, pcpcs) }
             }
-
-            // 设置响应为：成功，准备好序列化数据了
-            *pcpgsr = CPGSR_RETURN_CREDENTIAL_FINISHED;
-
-            // 准备拼接后的用户名 (Domain\User)
-            let full_username = if creds.domain.is_empty() || creds.domain == "." {
-                creds.username.clone()
-            } else {
-                format!("{}\\{}", creds.domain, creds.username)
-            };
-
-            // 获取管道收到的用户名和密码
-            let v_username = to_wide_vec(&full_username);
-            let v_password = to_wide_vec(&creds.password);
-
-            // 转换成 PCWSTR (指向 u16 数组开头的指针)
-            let pwz_username = PCWSTR(v_username.as_ptr());
-            let pwz_password = PCWSTR(v_password.as_ptr());
-
-            // 调用 LSA 序列化开始
-
-            let mut auth_buffer_size: u32 = 0;
-
-            // 使用系统 API 打包 Kerberos 凭据
-            // 第一次调用获取长度
-            let _ = CredPackAuthenticationBufferW(
-                CRED_PACK_FLAGS(0), // 默认传 0
-                pwz_username,
-                pwz_password,
-                None, // 第一次传 None
-                &mut auth_buffer_size
-            );
-
-            // 分配 COM 内存，系统会自动释放这块内存
-            let out_buf = CoTaskMemAlloc(auth_buffer_size as usize) as *mut u8;
-
-            // 第二次调用真正打包
-            CredPackAuthenticationBufferW(
-                CRED_PACK_FLAGS(0),
-                pwz_username,
-                pwz_password,
-                Some(out_buf), // 传入分配好的指针
-                &mut auth_buffer_size
-            )?;
-
-            // 填充返回给 Windows 的结构体
-            *pcpgsr = CPGSR_RETURN_CREDENTIAL_FINISHED;
-            (*pcpcs).clsidCredentialProvider = CLSID_SampleProvider; 
-            (*pcpcs).cbSerialization = auth_buffer_size;
-            (*pcpcs).rgbSerialization = out_buf;
-
-            // 重点：AuthenticationPackage 需要通过 LsaLookupAuthenticationPackage 获取
-            // 通常在 Provider 初始化时获取一次。
-            (*pcpcs).ulAuthenticationPackage = self.auth_package_id;
-
-            info!("用户名密码已发送到 LSA");
         }
-        Ok(())
     }
 
     /// 报告登录结果
@@ -260,28 +328,31 @@ impl ICredentialProviderCredential_Impl for SampleCredential_Impl {
         ppszoptionalstatustext: *mut PWSTR, 
         pcpsioptionalstatusicon: *mut CREDENTIAL_PROVIDER_STATUS_ICON
     ) -> windows_core::Result<()> {
-        info!("SampleCredential::ReportResult - 报告登录结果");
+        info!("SampleCredential::ReportResult - 报告登录结果: 0x{:08X}", ntsstatus.0 as u32);
+        if !self.password_serialized.swap(false, Ordering::SeqCst) {
+            unsafe { report_face_result(&self.shared_creds, ntsstatus, self.password_fallback, self.language, ppszoptionalstatustext, pcpsioptionalstatusicon) };
+            return Ok(());
+        }
+
+        // 输入的密码登录失败，清空输入框让用户重新输入
         unsafe {
+            if ntsstatus == STATUS_SUCCESS {
+                // 手动登录成功，恢复该账户的面容自动提交
+                if let Some(user) = &self.user {
+                    failure_budget::reset(&user.qualified_name);
+                }
+            }
             if ntsstatus != STATUS_SUCCESS {
-                // 如果登录失败
-                let mut creds = self.shared_creds.lock().unwrap();
-                // 清空错误凭据
-                creds.username.clear();
-                creds.password.clear();
-                creds.is_ready = false;
-
-                // 设置错误提示文本
-                let error_text = "用户名或密码错误，请点击自己账户，手动输入密码进入系统。";
-                let utf16: Vec<u16> = error_text.encode_utf16().chain(Some(0)).collect();
-                let ptr = windows::Win32::System::Com::CoTaskMemAlloc(utf16.len() * 2);
-                if !ptr.is_null() {
-                    std::ptr::copy_nonoverlapping(utf16.as_ptr(), ptr as *mut u16, utf16.len());
-                    *ppszoptionalstatustext = PWSTR(ptr as *mut _);
+                if let Some(events) = self.events.lock().unwrap().clone() {
+                    let credential: ICredentialProviderCredential2 = self.to_interface();
+                    let credential: ICredentialProviderCredential = credential.into();
+                    let _ = events.SetFieldString(&credential, PASSWORD_FIELD_ID, windows_core::w!(""));
+                }
+                if let Ok(text) = co_task_string(&Text::WrongPassword.localize(self.language)) {
+                    *ppszoptionalstatustext = text;
                 }
-
                 *pcpsioptionalstatusicon = CPSI_ERROR;
             } else {
-                // 登录成功
                 *ppszoptionalstatustext = PWSTR(std::ptr::null_mut());
                 *pcpsioptionalstatusicon = CPSI_NONE;
             }
@@ -290,6 +361,233 @@ impl ICredentialProviderCredential_Impl for SampleCredential_Impl {
     }
 }
 
+impl ICredentialProviderCredential2_Impl for SampleCredential_Impl {
+    /// 返回关联用户的 SID，登录界面据此把凭据放到该用户的磁贴下
+    fn GetUserSid(&self) -> windows_core::Result<PWSTR> {
+        match &self.user {
+            Some(user) => {
+                info!("SampleCredential::GetUserSid - {}", user.qualified_name);
+                co_task_string(&user.sid)
+            }
+            // 没有关联用户，S_FALSE 表示显示为单独的磁贴
+            None => Err(S_FALSE.into()),
+        }
+    }
+}
+
+impl SerializationTarget {
+    // 解锁工作站时 LSA 要求 KerbWorkstationUnlockLogon，登录和 CredUI 使用 KerbInteractiveLogon
+    fn message_type(&self) -> KerbLogonSubmitType {
+        if self.usage_scenario == CPUS_UNLOCK_WORKSTATION {
+            KerbLogonSubmitType::WorkstationUnlockLogon
+        } else {
+            KerbLogonSubmitType::InteractiveLogon
+        }
+    }
+}
+
+/// 把管道收到的面容凭据序列化交给 LSA，包装密码凭据时同样使用
+pub(crate) unsafe fn serialize_face_credentials(
+    shared_creds: &Mutex<SharedCredentials>,
+    target: &SerializationTarget,
+    pcpgsr: *mut CREDENTIAL_PROVIDER_GET_SERIALIZATION_RESPONSE,
+    pcpcs: *mut CREDENTIAL_PROVIDER_CREDENTIAL_SERIALIZATION,
+) -> windows_core::Result<()> {
+    let mut creds = shared_creds.lock().unwrap();
+    // 凭据只能取走一次，过期或已经提交过的不再提交
+    let (user_name, password) = creds.logon.take_credentials(Instant::now()).map_err(|e| {
+        error!("serialize_face_credentials - 凭据不可用：{:?}", e);
+        windows_core::Error::from(ERROR_NOT_READY)
+    })?;
+
+    if let Err(e) = unsafe { serialize_credentials(&user_name, &password, target, pcpgsr, pcpcs) } {
+        // 打包失败时没有交给 LSA，不会有 ReportResult，直接结束这次尝试
+        if let Some(attempt_id) = creds.logon.attempt_id().map(str::to_string) {
+            creds.logon.abandon(&attempt_id);
+        }
+        return Err(e);
+    }
+
+    info!("用户名密码已发送到 LSA");
+    if let Some(attempt) = creds.logon.attempt_mut() {
+        info!("{}", attempt.mark(Stage::Serialized));
+    }
+    Ok(())
+}
+
+/// 按使用场景打包用户名和密码，填充交给 LSA 的序列化结构
+unsafe fn serialize_credentials(
+    user_name: &str,
+    password: &str,
+    target: &SerializationTarget,
+    pcpgsr: *mut CREDENTIAL_PROVIDER_GET_SERIALIZATION_RESPONSE,
+    pcpcs: *mut CREDENTIAL_PROVIDER_CREDENTIAL_SERIALIZATION,
+) -> windows_core::Result<()> {
+    // CredUI 要求明文凭据时交给系统打包，其他情况自己打包 KERB_INTERACTIVE_UNLOCK_LOGON
+    let buffer = if target.pack_flags.contains(CRED_PACK_GENERIC_CREDENTIALS) {
+        unsafe { pack_with_system(user_name, password, target.pack_flags)? }
+    } else {
+        let account = split_account(user_name, &computer_name());
+        let width = if target.pack_flags.contains(CRED_PACK_WOW_BUFFER) { PointerWidth::Bits32 } else { PointerWidth::native() };
+        info!("serialize_credentials - 域: {}，用户: {}，{:?}", account.domain, account.user_name, target.message_type());
+        pack_unlock_logon(target.message_type(), &account, password, width).map_err(|e| {
+            error!("serialize_credentials - 打包凭据失败：{}", e);
+            windows_core::Error::from(E_INVALIDARG)
+        })?
+    };
+
+    unsafe {
+        // 分配 COM 内存，系统会自动释放这块内存
+        let out_buf = CoTaskMemAlloc(buffer.len()) as *mut u8;
+        if out_buf.is_null() {
+            error!("serialize_credentials - 内存分配失败");
+            return Err(E_OUTOFMEMORY.into());
+        }
+        std::ptr::copy_nonoverlapping(buffer.as_ptr(), out_buf, buffer.len());
+
+        // 填充返回给 Windows 的结构体
+        *pcpgsr = CPGSR_RETURN_CREDENTIAL_FINISHED;
+        (*pcpcs).clsidCredentialProvider = CLSID_SampleProvider; 
+        (*pcpcs).cbSerialization = buffer.len() as u32;
+        (*pcpcs).rgbSerialization = out_buf;
+
+        // 重点：AuthenticationPackage 需要通过 LsaLookupAuthenticationPackage 获取
+        // 通常在 Provider 初始化时获取一次，CredUI 场景下使用对话框要求的认证包
+        (*pcpcs).ulAuthenticationPackage = target.auth_package_id;
+    }
+    Ok(())
+}
+
+/// 记录面容凭据的登录结果，失败时清空凭据并提示用户手动输入密码
+/// in_tile_password: 当前磁贴中就有密码输入框，提示用户直接输入
+/// language: 提示文字的语言
+pub(crate) unsafe fn report_face_result(
+    shared_creds: &Mutex<SharedCredentials>,
+    ntsstatus: NTSTATUS,
+    in_tile_password: bool,
+    language: Language,
+    ppszoptionalstatustext: *mut PWSTR,
+    pcpsioptionalstatusicon: *mut CREDENTIAL_PROVIDER_STATUS_ICON,
+) {
+    let mut creds = shared_creds.lock().unwrap();
+    let user_name = creds.logon.submitted_user().unwrap_or_default().to_string();
+    if user_name.is_empty() {
+        warn!("report_face_result - 没有等待登录结果的面容凭据");
+    }
+
+    // 按账户累计连续失败次数，达到上限后不再自动提交
+    let budget = failure_budget::read_budget();
+    let suspended = if ntsstatus != STATUS_SUCCESS {
+        failure_budget::record_failure(&user_name).filter(|&failures| budget != 0 && failures >= budget)
+    } else {
+        failure_budget::reset(&user_name);
+        None
+    };
+
+    // 记录登录结果，由管道线程回复给 Unlock 服务
+    if creds.logon.report(ntsstatus.0, suspended) {
+        if let Some(attempt) = creds.logon.attempt_mut() {
+            info!("{}", attempt.mark(Stage::LogonResult));
+        }
+    }
+
+    unsafe {
+        if ntsstatus != STATUS_SUCCESS {
+            // 设置错误提示文本
+            let message = if let Some(failures) = suspended {
+                TileText::new(Text::SavedPasswordSuspended).with_arg(failures)
+            } else if in_tile_password {
+                TileText::new(Text::SavedPasswordWrongInTile)
+            } else {
+                TileText::new(Text::SavedPasswordWrong)
+            };
+            if let Ok(text) = co_task_string(&message.render(language)) {
+                *ppszoptionalstatustext = text;
+            }
+            *pcpsioptionalstatusicon = CPSI_ERROR;
+        } else {
+            // 登录成功
+            *ppszoptionalstatustext = PWSTR(std::ptr::null_mut());
+            *pcpsioptionalstatusicon = CPSI_NONE;
+        }
+    }
+}
+
+/// 分配COM可释放的字符串（使用CoTaskMemAlloc），由调用方释放
+pub(crate) fn co_task_string(val: &str) -> windows_core::Result<PWSTR> {
+    let utf16 = to_wide_vec(val);
+    unsafe {
+        let ptr = CoTaskMemAlloc(utf16.len() * 2) as *mut u16;
+        if ptr.is_null() {
+            error!("co_task_string - 内存分配失败");
+            return Err(E_OUTOFMEMORY.into());
+        }
+        // 复制数据到分配的内存
+        std::ptr::copy_nonoverlapping(utf16.as_ptr(), ptr, utf16.len());
+        Ok(PWSTR(ptr))
+    }
+}
+
+// 用 CredPackAuthenticationBufferW 打包（CredUI 的 CREDUIWIN_GENERIC）
+unsafe fn pack_with_system(user_name: &str, password: &str, flags: CRED_PACK_FLAGS) -> windows_core::Result<Vec<u8>> {
+    let v_username = to_wide_vec(user_name);
+    let v_password = to_wide_vec(password);
+    let pwz_username = PCWSTR(v_username.as_ptr());
+    let pwz_password = PCWSTR(v_password.as_ptr());
+
+    // 第一次调用获取长度
+    let mut size: u32 = 0;
+    let _ = unsafe { CredPackAuthenticationBufferW(flags, pwz_username, pwz_password, None, &mut size) };
+
+    // 第二次调用真正打包
+    let mut buffer = vec![0u8; size as usize];
+    unsafe { CredPackAuthenticationBufferW(flags, pwz_username, pwz_password, Some(buffer.as_mut_ptr()), &mut size)? };
+    buffer.truncate(size as usize);
+    Ok(buffer)
+}
+
+// 把解码后的图片复制到自上而下的 32 位 DIB
+fn create_bitmap(image: &Image) -> windows_core::Result<HBITMAP> {
+    let info = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: image.width as i32,
+            // 高度为负表示自上而下
+            biHeight: -(image.height as i32),
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut bits: *mut std::ffi::c_void = std::ptr::null_mut();
+    unsafe {
+        let bitmap = CreateDIBSection(None, &info, DIB_RGB_COLORS, &mut bits, None, 0)?;
+        std::ptr::copy_nonoverlapping(image.pixels.as_ptr(), bits as *mut u8, image.pixels.len());
+        Ok(bitmap)
+    }
+}
+
+// 本机计算机名，本地账户以它作为域名
+pub(crate) fn computer_name() -> String {
+    let mut buffer = [0u16; 256];
+    let mut size = buffer.len() as u32;
+    match unsafe { GetComputerNameW(Some(PWSTR(buffer.as_mut_ptr())), &mut size) } {
+        Ok(_) => String::from_utf16_lossy(&buffer[..size as usize]),
+        Err(e) => {
+            // 取不到时用 "."，LSA 同样按本地账户处理
+            warn!("获取计算机名失败：{:?}", e);
+            String::from(".")
+        }
+    }
+}
+
+// 是否在面容磁贴中显示密码输入框，未配置时不显示
+fn read_password_fallback() -> bool {
+    matches!(read_facewinunlock_registry("PASSWORD_FALLBACK").as_deref(), Ok("1"))
+}
+
 // 将 String 转换为符合 Win32 要求的 UTF-16 向量（带 null 结尾）
 fn to_wide_vec(s: &str) -> Vec<u16> {
     s.encode_utf16().chain(std::iter::once(0)).collect()
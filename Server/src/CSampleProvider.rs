@@ -1,11 +1,11 @@
 // 引入必要的Win32 API和同步原语
-use windows::Win32::{Foundation::{HANDLE, STATUS_SUCCESS}, Security::Authentication::Identity::{LsaConnectUntrusted, LsaDeregisterLogonProcess, LsaLookupAuthenticationPackage, LSA_STRING}, UI::Shell::*};
-use std::sync::{atomic::Ordering, Arc, Mutex};
-use crate::{dll_add_ref, dll_release, read_facewinunlock_registry, CPipeListener::CPipeListener, CSampleCredential::SampleCredential, SharedCredentials};
-use windows_core::{implement, BOOL, PSTR, PWSTR};
+use windows::Win32::{Foundation::{E_NOTIMPL, HANDLE, STATUS_SUCCESS}, Security::{Authentication::Identity::{LsaConnectUntrusted, LsaDeregisterLogonProcess, LsaLookupAuthenticationPackage, LSA_STRING}, Credentials::{CREDUIWIN_FLAGS, CREDUIWIN_GENERIC, CREDUIWIN_IN_CRED_ONLY, CREDUIWIN_PACK_32_WOW, CRED_PACK_FLAGS, CRED_PACK_GENERIC_CREDENTIALS, CRED_PACK_WOW_BUFFER}}, Storage::EnhancedStorage::PKEY_Identity_QualifiedUserName, System::Com::{CoCreateInstance, CoTaskMemFree, CLSCTX_INPROC_SERVER}, UI::Shell::*};
+use std::{sync::{Arc, Mutex}, time::Instant};
+use crate::{crash_guard, dll_add_ref, logon_state::LogonState, strings::{user_language, Text}, dll_release, read_facewinunlock_registry, read_provider_mode, ProviderMode, CLSID_PasswordCredentialProvider, CLSID_SampleProvider, CPipeListener::{trigger_verify, CPipeListener}, CSampleCredential::{SampleCredential, SerializationTarget, PASSWORD_FIELD_ID, RETRY_FIELD_ID, STATUS_FIELD_ID, SUBMIT_FIELD_ID, TILE_IMAGE_FIELD_ID}, CWrappedCredential::{WrappedCredential, WRAPPED_EXTRA_FIELDS}, SharedCredentials, TileUser};
+use windows_core::{implement, Interface, BOOL, GUID, PSTR, PWSTR};
 
 /// 凭据提供程序主类，负责管理凭据和与系统交互
-#[implement(ICredentialProvider)]
+#[implement(ICredentialProvider, ICredentialProviderSetUserArray)]
 pub struct SampleProvider {
     // 内部状态（使用互斥锁保证线程安全）
     inner: Mutex<ProviderInner>,
@@ -13,13 +13,61 @@ pub struct SampleProvider {
 
 /// 凭据提供程序的内部状态
 struct ProviderInner {
-    usage_scenario: CREDENTIAL_PROVIDER_USAGE_SCENARIO, // 使用场景（登录、解锁等）
     events: Option<ICredentialProviderEvents>, // 系统事件接口
     advise_context: usize, // 通知上下文ID
     listener: Option<Arc<Mutex<CPipeListener>>>, // 管道监听器实例
     pub shared_creds: Arc<Mutex<SharedCredentials>>, // 共享的凭据列表
-    pub auth_package_id: u32, // 认证包ID
-    pub credential: Option<ICredentialProviderCredential>,
+    target: SerializationTarget, // 凭据的使用场景、认证包和打包方式
+    credui_flags: CREDUIWIN_FLAGS, // CredUI 场景下对话框传入的 CREDUIWIN_* 标志
+    foreign_in_cred: bool, // CREDUIWIN_IN_CRED_ONLY 且传入的凭据不属于本提供程序，不显示磁贴
+    verify_requested: bool, // CredUI 场景下是否已请求 Unlock 服务验证面容
+    users: Vec<TileUser>, // 登录界面上已录入面容的用户，每个用户一个凭据
+    credentials: Vec<Option<ICredentialProviderCredential>>, // 已创建的凭据实例，与 users 一一对应
+    mode: ProviderMode, // 面容解锁在登录界面上的呈现方式
+    wrapped: Option<ICredentialProvider>, // 包装模式下被包装的系统密码凭据提供程序
+    wrapped_field_count: u32, // 被包装的提供程序的字段数量，面容字段排在它们后面
+    wrappers: Vec<(ICredentialProviderCredential, ICredentialProviderCredential)>, // 被包装的凭据和包装后的凭据
+    disabled: bool, // 崩溃保护或 DISABLED 开关停用了面容解锁，不参与任何场景
+    guard_armed: bool, // 是否写入了崩溃保护的运行标记，正常 UnAdvise 时清除
+}
+
+impl ProviderInner {
+    // 凭据数量：没有关联用户时退回到一个单独的磁贴
+    fn credential_count(&self) -> usize {
+        self.users.len().max(1)
+    }
+
+    // 管道收到的用户对应的凭据索引，找不到时使用第一个
+    fn default_index(&self) -> u32 {
+        let creds = self.shared_creds.lock().unwrap();
+        let user_name = creds.logon.user_name().unwrap_or_default();
+        self.users.iter().position(|user| user.matches(user_name)).unwrap_or(0) as u32
+    }
+
+    // 有效期内待提交的面容凭据的用户名
+    fn pending_user(&self) -> Option<String> {
+        self.shared_creds.lock().unwrap().logon.pending_user(Instant::now()).map(str::to_string)
+    }
+
+    // 包装被包装的提供程序的第 index 个凭据，同一个凭据复用同一个包装
+    fn wrapped_credential_at(&mut self, wrapped: &ICredentialProvider, index: u32) -> windows_core::Result<(ICredentialProviderCredential, Option<TileUser>)> {
+        let inner = unsafe { wrapped.GetCredentialAt(index)? };
+        let sid = match inner.cast::<ICredentialProviderCredential2>() {
+            Ok(credential) => unsafe { take_co_string(credential.GetUserSid()?) },
+            Err(_) => String::new(),
+        };
+        let user = self.users.iter().find(|user| !sid.is_empty() && user.sid == sid).cloned();
+        if let Some((_, credential)) = self.wrappers.iter().find(|(wrapped, _)| *wrapped == inner) {
+            return Ok((credential.clone(), user));
+        }
+
+        info!("SampleProvider::GetCredentialAt - 包装密码凭据，索引: {}，面容用户: {:?}", index, user);
+        let credential = WrappedCredential::new(inner.clone(), self.shared_creds.clone(), self.target, self.wrapped_field_count, user.clone());
+        let credential: ICredentialProviderCredential2 = credential.into();
+        let credential: ICredentialProviderCredential = credential.into();
+        self.wrappers.push((inner, credential.clone()));
+        Ok((credential, user))
+    }
 }
 
 impl SampleProvider {
@@ -30,24 +78,55 @@ impl SampleProvider {
 
         // 创建共享的凭据列表实例
         let shared = Arc::new(Mutex::new(SharedCredentials {
-            username: String::new(),
-            password: String::new(),
-            domain: String::from("."),
-            is_ready: false,
+            logon: LogonState::Idle,
+            status: None,
+            status_sinks: Vec::new(),
         }));
 
         // 获取认证包ID
         let auth_id = retrieve_negotiate_auth_package().unwrap_or(0);
 
+        // 停用时不创建被包装的提供程序，系统自带的密码登录不受影响
+        let disabled = crash_guard::is_disabled();
+
+        // 包装模式下创建系统的密码凭据提供程序，创建失败时退回到单独的磁贴
+        let mut mode = read_provider_mode();
+        let wrapped = if mode == ProviderMode::Wrap && !disabled {
+            match unsafe { CoCreateInstance::<_, ICredentialProvider>(&CLSID_PasswordCredentialProvider, None, CLSCTX_INPROC_SERVER) } {
+                Ok(provider) => Some(provider),
+                Err(e) => {
+                    error!("SampleProvider::new - 创建系统的密码凭据提供程序失败，退回到单独的磁贴: {:?}", e);
+                    mode = ProviderMode::Standalone;
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        info!("SampleProvider::new - 呈现方式: {:?}", mode);
+
         Self {
             inner: Mutex::new(ProviderInner {
-                usage_scenario: CPUS_LOGON, // 默认场景为登录
                 events: None,
                 advise_context: 0,
                 listener: None,
                 shared_creds: shared,
-                auth_package_id: auth_id,
-                credential: None
+                target: SerializationTarget {
+                    usage_scenario: CPUS_LOGON, // 默认场景为登录
+                    auth_package_id: auth_id,
+                    pack_flags: CRED_PACK_FLAGS(0),
+                },
+                credui_flags: CREDUIWIN_FLAGS(0),
+                foreign_in_cred: false,
+                verify_requested: false,
+                users: Vec::new(),
+                credentials: Vec::new(),
+                mode,
+                wrapped,
+                wrapped_field_count: 0,
+                wrappers: Vec::new(),
+                disabled,
+                guard_armed: false,
             }),
         }
     }
@@ -65,18 +144,68 @@ impl Drop for SampleProvider {
 impl ICredentialProvider_Impl for SampleProvider_Impl {
     /// 设置凭据提供程序的使用场景
     /// cpus: 使用场景（登录、解锁、切换用户等）
-    /// _dwflags: 附加标志
-    fn SetUsageScenario(&self, cpus: CREDENTIAL_PROVIDER_USAGE_SCENARIO, _dwflags: u32) -> windows_core::Result<()> {
-        info!("SampleProvider::SetUsageScenario - 设置使用场景: {:?}", cpus);
+    /// dwflags: CredUI 场景下为对话框的 CREDUIWIN_* 标志
+    fn SetUsageScenario(&self, cpus: CREDENTIAL_PROVIDER_USAGE_SCENARIO, dwflags: u32) -> windows_core::Result<()> {
+        info!("SampleProvider::SetUsageScenario - 设置使用场景: {:?}，标志: 0x{:08X}", cpus, dwflags);
         let mut inner = self.inner.lock().unwrap();
-        inner.usage_scenario = cpus; // 保存使用场景
+        if inner.disabled {
+            info!("SampleProvider::SetUsageScenario - 面容解锁已停用，不参与");
+            return Err(E_NOTIMPL.into());
+        }
+        match cpus {
+            CPUS_LOGON | CPUS_UNLOCK_WORKSTATION => {}
+            CPUS_CREDUI => {
+                let flags = CREDUIWIN_FLAGS(dwflags);
+                inner.credui_flags = flags;
+                inner.target.pack_flags = credui_pack_flags(flags);
+            }
+            // 修改密码等场景无法用面容完成，不参与
+            _ => {
+                info!("SampleProvider::SetUsageScenario - 不支持的使用场景，不显示面容解锁");
+                return Err(E_NOTIMPL.into());
+            }
+        }
+        inner.target.usage_scenario = cpus; // 保存使用场景
+
+        // 被包装的提供程序不支持这个场景时，退回到单独的磁贴
+        if let Some(wrapped) = inner.wrapped.clone() {
+            if let Err(e) = unsafe { wrapped.SetUsageScenario(cpus, dwflags) } {
+                error!("SampleProvider::SetUsageScenario - 被包装的提供程序不支持该场景，退回到单独的磁贴: {:?}", e);
+                inner.wrapped = None;
+                inner.mode = ProviderMode::Standalone;
+            }
+        }
         Ok(())
     }
 
-    /// 设置序列化的凭据信息（用于预填充凭据，这里空实现）
-    /// _pcpcs: 序列化的凭据数据
-    fn SetSerialization(&self, _pcpcs: *const CREDENTIAL_PROVIDER_CREDENTIAL_SERIALIZATION) -> windows_core::Result<()> {
-        info!("SampleProvider::SetSerialization - 空实现");
+    /// 设置序列化的凭据信息
+    /// CredUI 场景下，对话框通过它告知要求的认证包，以及 CREDUIWIN_IN_CRED_ONLY 时唯一可以显示的凭据
+    /// pcpcs: 序列化的凭据数据
+    fn SetSerialization(&self, pcpcs: *const CREDENTIAL_PROVIDER_CREDENTIAL_SERIALIZATION) -> windows_core::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        // 远程桌面、CredUI 传入的密码凭据交给被包装的提供程序预填
+        if let Some(wrapped) = &inner.wrapped {
+            if let Err(e) = unsafe { wrapped.SetSerialization(pcpcs) } {
+                warn!("SampleProvider::SetSerialization - 被包装的提供程序未接受传入的凭据: {:?}", e);
+            }
+        }
+        if inner.target.usage_scenario != CPUS_CREDUI || pcpcs.is_null() {
+            info!("SampleProvider::SetSerialization - 非 CredUI 场景，忽略");
+            return Ok(());
+        }
+
+        let serialization = unsafe { &*pcpcs };
+        info!(
+            "SampleProvider::SetSerialization - 对话框要求的认证包: {}，凭据提供程序: {:?}",
+            serialization.ulAuthenticationPackage, serialization.clsidCredentialProvider
+        );
+        // 按对话框要求的认证包序列化，否则调用方无法解包
+        inner.target.auth_package_id = serialization.ulAuthenticationPackage;
+        // 只允许显示传入的凭据，而传入的不是本提供程序的凭据
+        // 包装模式下密码提供程序的凭据由本提供程序显示
+        let own = serialization.clsidCredentialProvider == CLSID_SampleProvider
+            || (inner.wrapped.is_some() && serialization.clsidCredentialProvider == CLSID_PasswordCredentialProvider);
+        inner.foreign_in_cred = inner.credui_flags.contains(CREDUIWIN_IN_CRED_ONLY) && !own;
         Ok(())
     }
 
@@ -88,6 +217,20 @@ impl ICredentialProvider_Impl for SampleProvider_Impl {
         let mut inner = self.inner.lock().unwrap();
         inner.events = pcpe.clone(); // 保存事件接口
         inner.advise_context = upadvisecontext; // 保存上下文ID
+        if inner.disabled {
+            return Ok(());
+        }
+
+        // 登录界面（winlogon）中写入运行标记，CredUI 对话框在普通进程中，崩溃不影响登录
+        if matches!(inner.target.usage_scenario, CPUS_LOGON | CPUS_UNLOCK_WORKSTATION) && !inner.guard_armed {
+            crash_guard::session_started();
+            inner.guard_armed = true;
+        }
+
+        // 被包装的提供程序通过同一个上下文通知凭据变化，登录界面会重新枚举本提供程序
+        if let Some(wrapped) = &inner.wrapped {
+            unsafe { wrapped.Advise(pcpe.as_ref(), upadvisecontext)? };
+        }
 
         // 启动管道监听，传入系统事件接口
         if let Some(events) = &inner.events {
@@ -113,12 +256,30 @@ impl ICredentialProvider_Impl for SampleProvider_Impl {
             listener.stop_and_join();
         }
         inner.listener = None;
+
+        if let Some(wrapped) = &inner.wrapped {
+            unsafe { wrapped.UnAdvise()? };
+        }
+
+        // 正常走到这里，说明这次没有崩溃或卡死
+        if inner.guard_armed {
+            crash_guard::session_ended();
+            inner.guard_armed = false;
+        }
         Ok(())
     }
 
     /// 获取字段描述符的数量
+    /// 包装模式下是被包装的字段加上识别状态和重新识别链接
     fn GetFieldDescriptorCount(&self) -> windows_core::Result<u32> {
-        let count = 2; // 我们定义了2个字段：图标和文本
+        let mut inner = self.inner.lock().unwrap();
+        let count = match inner.wrapped.clone() {
+            Some(wrapped) => {
+                inner.wrapped_field_count = unsafe { wrapped.GetFieldDescriptorCount()? };
+                inner.wrapped_field_count + WRAPPED_EXTRA_FIELDS
+            }
+            None => 6, // 我们定义了6个字段：图标、文本、识别状态、重新识别链接、密码输入框和提交按钮
+        };
         info!("SampleProvider::GetFieldDescriptorCount - 字段数量: {}", count);
         Ok(count)
     }
@@ -127,42 +288,30 @@ impl ICredentialProvider_Impl for SampleProvider_Impl {
     /// dwindex: 字段索引
     fn GetFieldDescriptorAt(&self, dwindex: u32) -> windows_core::Result<*mut CREDENTIAL_PROVIDER_FIELD_DESCRIPTOR> {
         info!("SampleProvider::GetFieldDescriptorAt - 获取字段 {} 的描述符", dwindex);
-        unsafe {
-            // 分配字段描述符的内存（使用CoTaskMemAlloc，系统会负责释放）
-            let size = std::mem::size_of::<CREDENTIAL_PROVIDER_FIELD_DESCRIPTOR>();
-            let ptr = windows::Win32::System::Com::CoTaskMemAlloc(size) as *mut CREDENTIAL_PROVIDER_FIELD_DESCRIPTOR;
-            if ptr.is_null() {
-                error!("SampleProvider::GetFieldDescriptorAt - 内存分配失败");
-                return Err(windows::Win32::Foundation::E_OUTOFMEMORY.into());
-            }
-    
-            // 根据索引设置字段类型和标签
-            let (ft, label) = match dwindex {
-                0 => (CPFT_TILE_IMAGE, "框架图标"),  // 字段0: 图标
-                1 => (CPFT_LARGE_TEXT, "WinLogon基础框架加载成功！"),  // 字段1: 文本
-                _ => {
-                    error!("SampleProvider::GetFieldDescriptorAt - 无效的字段索引: {}", dwindex);
-                    return Err(windows::Win32::Foundation::E_INVALIDARG.into());
-                }
-            };
-    
-            // 转换标签为UTF-16并分配内存
-            let label_u16: Vec<u16> = label.encode_utf16().chain(Some(0)).collect();
-            let label_ptr = windows::Win32::System::Com::CoTaskMemAlloc(label_u16.len() * 2) as *mut u16;
-            if label_ptr.is_null() {
-                error!("SampleProvider::GetFieldDescriptorAt - 标签内存分配失败");
-                windows::Win32::System::Com::CoTaskMemFree(Some(ptr as *mut _)); // 释放之前分配的内存
-                return Err(windows::Win32::Foundation::E_OUTOFMEMORY.into());
+        let inner = self.inner.lock().unwrap();
+        // 包装模式下，被包装的字段原样返回，面容字段对应单独磁贴中的字段
+        let field_id = match &inner.wrapped {
+            Some(wrapped) if dwindex < inner.wrapped_field_count => return unsafe { wrapped.GetFieldDescriptorAt(dwindex) },
+            Some(_) => dwindex - inner.wrapped_field_count + STATUS_FIELD_ID,
+            None => dwindex,
+        };
+
+        // 根据索引设置字段类型和标签，字段描述符由所有磁贴共享，使用系统的显示语言
+        let (ft, label, guid) = match field_id {
+            // 字段0: 图标，标记为提供程序图标，登录选项列表中显示同一个图标
+            TILE_IMAGE_FIELD_ID => (CPFT_TILE_IMAGE, Text::ProviderLabel, CPFG_CREDENTIAL_PROVIDER_LOGO),
+            // 字段1: 文本，标记为提供程序名称，作为登录选项的提示文字
+            1 => (CPFT_LARGE_TEXT, Text::ProviderLabel, CPFG_CREDENTIAL_PROVIDER_LABEL),
+            STATUS_FIELD_ID => (CPFT_SMALL_TEXT, Text::StatusLabel, GUID::zeroed()),  // 字段2: Unlock 服务推送的识别状态
+            RETRY_FIELD_ID => (CPFT_COMMAND_LINK, Text::Retry, GUID::zeroed()),  // 字段3: 点击后请求 Unlock 服务重新识别
+            PASSWORD_FIELD_ID => (CPFT_PASSWORD_TEXT, Text::Password, CPFG_LOGON_PASSWORD),  // 字段4: 识别失败时直接输入密码
+            SUBMIT_FIELD_ID => (CPFT_SUBMIT_BUTTON, Text::Submit, GUID::zeroed()),  // 字段5: 提交输入的密码
+            _ => {
+                error!("SampleProvider::GetFieldDescriptorAt - 无效的字段索引: {}", dwindex);
+                return Err(windows::Win32::Foundation::E_INVALIDARG.into());
             }
-            std::ptr::copy_nonoverlapping(label_u16.as_ptr(), label_ptr, label_u16.len());
-    
-            // 设置字段描述符的属性
-            (*ptr).dwFieldID = dwindex;
-            (*ptr).cpft = ft;
-            (*ptr).pszLabel = PWSTR(label_ptr);
-    
-            Ok(ptr)
-        }
+        };
+        unsafe { alloc_field_descriptor(dwindex, ft, &label.localize(user_language(None)), guid) }
     }
 
     /// 获取凭据的数量和默认凭据
@@ -176,7 +325,18 @@ impl ICredentialProvider_Impl for SampleProvider_Impl {
         pbautologonwithdefault: *mut BOOL
     ) -> windows_core::Result<()> {
         info!("SampleProvider::GetCredentialCount - 获取凭据数量");
-        let inner = self.inner.lock().unwrap();
+        let mut inner = self.inner.lock().unwrap();
+        if inner.disabled {
+            unsafe {
+                *pdwcount = 0;
+                *pdwdefault = CREDENTIAL_PROVIDER_NO_DEFAULT;
+                *pbautologonwithdefault = BOOL::from(false);
+            }
+            return Ok(());
+        }
+        if let Some(wrapped) = inner.wrapped.clone() {
+            return unsafe { self.wrapped_credential_count(&mut inner, &wrapped, pdwcount, pdwdefault, pbautologonwithdefault) };
+        }
         let mut show_tile = true;
         if let Ok(result) = read_facewinunlock_registry("SHOW_TILE") {
             if result.as_str() == "0" {
@@ -188,21 +348,32 @@ impl ICredentialProvider_Impl for SampleProvider_Impl {
 
         info!( "是否显示图标: {}", show_tile);
 
+        let mut count = inner.credential_count() as u32;
+        if inner.target.usage_scenario == CPUS_CREDUI {
+            // 对话框中没有已录入面容的账户，或只允许显示其他提供程序的凭据时，不显示磁贴也不开始识别
+            if inner.users.is_empty() || inner.foreign_in_cred {
+                count = 0;
+            } else if !inner.verify_requested {
+                info!("SampleProvider::GetCredentialCount - CredUI 对话框中有已录入面容的账户，请求验证面容");
+                inner.verify_requested = true;
+                trigger_verify();
+            }
+        }
         unsafe {
-            // 如果管道已经收到了数据，告诉系统我们要自动登录
-            if let Some(l) = &inner.listener {
-                let listener = l.lock().unwrap();
-                if listener.is_unlocked.load(Ordering::SeqCst) {
-                    listener.is_unlocked.store(false, Ordering::SeqCst);
-                    *pdwcount = 1;
-                    *pdwdefault = 0;
+            // 如果管道收到的凭据还在有效期内，告诉系统我们要自动登录
+            if inner.listener.is_some() {
+                if inner.pending_user().is_some() {
+                    *pdwcount = count;
+                    *pdwdefault = inner.default_index(); // 默认选中收到的用户
                     *pbautologonwithdefault = BOOL::from(true); // 触发自动登录
                 } else {
-                    *pdwcount = if show_tile { 1 } else { 0 };
+                    *pdwcount = if show_tile { count } else { 0 };
+                    // 作为默认登录选项时选中已录入面容的用户，否则由系统决定
+                    *pdwdefault = if inner.mode == ProviderMode::Default { inner.default_index() } else { CREDENTIAL_PROVIDER_NO_DEFAULT };
                 }
             }
+            info!("SampleProvider::GetCredentialCount - 凭据数量: {}，默认索引: {}", *pdwcount, *pdwdefault);
         }
-        info!("SampleProvider::GetCredentialCount - 凭据数量: 1，默认索引: 0");
         Ok(())
     }
 
@@ -210,18 +381,28 @@ impl ICredentialProvider_Impl for SampleProvider_Impl {
     /// dwindex: 凭据索引
     fn GetCredentialAt(&self, dwindex: u32) -> windows_core::Result<ICredentialProviderCredential> {
         info!("SampleProvider::GetCredentialAt - 获取凭据，索引: {}", dwindex);
-        if dwindex == 0 {
-            let mut inner = self.inner.lock().unwrap();
-            if let Some(ref credential) = inner.credential {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(wrapped) = inner.wrapped.clone() {
+            return inner.wrapped_credential_at(&wrapped, dwindex).map(|(credential, _)| credential);
+        }
+        let index = dwindex as usize;
+        if index < inner.credential_count() {
+            if inner.credentials.len() < inner.credential_count() {
+                let count = inner.credential_count();
+                inner.credentials.resize(count, None);
+            }
+            if let Some(ref credential) = inner.credentials[index] {
                 info!("SampleProvider::GetCredentialAt - 复用已存在的凭据实例");
                 return Ok(credential.clone());
             }
 
             // 创建凭据实例并转换为接口返回，并传递收到的用户名和密码
-            info!("SampleProvider::GetCredentialAt - 首次创建凭据实例");
-            let cred = SampleCredential::new(inner.shared_creds.clone(), inner.auth_package_id);
-            let cred_interface: ICredentialProviderCredential = cred.into();
-            inner.credential = Some(cred_interface.clone());
+            let user = inner.users.get(index).cloned();
+            info!("SampleProvider::GetCredentialAt - 首次创建凭据实例，用户: {:?}", user);
+            let cred = SampleCredential::new(inner.shared_creds.clone(), inner.target, user);
+            let cred_interface: ICredentialProviderCredential2 = cred.into();
+            let cred_interface: ICredentialProviderCredential = cred_interface.into();
+            inner.credentials[index] = Some(cred_interface.clone());
             Ok(cred_interface)
         } else {
             error!("SampleProvider::GetCredentialAt - 无效的凭据索引: {}", dwindex);
@@ -230,6 +411,144 @@ impl ICredentialProvider_Impl for SampleProvider_Impl {
     }
 }
 
+/// 登录界面把要显示的用户交给凭据提供程序（在 GetCredentialCount 之前调用）
+/// 只为已录入面容的用户创建凭据，面容解锁显示为这些用户磁贴下的登录选项
+impl ICredentialProviderSetUserArray_Impl for SampleProvider_Impl {
+    fn SetUserArray(&self, users: windows_core::Ref<ICredentialProviderUserArray>) -> windows_core::Result<()> {
+        // 被包装的密码提供程序同样按用户创建凭据
+        if let Some(wrapped) = &self.inner.lock().unwrap().wrapped {
+            if let Ok(set_user_array) = wrapped.cast::<ICredentialProviderSetUserArray>() {
+                unsafe { set_user_array.SetUserArray(users.as_ref())? };
+            }
+        }
+        let enrolled = read_enrolled_users();
+        let mut tile_users = Vec::new();
+        if let Some(users) = users.as_ref() {
+            let count = unsafe { users.GetCount()? };
+            for i in 0..count {
+                let user = unsafe { users.GetAt(i)? };
+                let sid = unsafe { take_co_string(user.GetSid()?) };
+                let qualified_name = unsafe { take_co_string(user.GetStringValue(&PKEY_Identity_QualifiedUserName)?) };
+                let tile_user = TileUser { sid, qualified_name };
+                if enrolled.iter().any(|name| tile_user.matches(name)) {
+                    tile_users.push(tile_user);
+                }
+            }
+        }
+        info!("SampleProvider::SetUserArray - 已录入面容的用户: {:?}", tile_users);
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.users = tile_users;
+        inner.credentials.clear();
+        inner.wrappers.clear();
+        Ok(())
+    }
+}
+
+impl SampleProvider_Impl {
+    /// 包装模式下的凭据数量和默认凭据，与被包装的提供程序一致
+    /// 面容匹配成功时选中并自动提交该用户的磁贴
+    unsafe fn wrapped_credential_count(
+        &self,
+        inner: &mut ProviderInner,
+        wrapped: &ICredentialProvider,
+        pdwcount: *mut u32,
+        pdwdefault: *mut u32,
+        pbautologonwithdefault: *mut BOOL,
+    ) -> windows_core::Result<()> {
+        unsafe { wrapped.GetCredentialCount(pdwcount, pdwdefault, pbautologonwithdefault)? };
+        let count = unsafe { *pdwcount };
+
+        if inner.target.usage_scenario == CPUS_CREDUI && !inner.users.is_empty() && !inner.foreign_in_cred && !inner.verify_requested {
+            info!("SampleProvider::GetCredentialCount - CredUI 对话框中有已录入面容的账户，请求验证面容");
+            inner.verify_requested = true;
+            trigger_verify();
+        }
+
+        let pending = if inner.listener.is_some() { inner.pending_user() } else { None };
+        if let Some(user_name) = pending {
+            for index in 0..count {
+                let (_, user) = inner.wrapped_credential_at(wrapped, index)?;
+                if user.is_some_and(|user| user.matches(&user_name)) {
+                    unsafe {
+                        *pdwdefault = index;
+                        *pbautologonwithdefault = BOOL::from(true); // 触发自动登录
+                    }
+                    break;
+                }
+            }
+        }
+        info!("SampleProvider::GetCredentialCount - 包装模式，凭据数量: {}，默认索引: {}", count, unsafe { *pdwdefault });
+        Ok(())
+    }
+}
+
+// 分配字段描述符和标签的内存（使用CoTaskMemAlloc，系统会负责释放）
+unsafe fn alloc_field_descriptor(field_id: u32, ft: CREDENTIAL_PROVIDER_FIELD_TYPE, label: &str, guid: GUID) -> windows_core::Result<*mut CREDENTIAL_PROVIDER_FIELD_DESCRIPTOR> {
+    unsafe {
+        let size = std::mem::size_of::<CREDENTIAL_PROVIDER_FIELD_DESCRIPTOR>();
+        let ptr = windows::Win32::System::Com::CoTaskMemAlloc(size) as *mut CREDENTIAL_PROVIDER_FIELD_DESCRIPTOR;
+        if ptr.is_null() {
+            error!("SampleProvider::GetFieldDescriptorAt - 内存分配失败");
+            return Err(windows::Win32::Foundation::E_OUTOFMEMORY.into());
+        }
+
+        // 转换标签为UTF-16并分配内存
+        let label_u16: Vec<u16> = label.encode_utf16().chain(Some(0)).collect();
+        let label_ptr = windows::Win32::System::Com::CoTaskMemAlloc(label_u16.len() * 2) as *mut u16;
+        if label_ptr.is_null() {
+            error!("SampleProvider::GetFieldDescriptorAt - 标签内存分配失败");
+            windows::Win32::System::Com::CoTaskMemFree(Some(ptr as *mut _)); // 释放之前分配的内存
+            return Err(windows::Win32::Foundation::E_OUTOFMEMORY.into());
+        }
+        std::ptr::copy_nonoverlapping(label_u16.as_ptr(), label_ptr, label_u16.len());
+
+        // 设置字段描述符的属性
+        ptr.write(CREDENTIAL_PROVIDER_FIELD_DESCRIPTOR {
+            dwFieldID: field_id,
+            cpft: ft,
+            pszLabel: PWSTR(label_ptr),
+            guidFieldType: guid,
+        });
+        Ok(ptr)
+    }
+}
+
+// CredUI 对话框的 CREDUIWIN_* 标志对应的打包方式
+fn credui_pack_flags(flags: CREDUIWIN_FLAGS) -> CRED_PACK_FLAGS {
+    let mut pack_flags = CRED_PACK_FLAGS(0);
+    // 调用方要求明文的用户名密码（CredUnPackAuthenticationBuffer 解包）
+    if flags.contains(CREDUIWIN_GENERIC) {
+        pack_flags |= CRED_PACK_GENERIC_CREDENTIALS;
+    }
+    // 32 位调用方运行在 64 位系统上
+    if flags.contains(CREDUIWIN_PACK_32_WOW) {
+        pack_flags |= CRED_PACK_WOW_BUFFER;
+    }
+    pack_flags
+}
+
+// 读取 UI 写入注册表的已录入面容账户，以 | 分隔
+pub(crate) fn read_enrolled_users() -> Vec<String> {
+    match read_facewinunlock_registry("ENROLLED_USERS") {
+        Ok(value) => value.split('|').map(str::trim).filter(|name| !name.is_empty()).map(String::from).collect(),
+        Err(e) => {
+            warn!("读取已录入面容的账户失败，显示为单独的磁贴：{:?}", e);
+            Vec::new()
+        }
+    }
+}
+
+// 读取系统用 CoTaskMemAlloc 分配的字符串并释放
+unsafe fn take_co_string(ptr: PWSTR) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    let value = unsafe { ptr.to_string() }.unwrap_or_default();
+    unsafe { CoTaskMemFree(Some(ptr.0 as *const _)) };
+    value
+}
+
 // 获取Negotiate AuthPackage ID
 pub fn retrieve_negotiate_auth_package() -> windows_core::Result<u32> {
     info!("正在获取 AuthPackage ID...");
@@ -0,0 +1,312 @@
+// 包装系统密码凭据的磁贴：密码输入和面容解锁在同一个磁贴中
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, time::Instant};
+use windows::Win32::{
+    Foundation::{E_INVALIDARG, HWND, NTSTATUS, STATUS_SUCCESS},
+    Graphics::Gdi::HBITMAP,
+    UI::Shell::{
+        ICredentialProviderCredential, ICredentialProviderCredential2, ICredentialProviderCredential2_Impl, ICredentialProviderCredentialEvents,
+        ICredentialProviderCredentialEvents_Impl, ICredentialProviderCredential_Impl, CPFIS_NONE, CPFS_DISPLAY_IN_SELECTED_TILE, CPFS_HIDDEN, CPUS_CREDUI,
+        CREDENTIAL_PROVIDER_CREDENTIAL_SERIALIZATION, CREDENTIAL_PROVIDER_FIELD_INTERACTIVE_STATE, CREDENTIAL_PROVIDER_FIELD_STATE,
+        CREDENTIAL_PROVIDER_GET_SERIALIZATION_RESPONSE, CREDENTIAL_PROVIDER_STATUS_ICON,
+    },
+};
+use windows_core::{implement, Interface, IUnknownImpl, Ref, BOOL, PCWSTR, PWSTR};
+use crate::{
+    CPipeListener::{trigger_retry, trigger_verify},
+    CSampleCredential::{co_task_string, report_face_result, serialize_face_credentials, SerializationTarget, StatusSink, RETRY_FIELD_ID, STATUS_FIELD_ID},
+    failure_budget, strings::{user_language, Language, Text}, SharedCredentials, TileUser,
+};
+
+/// 包装模式下追加在被包装字段后面的面容字段数量：识别状态、重新识别链接
+pub const WRAPPED_EXTRA_FIELDS: u32 = 2;
+
+/// 包装一个系统密码凭据，被包装的字段ID保持不变，面容字段排在它们后面
+/// 同时作为被包装凭据的事件接收方，把它发出的事件以自己的身份转发给登录界面
+#[implement(ICredentialProviderCredential2, ICredentialProviderCredentialEvents)]
+pub struct WrappedCredential {
+    inner: ICredentialProviderCredential,
+    // 登录界面的事件接口
+    events: Mutex<Option<ICredentialProviderCredentialEvents>>,
+    shared_creds: Arc<Mutex<SharedCredentials>>,
+    target: SerializationTarget,
+    // 被包装凭据的字段数量
+    field_offset: u32,
+    // 这个磁贴的用户已录入面容时才显示面容字段、接管序列化
+    user: Option<TileUser>,
+    // 本次序列化的是面容凭据，ReportResult 时由自己处理
+    face_serialized: AtomicBool,
+    // 面容字段文字的语言，跟随磁贴用户的显示语言
+    language: Language,
+}
+
+impl WrappedCredential {
+    pub fn new(
+        inner: ICredentialProviderCredential,
+        shared_creds: Arc<Mutex<SharedCredentials>>,
+        target: SerializationTarget,
+        field_offset: u32,
+        user: Option<TileUser>,
+    ) -> Self {
+        info!("WrappedCredential::new - 包装密码凭据，面容用户: {:?}", user);
+        let language = user_language(user.as_ref().map(|user| user.sid.as_str()));
+        Self { inner, events: Mutex::new(None), shared_creds, target, field_offset, user, face_serialized: AtomicBool::new(false), language }
+    }
+
+    // 面容字段对应 SampleCredential 中的字段ID，被包装的字段返回 None
+    fn face_field(&self, dwfieldid: u32) -> Option<u32> {
+        (dwfieldid >= self.field_offset).then(|| dwfieldid - self.field_offset + STATUS_FIELD_ID)
+    }
+
+    // 管道收到的面容凭据是否属于这个磁贴的用户
+    fn owns_face_credentials(&self) -> bool {
+        let creds = self.shared_creds.lock().unwrap();
+        let pending = creds.logon.pending_user(Instant::now());
+        self.user.as_ref().is_some_and(|user| pending.is_some_and(|name| user.matches(name)))
+    }
+}
+
+impl WrappedCredential_Impl {
+    // 自己的 ICredentialProviderCredential 接口，转发事件和登记状态刷新时使用
+    fn credential(&self) -> ICredentialProviderCredential {
+        let credential: ICredentialProviderCredential2 = self.to_interface();
+        credential.into()
+    }
+
+    // 登录界面的事件接口，未注册时返回错误
+    fn ui_events(&self) -> windows_core::Result<ICredentialProviderCredentialEvents> {
+        self.events.lock().unwrap().clone().ok_or_else(|| E_INVALIDARG.into())
+    }
+}
+
+impl ICredentialProviderCredential_Impl for WrappedCredential_Impl {
+    fn Advise(&self, pcpce: Ref<ICredentialProviderCredentialEvents>) -> windows_core::Result<()> {
+        info!("WrappedCredential::Advise - 注册事件通知");
+        *self.events.lock().unwrap() = pcpce.clone();
+
+        // 已录入面容的用户才刷新识别状态
+        if let (Some(events), Some(_)) = (pcpce.clone(), &self.user) {
+            let credential = self.credential();
+            let mut creds = self.shared_creds.lock().unwrap();
+            creds.status_sinks.retain(|sink| sink.credential != credential);
+            creds.status_sinks.push(StatusSink { credential, events, field_id: self.field_offset, language: self.language });
+        }
+
+        // 被包装的凭据把事件发给自己，由自己换成包装后的凭据再转发
+        let events: ICredentialProviderCredentialEvents = self.to_interface();
+        unsafe { self.inner.Advise(&events) }
+    }
+
+    fn UnAdvise(&self) -> windows_core::Result<()> {
+        info!("WrappedCredential::UnAdvise - 取消事件通知");
+        *self.events.lock().unwrap() = None;
+        let credential = self.credential();
+        self.shared_creds.lock().unwrap().status_sinks.retain(|sink| sink.credential != credential);
+        unsafe { self.inner.UnAdvise() }
+    }
+
+    fn SetSelected(&self) -> windows_core::Result<BOOL> {
+        unsafe { self.inner.SetSelected() }
+    }
+
+    fn SetDeselected(&self) -> windows_core::Result<()> {
+        unsafe { self.inner.SetDeselected() }
+    }
+
+    fn GetFieldState(
+        &self,
+        dwfieldid: u32,
+        pcpfs: *mut CREDENTIAL_PROVIDER_FIELD_STATE,
+        pcpfis: *mut CREDENTIAL_PROVIDER_FIELD_INTERACTIVE_STATE,
+    ) -> windows_core::Result<()> {
+        match self.face_field(dwfieldid) {
+            Some(STATUS_FIELD_ID | RETRY_FIELD_ID) => {
+                unsafe {
+                    *pcpfs = if self.user.is_some() { CPFS_DISPLAY_IN_SELECTED_TILE } else { CPFS_HIDDEN };
+                    *pcpfis = CPFIS_NONE;
+                }
+                Ok(())
+            }
+            Some(_) => Err(E_INVALIDARG.into()),
+            None => unsafe { self.inner.GetFieldState(dwfieldid, pcpfs, pcpfis) },
+        }
+    }
+
+    fn GetStringValue(&self, dwfieldid: u32) -> windows_core::Result<PWSTR> {
+        match self.face_field(dwfieldid) {
+            Some(STATUS_FIELD_ID) => {
+                let status = self.shared_creds.lock().unwrap().status.clone();
+                co_task_string(&status.map(|status| status.render(self.language)).unwrap_or_default())
+            }
+            Some(RETRY_FIELD_ID) => co_task_string(&Text::Retry.localize(self.language)),
+            Some(_) => Err(E_INVALIDARG.into()),
+            None => unsafe { self.inner.GetStringValue(dwfieldid) },
+        }
+    }
+
+    fn GetBitmapValue(&self, dwfieldid: u32) -> windows_core::Result<HBITMAP> {
+        match self.face_field(dwfieldid) {
+            Some(_) => Err(E_INVALIDARG.into()),
+            None => unsafe { self.inner.GetBitmapValue(dwfieldid) },
+        }
+    }
+
+    fn GetCheckboxValue(&self, dwfieldid: u32, pbchecked: *mut BOOL, ppszlabel: *mut PWSTR) -> windows_core::Result<()> {
+        match self.face_field(dwfieldid) {
+            Some(_) => Err(E_INVALIDARG.into()),
+            None => unsafe { self.inner.GetCheckboxValue(dwfieldid, pbchecked, ppszlabel) },
+        }
+    }
+
+    fn GetSubmitButtonValue(&self, dwfieldid: u32) -> windows_core::Result<u32> {
+        match self.face_field(dwfieldid) {
+            Some(_) => Err(E_INVALIDARG.into()),
+            None => unsafe { self.inner.GetSubmitButtonValue(dwfieldid) },
+        }
+    }
+
+    fn GetComboBoxValueCount(&self, dwfieldid: u32, pcitems: *mut u32, pdwselecteditem: *mut u32) -> windows_core::Result<()> {
+        match self.face_field(dwfieldid) {
+            Some(_) => Err(E_INVALIDARG.into()),
+            None => unsafe { self.inner.GetComboBoxValueCount(dwfieldid, pcitems, pdwselecteditem) },
+        }
+    }
+
+    fn GetComboBoxValueAt(&self, dwfieldid: u32, dwitem: u32) -> windows_core::Result<PWSTR> {
+        match self.face_field(dwfieldid) {
+            Some(_) => Err(E_INVALIDARG.into()),
+            None => unsafe { self.inner.GetComboBoxValueAt(dwfieldid, dwitem) },
+        }
+    }
+
+    fn SetStringValue(&self, dwfieldid: u32, psz: &PCWSTR) -> windows_core::Result<()> {
+        match self.face_field(dwfieldid) {
+            Some(_) => Err(E_INVALIDARG.into()),
+            None => unsafe { self.inner.SetStringValue(dwfieldid, *psz) },
+        }
+    }
+
+    fn SetCheckboxValue(&self, dwfieldid: u32, bchecked: BOOL) -> windows_core::Result<()> {
+        match self.face_field(dwfieldid) {
+            Some(_) => Err(E_INVALIDARG.into()),
+            None => unsafe { self.inner.SetCheckboxValue(dwfieldid, bchecked.as_bool()) },
+        }
+    }
+
+    fn SetComboBoxSelectedValue(&self, dwfieldid: u32, dwselecteditem: u32) -> windows_core::Result<()> {
+        match self.face_field(dwfieldid) {
+            Some(_) => Err(E_INVALIDARG.into()),
+            None => unsafe { self.inner.SetComboBoxSelectedValue(dwfieldid, dwselecteditem) },
+        }
+    }
+
+    fn CommandLinkClicked(&self, dwfieldid: u32) -> windows_core::Result<()> {
+        match self.face_field(dwfieldid) {
+            Some(RETRY_FIELD_ID) => {
+                info!("WrappedCredential::CommandLinkClicked - 请求重新识别");
+                if self.target.usage_scenario == CPUS_CREDUI {
+                    trigger_verify();
+                } else {
+                    trigger_retry();
+                }
+                Ok(())
+            }
+            Some(_) => Err(E_INVALIDARG.into()),
+            None => unsafe { self.inner.CommandLinkClicked(dwfieldid) },
+        }
+    }
+
+    /// 面容匹配成功时序列化面容凭据，否则交给被包装的密码凭据（用户输入的密码）
+    fn GetSerialization(
+        &self,
+        pcpgsr: *mut CREDENTIAL_PROVIDER_GET_SERIALIZATION_RESPONSE,
+        pcpcs: *mut CREDENTIAL_PROVIDER_CREDENTIAL_SERIALIZATION,
+        ppszoptionalstatustext: *mut PWSTR,
+        pcpsioptionalstatusicon: *mut CREDENTIAL_PROVIDER_STATUS_ICON,
+    ) -> windows_core::Result<()> {
+        let face = self.owns_face_credentials();
+        info!("WrappedCredential::GetSerialization - 使用{}", if face { "面容凭据" } else { "密码凭据" });
+        self.face_serialized.store(face, Ordering::SeqCst);
+        if face {
+            unsafe { serialize_face_credentials(&self.shared_creds, &self.target, pcpgsr, pcpcs) }
+        } else {
+            unsafe { self.inner.GetSerialization(pcpgsr, pcpcs, ppszoptionalstatustext, pcpsioptionalstatusicon) }
+        }
+    }
+
+    fn ReportResult(
+        &self,
+        ntsstatus: NTSTATUS,
+        ntssubstatus: NTSTATUS,
+        ppszoptionalstatustext: *mut PWSTR,
+        pcpsioptionalstatusicon: *mut CREDENTIAL_PROVIDER_STATUS_ICON,
+    ) -> windows_core::Result<()> {
+        info!("WrappedCredential::ReportResult - 登录结果: 0x{:08X}", ntsstatus.0 as u32);
+        if self.face_serialized.swap(false, Ordering::SeqCst) {
+            unsafe { report_face_result(&self.shared_creds, ntsstatus, true, self.language, ppszoptionalstatustext, pcpsioptionalstatusicon) };
+            Ok(())
+        } else {
+            // 手动输入的密码登录成功，恢复该账户的面容自动提交
+            if ntsstatus == STATUS_SUCCESS {
+                if let Some(user) = &self.user {
+                    failure_budget::reset(&user.qualified_name);
+                }
+            }
+            unsafe { self.inner.ReportResult(ntsstatus, ntssubstatus, ppszoptionalstatustext, pcpsioptionalstatusicon) }
+        }
+    }
+}
+
+impl ICredentialProviderCredential2_Impl for WrappedCredential_Impl {
+    /// 被包装的密码凭据本身就属于某个用户
+    fn GetUserSid(&self) -> windows_core::Result<PWSTR> {
+        unsafe { self.inner.cast::<ICredentialProviderCredential2>()?.GetUserSid() }
+    }
+}
+
+/// 被包装凭据发出的事件，换成包装后的凭据转发给登录界面
+impl ICredentialProviderCredentialEvents_Impl for WrappedCredential_Impl {
+    fn SetFieldState(&self, _pcpc: Ref<ICredentialProviderCredential>, dwfieldid: u32, cpfs: CREDENTIAL_PROVIDER_FIELD_STATE) -> windows_core::Result<()> {
+        unsafe { self.ui_events()?.SetFieldState(&self.credential(), dwfieldid, cpfs) }
+    }
+
+    fn SetFieldInteractiveState(
+        &self,
+        _pcpc: Ref<ICredentialProviderCredential>,
+        dwfieldid: u32,
+        cpfis: CREDENTIAL_PROVIDER_FIELD_INTERACTIVE_STATE,
+    ) -> windows_core::Result<()> {
+        unsafe { self.ui_events()?.SetFieldInteractiveState(&self.credential(), dwfieldid, cpfis) }
+    }
+
+    fn SetFieldString(&self, _pcpc: Ref<ICredentialProviderCredential>, dwfieldid: u32, psz: &PCWSTR) -> windows_core::Result<()> {
+        unsafe { self.ui_events()?.SetFieldString(&self.credential(), dwfieldid, *psz) }
+    }
+
+    fn SetFieldCheckbox(&self, _pcpc: Ref<ICredentialProviderCredential>, dwfieldid: u32, bchecked: BOOL, pszlabel: &PCWSTR) -> windows_core::Result<()> {
+        unsafe { self.ui_events()?.SetFieldCheckbox(&self.credential(), dwfieldid, bchecked.as_bool(), *pszlabel) }
+    }
+
+    fn SetFieldBitmap(&self, _pcpc: Ref<ICredentialProviderCredential>, dwfieldid: u32, hbmp: HBITMAP) -> windows_core::Result<()> {
+        unsafe { self.ui_events()?.SetFieldBitmap(&self.credential(), dwfieldid, hbmp) }
+    }
+
+    fn SetFieldComboBoxSelectedItem(&self, _pcpc: Ref<ICredentialProviderCredential>, dwfieldid: u32, dwselecteditem: u32) -> windows_core::Result<()> {
+        unsafe { self.ui_events()?.SetFieldComboBoxSelectedItem(&self.credential(), dwfieldid, dwselecteditem) }
+    }
+
+    fn DeleteFieldComboBoxItem(&self, _pcpc: Ref<ICredentialProviderCredential>, dwfieldid: u32, dwitem: u32) -> windows_core::Result<()> {
+        unsafe { self.ui_events()?.DeleteFieldComboBoxItem(&self.credential(), dwfieldid, dwitem) }
+    }
+
+    fn AppendFieldComboBoxItem(&self, _pcpc: Ref<ICredentialProviderCredential>, dwfieldid: u32, pszitem: &PCWSTR) -> windows_core::Result<()> {
+        unsafe { self.ui_events()?.AppendFieldComboBoxItem(&self.credential(), dwfieldid, *pszitem) }
+    }
+
+    fn SetFieldSubmitButton(&self, _pcpc: Ref<ICredentialProviderCredential>, dwfieldid: u32, dwadjacentto: u32) -> windows_core::Result<()> {
+        unsafe { self.ui_events()?.SetFieldSubmitButton(&self.credential(), dwfieldid, dwadjacentto) }
+    }
+
+    fn OnCreatingWindow(&self) -> windows_core::Result<HWND> {
+        unsafe { self.ui_events()?.OnCreatingWindow() }
+    }
+}
@@ -0,0 +1,268 @@
+//! 磁贴图片的解码和缩放
+//!
+//! 不调用 GDI，只处理内存中的像素，HBITMAP 的创建在 CSampleCredential 中完成。
+//! UI 在部署和同步已录入面容的账户时把图片转成未压缩的 BMP（24 或 32 位），DLL 只需要解析这一种格式。
+
+/// 解码后的图片，像素按 BGRA 排列，从上到下逐行存放（与自上而下的 32 位 DIB 一致）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+// BITMAPFILEHEADER 的长度
+const FILE_HEADER_SIZE: usize = 14;
+// BITMAPINFOHEADER 的长度，更新的 V4、V5 头以它为前缀
+const INFO_HEADER_SIZE: usize = 40;
+// biCompression
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+// 图片宽高上限，避免损坏的文件导致分配过大的内存
+const MAX_DIMENSION: u32 = 4096;
+
+fn read_u16(bytes: &[u8], at: usize) -> Result<u16, String> {
+    bytes.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or_else(|| String::from("文件长度不足"))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<u32, String> {
+    bytes.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or_else(|| String::from("文件长度不足"))
+}
+
+/// 解析未压缩的 24 位、32 位 BMP 文件
+///
+/// 24 位图片的 Alpha 通道填充为不透明；32 位图片按 BGRA 读取，Alpha 全为 0 时同样视为不透明（画图等工具保存的文件）
+pub fn decode_bmp(bytes: &[u8]) -> Result<Image, String> {
+    if bytes.get(0..2) != Some(b"BM") {
+        return Err(String::from("不是 BMP 文件"));
+    }
+    let data_offset = read_u32(bytes, 10)? as usize;
+    let header_size = read_u32(bytes, FILE_HEADER_SIZE)? as usize;
+    if header_size < INFO_HEADER_SIZE {
+        return Err(format!("不支持的 BMP 信息头：{} 字节", header_size));
+    }
+
+    let width = read_u32(bytes, FILE_HEADER_SIZE + 4)? as i32;
+    let height = read_u32(bytes, FILE_HEADER_SIZE + 8)? as i32;
+    let bit_count = read_u16(bytes, FILE_HEADER_SIZE + 14)?;
+    let compression = read_u32(bytes, FILE_HEADER_SIZE + 16)?;
+
+    // 高度为负表示自上而下存放
+    let top_down = height < 0;
+    let (width, height) = (width.unsigned_abs(), height.unsigned_abs());
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(format!("图片尺寸无效：{}x{}", width, height));
+    }
+    let bytes_per_pixel = match (bit_count, compression) {
+        (24, BI_RGB) => 3,
+        (32, BI_RGB | BI_BITFIELDS) => 4,
+        _ => return Err(format!("不支持的 BMP 格式：{} 位，压缩方式 {}", bit_count, compression)),
+    };
+
+    // 每行按 4 字节对齐
+    let stride = (width as usize * bytes_per_pixel).div_ceil(4) * 4;
+    // 偏移量来自文件，损坏时可能溢出
+    let data = data_offset
+        .checked_add(stride * height as usize)
+        .and_then(|end| bytes.get(data_offset..end))
+        .ok_or_else(|| String::from("像素数据不完整"))?;
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height as usize {
+        let row = if top_down { y } else { height as usize - 1 - y };
+        let line = &data[row * stride..row * stride + width as usize * bytes_per_pixel];
+        for pixel in line.chunks_exact(bytes_per_pixel) {
+            let alpha = if bytes_per_pixel == 4 { pixel[3] } else { 0xFF };
+            pixels.extend_from_slice(&[pixel[0], pixel[1], pixel[2], alpha]);
+        }
+    }
+    if bytes_per_pixel == 4 && pixels.chunks_exact(4).all(|pixel| pixel[3] == 0) {
+        pixels.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 0xFF);
+    }
+
+    Ok(Image { width, height, pixels })
+}
+
+impl Image {
+    /// 从中间裁出最大的正方形，头像和图标都按正方形显示
+    pub fn crop_square(&self) -> Image {
+        let side = self.width.min(self.height);
+        let left = (self.width - side) / 2;
+        let top = (self.height - side) / 2;
+        let mut pixels = Vec::with_capacity(side as usize * side as usize * 4);
+        for y in top..top + side {
+            let start = (y as usize * self.width as usize + left as usize) * 4;
+            pixels.extend_from_slice(&self.pixels[start..start + side as usize * 4]);
+        }
+        Image { width: side, height: side, pixels }
+    }
+
+    /// 双线性插值缩放到指定尺寸
+    pub fn resize(&self, width: u32, height: u32) -> Image {
+        if width == self.width && height == self.height {
+            return self.clone();
+        }
+        let width = width.max(1);
+        let height = height.max(1);
+        let at = |x: usize, y: usize, channel: usize| self.pixels[(y * self.width as usize + x) * 4 + channel] as f32;
+
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height {
+            // 按像素中心对齐采样
+            let src_y = ((y as f32 + 0.5) * self.height as f32 / height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+            let (y0, fy) = (src_y.floor() as usize, src_y.fract());
+            let y1 = (y0 + 1).min(self.height as usize - 1);
+            for x in 0..width {
+                let src_x = ((x as f32 + 0.5) * self.width as f32 / width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+                let (x0, fx) = (src_x.floor() as usize, src_x.fract());
+                let x1 = (x0 + 1).min(self.width as usize - 1);
+                for channel in 0..4 {
+                    let top = at(x0, y0, channel) * (1.0 - fx) + at(x1, y0, channel) * fx;
+                    let bottom = at(x0, y1, channel) * (1.0 - fx) + at(x1, y1, channel) * fx;
+                    pixels.push((top * (1.0 - fy) + bottom * fy).round() as u8);
+                }
+            }
+        }
+        Image { width, height, pixels }
+    }
+
+    /// 转成 AlphaBlend 要求的预乘 Alpha
+    pub fn premultiplied(mut self) -> Image {
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u32;
+            for channel in &mut pixel[..3] {
+                *channel = ((*channel as u32 * alpha + 127) / 255) as u8;
+            }
+        }
+        self
+    }
+}
+
+/// 读取 BMP 文件并裁成正方形、缩放到磁贴需要的尺寸
+pub fn load_tile_image(bytes: &[u8], size: u32) -> Result<Image, String> {
+    Ok(decode_bmp(bytes)?.crop_square().resize(size, size).premultiplied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 按给定的像素（BGRA，从上到下）生成 BMP 文件，`top_down` 决定高度的符号和行的存放顺序
+    fn bmp(rows: &[Vec<[u8; 4]>], bit_count: u16, top_down: bool) -> Vec<u8> {
+        let width = rows[0].len();
+        let height = rows.len();
+        let bytes_per_pixel = bit_count as usize / 8;
+        let stride = (width * bytes_per_pixel).div_ceil(4) * 4;
+        let data_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"BM");
+        bytes.extend_from_slice(&((data_offset + stride * height) as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&(data_offset as u32).to_le_bytes());
+
+        let signed_height = if top_down { -(height as i32) } else { height as i32 };
+        bytes.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+        bytes.extend_from_slice(&(width as i32).to_le_bytes());
+        bytes.extend_from_slice(&signed_height.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&bit_count.to_le_bytes());
+        bytes.extend_from_slice(&BI_RGB.to_le_bytes());
+        bytes.extend_from_slice(&[0; 20]);
+
+        let mut ordered: Vec<&Vec<[u8; 4]>> = rows.iter().collect();
+        if !top_down {
+            ordered.reverse();
+        }
+        for row in ordered {
+            let start = bytes.len();
+            for pixel in row {
+                bytes.extend_from_slice(&pixel[..bytes_per_pixel]);
+            }
+            bytes.resize(start + stride, 0);
+        }
+        bytes
+    }
+
+    // 2x3，每个像素都不同，24 位时每行需要补齐
+    fn sample() -> Vec<Vec<[u8; 4]>> {
+        vec![
+            vec![[1, 2, 3, 40], [4, 5, 6, 50]],
+            vec![[7, 8, 9, 60], [10, 11, 12, 70]],
+            vec![[13, 14, 15, 80], [16, 17, 18, 90]],
+        ]
+    }
+
+    #[test]
+    fn decodes_24_bit_both_orders() {
+        let expected: Vec<u8> = sample().concat().iter().flat_map(|p| [p[0], p[1], p[2], 0xFF]).collect();
+        for top_down in [false, true] {
+            let image = decode_bmp(&bmp(&sample(), 24, top_down)).unwrap();
+            assert_eq!((image.width, image.height), (2, 3));
+            assert_eq!(image.pixels, expected, "top_down = {}", top_down);
+        }
+    }
+
+    #[test]
+    fn decodes_32_bit_both_orders() {
+        let expected: Vec<u8> = sample().concat().concat();
+        for top_down in [false, true] {
+            let image = decode_bmp(&bmp(&sample(), 32, top_down)).unwrap();
+            assert_eq!((image.width, image.height), (2, 3));
+            assert_eq!(image.pixels, expected, "top_down = {}", top_down);
+        }
+    }
+
+    #[test]
+    fn zero_alpha_32_bit_is_opaque() {
+        let rows = vec![vec![[1, 2, 3, 0], [4, 5, 6, 0]]];
+        let image = decode_bmp(&bmp(&rows, 32, false)).unwrap();
+        assert_eq!(image.pixels, vec![1, 2, 3, 0xFF, 4, 5, 6, 0xFF]);
+    }
+
+    #[test]
+    fn tile_image_is_square_192() {
+        let rows = vec![vec![[10, 20, 30, 0xFF]; 300]; 200];
+        let image = load_tile_image(&bmp(&rows, 24, false), 192).unwrap();
+        assert_eq!((image.width, image.height), (192, 192));
+        assert_eq!(image.pixels.len(), 192 * 192 * 4);
+        assert!(image.pixels.chunks_exact(4).all(|pixel| pixel == [10, 20, 30, 0xFF]));
+    }
+
+    #[test]
+    fn corrupt_files_are_rejected() {
+        let valid = bmp(&sample(), 24, false);
+        let set_u32 = |at: usize, value: u32| {
+            let mut bytes = valid.clone();
+            bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+            bytes
+        };
+
+        // 截断在文件头、信息头、像素数据的各个位置
+        for len in [0, 1, 2, 10, FILE_HEADER_SIZE, FILE_HEADER_SIZE + 10, FILE_HEADER_SIZE + INFO_HEADER_SIZE, valid.len() - 1] {
+            assert!(decode_bmp(&valid[..len]).is_err(), "截断到 {} 字节", len);
+        }
+
+        let mut not_bmp = valid.clone();
+        not_bmp[0] = b'X';
+        let mut rle = valid.clone();
+        rle[FILE_HEADER_SIZE + 16] = 1;
+        let mut eight_bit = valid.clone();
+        eight_bit[FILE_HEADER_SIZE + 14] = 8;
+
+        for bytes in [
+            not_bmp,
+            rle,
+            eight_bit,
+            set_u32(10, u32::MAX),
+            set_u32(FILE_HEADER_SIZE, 12),
+            set_u32(FILE_HEADER_SIZE + 4, 0),
+            set_u32(FILE_HEADER_SIZE + 4, MAX_DIMENSION + 1),
+            set_u32(FILE_HEADER_SIZE + 8, i32::MIN as u32),
+            set_u32(FILE_HEADER_SIZE + 8, 4096),
+        ] {
+            assert!(decode_bmp(&bytes).is_err());
+            assert!(load_tile_image(&bytes, 192).is_err());
+        }
+    }
+}
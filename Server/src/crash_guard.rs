@@ -0,0 +1,60 @@
+// 崩溃保护：DLL 运行在 winlogon 的登录界面中，反复崩溃或卡死会导致任何人都无法登录
+// 登录界面开始使用本提供程序时写入运行标记，正常 UnAdvise 时清除；下次启动时标记还在，说明上次异常退出
+// 连续异常退出达到上限，或注册表 DISABLED 为 "1" 时，提供程序不显示磁贴、不启动监听线程，退化为什么都不做
+use protocol::CRASH_GUARD_REG_PATH;
+use crate::{delete_registry_value, read_facewinunlock_registry, read_registry_string, write_registry_string};
+
+// 连续异常退出多少次后停用
+const MAX_UNCLEAN_EXITS: u32 = 3;
+// 运行标记，值为写入标记的进程 ID
+const RUNNING_VALUE: &str = "RUNNING";
+// 连续异常退出的次数
+const UNCLEAN_EXITS_VALUE: &str = "UNCLEAN_EXITS";
+
+/// 连续异常退出的次数
+pub fn unclean_exits() -> u32 {
+    read_registry_string(CRASH_GUARD_REG_PATH, UNCLEAN_EXITS_VALUE)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0)
+}
+
+/// 是否停用面容解锁：手动打开了 DISABLED 开关，或连续异常退出次数达到上限
+pub fn is_disabled() -> bool {
+    if read_facewinunlock_registry("DISABLED").is_ok_and(|value| value.trim() == "1") {
+        info!("注册表 DISABLED 为 1，面容解锁已停用");
+        return true;
+    }
+    let exits = unclean_exits();
+    if exits >= MAX_UNCLEAN_EXITS {
+        warn!("登录界面连续 {} 次异常退出，面容解锁已停用，在设置中保存 DLL 配置后恢复", exits);
+        return true;
+    }
+    false
+}
+
+/// 登录界面开始使用本提供程序，上一次留下的运行标记不属于当前进程时记为一次异常退出
+pub fn session_started() {
+    let pid = std::process::id().to_string();
+    if let Ok(marker) = read_registry_string(CRASH_GUARD_REG_PATH, RUNNING_VALUE)
+        && marker != pid
+    {
+        let exits = unclean_exits() + 1;
+        warn!("上次登录界面（进程 {}）没有正常退出，连续异常退出 {} 次", marker, exits);
+        if let Err(e) = write_registry_string(CRASH_GUARD_REG_PATH, UNCLEAN_EXITS_VALUE, &exits.to_string()) {
+            error!("记录异常退出次数失败：{:?}", e);
+        }
+    }
+    if let Err(e) = write_registry_string(CRASH_GUARD_REG_PATH, RUNNING_VALUE, &pid) {
+        error!("写入运行标记失败：{:?}", e);
+    }
+}
+
+/// 登录界面正常结束，清除运行标记和异常退出次数
+pub fn session_ended() {
+    for value in [RUNNING_VALUE, UNCLEAN_EXITS_VALUE] {
+        if let Err(e) = delete_registry_value(CRASH_GUARD_REG_PATH, value) {
+            error!("清除崩溃保护记录 {} 失败：{:?}", value, e);
+        }
+    }
+}
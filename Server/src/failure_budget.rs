@@ -0,0 +1,88 @@
+// 按账户记录面容凭据的 LSA 连续登录失败次数，避免保存的密码错误时反复提交导致 Windows 账户被锁定
+// 次数写在注册表中（LSA_FAILURE_REG_PATH），winlogon 重启后仍然有效；Unlock 服务在用户手动登录成功后清空
+// 按实际提交给 LSA 的“域名\用户名”计数，不同域下的同名账户互不影响
+use protocol::LSA_FAILURE_REG_PATH;
+use crate::{account_name, delete_registry_value, kerb::split_account, read_facewinunlock_registry, read_registry_string, write_registry_string, CSampleCredential::computer_name, CSampleProvider::read_enrolled_users};
+
+// 未配置时允许的连续失败次数，Windows 默认的账户锁定阈值是 10 次，留出手动输入密码的余量
+const DEFAULT_BUDGET: u32 = 3;
+
+/// 允许的连续失败次数，由 UI 写入注册表 LSA_FAILURE_BUDGET，0 表示不限制
+pub fn read_budget() -> u32 {
+    match read_facewinunlock_registry("LSA_FAILURE_BUDGET") {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            warn!("LSA_FAILURE_BUDGET 不是数字：{}，使用默认值 {}", value, DEFAULT_BUDGET);
+            DEFAULT_BUDGET
+        }),
+        Err(_) => DEFAULT_BUDGET,
+    }
+}
+
+// 注册表值名：与打包凭据时一样拆出域名（本地账户为计算机名），小写
+fn budget_key(user_name: &str, computer_name: &str) -> String {
+    if user_name.is_empty() {
+        return String::new();
+    }
+    split_account(user_name, computer_name).qualified_name().to_lowercase()
+}
+
+/// 账户已记录的连续失败次数
+pub fn failures(user_name: &str) -> u32 {
+    read_registry_string(LSA_FAILURE_REG_PATH, &budget_key(user_name, &computer_name()))
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0)
+}
+
+/// 账户的失败次数是否已达到上限，达到后不再自动提交它的面容凭据
+pub fn is_exhausted(user_name: &str) -> bool {
+    let budget = read_budget();
+    budget != 0 && failures(user_name) >= budget
+}
+
+/// 记录一次面容凭据的登录失败，返回记录后的次数
+/// 只记录已录入面容的账户，其他账户的凭据不计入
+pub fn record_failure(user_name: &str) -> Option<u32> {
+    let account = budget_key(user_name, &computer_name());
+    if account.is_empty() || !read_enrolled_users().iter().any(|name| account_name(name) == account_name(user_name)) {
+        return None;
+    }
+
+    let count = failures(user_name) + 1;
+    if let Err(e) = write_registry_string(LSA_FAILURE_REG_PATH, &account, &count.to_string()) {
+        error!("记录账户 {} 的登录失败次数失败：{:?}", account, e);
+    }
+    warn!("账户 {} 连续登录失败 {} 次", account, count);
+    Some(count)
+}
+
+/// 登录成功后清空账户的失败次数
+pub fn reset(user_name: &str) {
+    let account = budget_key(user_name, &computer_name());
+    if account.is_empty() || failures(user_name) == 0 {
+        return;
+    }
+    info!("账户 {} 登录成功，清空失败次数", account);
+    if let Err(e) = delete_registry_value(LSA_FAILURE_REG_PATH, &account) {
+        error!("清空账户 {} 的登录失败次数失败：{:?}", account, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_key_separates_domains() {
+        // 本地账户的各种写法落在同一个值上
+        assert_eq!(budget_key(r".\Bob", "PC"), r"pc\bob");
+        assert_eq!(budget_key("bob", "PC"), r"pc\bob");
+        assert_eq!(budget_key(r"PC\bob", "PC"), r"pc\bob");
+
+        // 域账户、云账户与本地同名账户分开计数
+        assert_eq!(budget_key(r"CORP\bob", "PC"), r"corp\bob");
+        assert_eq!(budget_key(r"microsoftaccount\Bob@outlook.com", "PC"), r"microsoftaccount\bob@outlook.com");
+        assert_eq!(budget_key("bob@contoso.com", "PC"), "bob@contoso.com");
+        assert_eq!(budget_key("", "PC"), "");
+    }
+}
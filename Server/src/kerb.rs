@@ -0,0 +1,245 @@
+//! KERB_INTERACTIVE_UNLOCK_LOGON 序列化
+//!
+//! 不调用 Win32 API，按 ntsecapi.h 中的结构布局逐字节拼出 GetSerialization 交给 LSA 的缓冲区：
+//! 结构体后面依次是域名、用户名、密码（UTF-16LE，不带结尾的 0），
+//! 各 UNICODE_STRING 的 Buffer 字段保存的是相对缓冲区开头的偏移，而不是指针。
+
+/// KERB_LOGON_SUBMIT_TYPE，决定 LSA 把这次登录当作新登录还是解锁
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum KerbLogonSubmitType {
+    /// 登录、CredUI
+    InteractiveLogon = 2,
+    /// 解锁工作站
+    WorkstationUnlockLogon = 7,
+}
+
+/// 调用方的指针宽度，WOW64 下的 32 位 CredUI 调用方需要 32 位布局
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerWidth {
+    Bits32,
+    Bits64,
+}
+
+impl PointerWidth {
+    /// 当前进程的指针宽度
+    pub fn native() -> Self {
+        if cfg!(target_pointer_width = "64") { PointerWidth::Bits64 } else { PointerWidth::Bits32 }
+    }
+
+    fn bytes(self) -> usize {
+        match self {
+            PointerWidth::Bits32 => 4,
+            PointerWidth::Bits64 => 8,
+        }
+    }
+}
+
+/// 拆分后交给 LSA 的域名和用户名
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogonAccount {
+    pub domain: String,
+    pub user_name: String,
+}
+
+impl LogonAccount {
+    /// `域名\用户名`，UPN 没有域名时只有用户名
+    pub fn qualified_name(&self) -> String {
+        if self.domain.is_empty() { self.user_name.clone() } else { format!("{}\\{}", self.domain, self.user_name) }
+    }
+}
+
+// 云账户的固定前缀，统一成系统使用的大小写
+const CLOUD_DOMAINS: &[&str] = &["MicrosoftAccount", "AzureAD"];
+
+/// 把 Unlock 服务发来的账户名拆成域名和用户名
+///
+/// - `.\user`、`user`：本地账户，域名为计算机名
+/// - `DOMAIN\user`：域账户
+/// - `MicrosoftAccount\user@outlook.com`、`AzureAD\user@contoso.com`：前缀作为域名，LSA 据此交给对应的云认证
+/// - `user@contoso.com`（UPN）：域名留空，由 LSA 解析
+pub fn split_account(name: &str, computer_name: &str) -> LogonAccount {
+    let local = |user_name: &str| LogonAccount { domain: computer_name.to_string(), user_name: user_name.to_string() };
+    match name.split_once('\\') {
+        Some((".", user_name)) | Some(("", user_name)) => local(user_name),
+        Some((domain, user_name)) => {
+            let domain = CLOUD_DOMAINS
+                .iter()
+                .find(|cloud| cloud.eq_ignore_ascii_case(domain))
+                .map_or(domain, |cloud| *cloud);
+            LogonAccount { domain: domain.to_string(), user_name: user_name.to_string() }
+        }
+        None if name.contains('@') => LogonAccount { domain: String::new(), user_name: name.to_string() },
+        None => local(name),
+    }
+}
+
+/// 打包 KERB_INTERACTIVE_UNLOCK_LOGON，LogonId 为 0
+///
+/// 任意字符串超过 UNICODE_STRING 能表示的长度（32767 个字符）时返回错误
+pub fn pack_unlock_logon(
+    message_type: KerbLogonSubmitType,
+    account: &LogonAccount,
+    password: &str,
+    width: PointerWidth,
+) -> Result<Vec<u8>, String> {
+    let ptr = width.bytes();
+    // UNICODE_STRING：Length、MaximumLength，对齐到指针宽度后是 Buffer
+    let unicode_string_size = ptr + ptr;
+    // MessageType 之后对齐到指针宽度
+    let strings_offset = ptr;
+    // 三个 UNICODE_STRING 之后是 LUID LogonId
+    let logon_id_offset = strings_offset + unicode_string_size * 3;
+    let header_size = logon_id_offset + 8;
+
+    let fields = [account.domain.as_str(), account.user_name.as_str(), password];
+    let encoded: Vec<Vec<u8>> = fields
+        .iter()
+        .map(|value| value.encode_utf16().flat_map(u16::to_le_bytes).collect())
+        .collect();
+
+    let mut buffer = vec![0u8; header_size];
+    buffer[0..4].copy_from_slice(&(message_type as u32).to_le_bytes());
+
+    let mut data_offset = header_size;
+    for (index, bytes) in encoded.iter().enumerate() {
+        let length = u16::try_from(bytes.len()).map_err(|_| format!("第 {} 个字段过长：{} 字节", index + 1, bytes.len()))?;
+        let at = strings_offset + unicode_string_size * index;
+        buffer[at..at + 2].copy_from_slice(&length.to_le_bytes());
+        buffer[at + 2..at + 4].copy_from_slice(&length.to_le_bytes());
+        // 空字符串的 Buffer 也指向数据区，和系统打包的结果保持一致
+        let offset = &(data_offset as u64).to_le_bytes()[..ptr];
+        buffer[at + ptr..at + ptr + ptr].copy_from_slice(offset);
+        data_offset += bytes.len();
+    }
+
+    for bytes in encoded {
+        buffer.extend_from_slice(&bytes);
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(domain: &str, user_name: &str) -> LogonAccount {
+        LogonAccount { domain: domain.to_string(), user_name: user_name.to_string() }
+    }
+
+    fn utf16(value: &str) -> Vec<u8> {
+        value.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn u16_at(buffer: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([buffer[at], buffer[at + 1]])
+    }
+
+    fn u32_at(buffer: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(buffer[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64_at(buffer: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(buffer[at..at + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn layout_64() {
+        let buffer = pack_unlock_logon(
+            KerbLogonSubmitType::WorkstationUnlockLogon,
+            &account("PC", "bob"),
+            "pw",
+            PointerWidth::Bits64,
+        )
+        .unwrap();
+
+        // MessageType + 填充 8 字节，三个 16 字节的 UNICODE_STRING，LogonId 8 字节
+        assert_eq!(buffer.len(), 64 + (2 + 3 + 2) * 2);
+        assert_eq!(u32_at(&buffer, 0), 7);
+        assert_eq!(u32_at(&buffer, 4), 0);
+        assert_eq!(u64_at(&buffer, 56), 0);
+
+        let expected = [(8, 4, 64), (24, 6, 68), (40, 4, 74)];
+        for (at, length, offset) in expected {
+            assert_eq!(u16_at(&buffer, at), length);
+            assert_eq!(u16_at(&buffer, at + 2), length);
+            assert_eq!(u32_at(&buffer, at + 4), 0, "Length 后的填充");
+            assert_eq!(u64_at(&buffer, at + 8), offset);
+        }
+
+        assert_eq!(&buffer[64..68], utf16("PC").as_slice());
+        assert_eq!(&buffer[68..74], utf16("bob").as_slice());
+        assert_eq!(&buffer[74..], utf16("pw").as_slice());
+    }
+
+    #[test]
+    fn layout_32() {
+        let buffer =
+            pack_unlock_logon(KerbLogonSubmitType::InteractiveLogon, &account("PC", "bob"), "pw", PointerWidth::Bits32)
+                .unwrap();
+
+        // MessageType 4 字节，三个 8 字节的 UNICODE_STRING，LogonId 8 字节
+        assert_eq!(buffer.len(), 36 + (2 + 3 + 2) * 2);
+        assert_eq!(u32_at(&buffer, 0), 2);
+        assert_eq!(u64_at(&buffer, 28), 0);
+
+        let expected = [(4, 4, 36), (12, 6, 40), (20, 4, 46)];
+        for (at, length, offset) in expected {
+            assert_eq!(u16_at(&buffer, at), length);
+            assert_eq!(u16_at(&buffer, at + 2), length);
+            assert_eq!(u32_at(&buffer, at + 4), offset);
+        }
+
+        assert_eq!(&buffer[36..40], utf16("PC").as_slice());
+        assert_eq!(&buffer[40..46], utf16("bob").as_slice());
+        assert_eq!(&buffer[46..], utf16("pw").as_slice());
+    }
+
+    #[test]
+    fn empty_strings_point_into_data() {
+        let buffer =
+            pack_unlock_logon(KerbLogonSubmitType::InteractiveLogon, &account("", "bob@contoso.com"), "", PointerWidth::Bits64)
+                .unwrap();
+
+        assert_eq!(buffer.len(), 64 + 15 * 2);
+        // 空的域名和密码长度为 0，Buffer 仍然指向它们在数据区中的位置
+        assert_eq!(u16_at(&buffer, 8), 0);
+        assert_eq!(u64_at(&buffer, 16), 64);
+        assert_eq!(u16_at(&buffer, 24), 30);
+        assert_eq!(u64_at(&buffer, 32), 64);
+        assert_eq!(u16_at(&buffer, 40), 0);
+        assert_eq!(u64_at(&buffer, 48), 94);
+    }
+
+    #[test]
+    fn too_long_string_is_rejected() {
+        let max = "a".repeat(32767);
+        assert!(
+            pack_unlock_logon(KerbLogonSubmitType::InteractiveLogon, &account("PC", "bob"), &max, PointerWidth::Bits64)
+                .is_ok()
+        );
+
+        let too_long = "a".repeat(32768);
+        assert!(
+            pack_unlock_logon(KerbLogonSubmitType::InteractiveLogon, &account("PC", "bob"), &too_long, PointerWidth::Bits64)
+                .is_err()
+        );
+        assert!(
+            pack_unlock_logon(KerbLogonSubmitType::InteractiveLogon, &account(&too_long, "bob"), "pw", PointerWidth::Bits32)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn split_account_forms() {
+        assert_eq!(split_account(r".\bob", "PC"), account("PC", "bob"));
+        assert_eq!(split_account("bob", "PC"), account("PC", "bob"));
+        assert_eq!(split_account(r"CORP\bob", "PC"), account("CORP", "bob"));
+        assert_eq!(
+            split_account(r"microsoftaccount\bob@outlook.com", "PC"),
+            account("MicrosoftAccount", "bob@outlook.com")
+        );
+        assert_eq!(split_account(r"AZUREAD\bob@contoso.com", "PC"), account("AzureAD", "bob@contoso.com"));
+        assert_eq!(split_account("bob@contoso.com", "PC"), account("", "bob@contoso.com"));
+    }
+}
@@ -2,8 +2,9 @@
 #[macro_use] extern crate log;
 extern crate simplelog;
 use simplelog::*;
-use windows::Win32::System::Registry::{RegCloseKey, RegOpenKeyExW, RegQueryValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_READ, REG_SZ, REG_VALUE_TYPE};
+use windows::Win32::System::Registry::{RegCloseKey, RegCreateKeyExW, RegDeleteKeyValueW, RegOpenKeyExW, RegQueryValueExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_READ, KEY_WRITE, REG_OPTION_NON_VOLATILE, REG_SZ, REG_VALUE_TYPE};
 use std::fs::File;
+use std::path::PathBuf;
 
 // 引入必要的系统类型和Win32 API绑定
 use std::ffi::{c_void, OsStr};
@@ -11,9 +12,9 @@ use std::os::windows::ffi::OsStrExt;
 use std::sync::atomic::{AtomicI32, Ordering};
 
 // Windows基础类型和COM接口
-use windows::Win32::Foundation::{CLASS_E_CLASSNOTAVAILABLE, CLASS_E_NOAGGREGATION, E_INVALIDARG, HINSTANCE, S_FALSE, S_OK};
+use windows::Win32::Foundation::{CLASS_E_CLASSNOTAVAILABLE, CLASS_E_NOAGGREGATION, ERROR_FILE_NOT_FOUND, E_INVALIDARG, HINSTANCE, S_FALSE, S_OK};
 use windows::Win32::System::SystemServices::DLL_PROCESS_ATTACH;
-use windows::Win32::UI::Shell::ICredentialProvider;
+use windows::Win32::UI::Shell::{ICredentialProvider, ICredentialProviderFilter};
 use windows_core::{implement, Ref, BOOL, GUID, PCWSTR};
 use windows::core::{Interface, HRESULT};
 use windows::Win32::System::Com::{IClassFactory, IClassFactory_Impl};
@@ -22,9 +23,18 @@ use windows::Win32::System::Com::{IClassFactory, IClassFactory_Impl};
 pub mod CSampleProvider;
 pub mod CSampleCredential;
 pub mod CPipeListener;
-pub mod Pipe;
+pub mod CWrappedCredential;
+pub mod CProviderFilter;
+pub mod kerb;
+pub mod bitmap;
+pub mod failure_budget;
+pub mod crash_guard;
+pub mod logon_state;
+pub mod trigger_policy;
+pub mod strings;
 
 use CSampleProvider::SampleProvider;
+use CProviderFilter::SampleFilter;
 
 // 全局引用计数器，用于管理DLL的生命周期
 // 当引用计数为0时，系统可以安全卸载DLL
@@ -43,7 +53,11 @@ pub fn dll_release() {
 
 /// 读取注册表数据
 pub fn read_facewinunlock_registry(key_name: &str) -> windows::core::Result<String> {
-    let reg_path = "SOFTWARE\\facewinunlock-tauri";
+    read_registry_string("SOFTWARE\\facewinunlock-tauri", key_name)
+}
+
+/// 读取HKLM下指定子项中的字符串值
+pub fn read_registry_string(reg_path: &str, key_name: &str) -> windows::core::Result<String> {
     // 打开HKLM下的注册表项
     let mut hkey: HKEY = HKEY::default();
 
@@ -118,22 +132,138 @@ pub fn read_facewinunlock_registry(key_name: &str) -> windows::core::Result<Stri
     Ok(value)
 }
 
+/// 写入HKLM下指定子项中的字符串值，子项不存在时创建
+pub fn write_registry_string(reg_path: &str, key_name: &str, value: &str) -> windows::core::Result<()> {
+    let reg_path: Vec<u16> = reg_path.encode_utf16().chain(std::iter::once(0)).collect();
+    let mut hkey = HKEY::default();
+    let status = unsafe {
+        RegCreateKeyExW(
+            HKEY_LOCAL_MACHINE,
+            PCWSTR::from_raw(reg_path.as_ptr()),
+            None,
+            PCWSTR::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_WRITE,
+            None,
+            &mut hkey,
+            None,
+        )
+    };
+    if status.is_err() {
+        return Err(windows_core::Error::new(HRESULT(0), format!("创建注册表项失败: {}", status.0)));
+    }
+
+    let key_name: Vec<u16> = key_name.encode_utf16().chain(std::iter::once(0)).collect();
+    let data: Vec<u8> = value.encode_utf16().chain(std::iter::once(0)).flat_map(u16::to_le_bytes).collect();
+    let status = unsafe { RegSetValueExW(hkey, PCWSTR::from_raw(key_name.as_ptr()), None, REG_SZ, Some(&data)) };
+    unsafe { let _ = RegCloseKey(hkey); };
+    if status.is_err() {
+        return Err(windows_core::Error::new(HRESULT(0), format!("写入注册表值失败: {}", status.0)));
+    }
+    Ok(())
+}
+
+/// 删除HKLM下指定子项中的值，值或子项不存在时视为成功
+pub fn delete_registry_value(reg_path: &str, key_name: &str) -> windows::core::Result<()> {
+    let reg_path: Vec<u16> = reg_path.encode_utf16().chain(std::iter::once(0)).collect();
+    let key_name: Vec<u16> = key_name.encode_utf16().chain(std::iter::once(0)).collect();
+    let status = unsafe { RegDeleteKeyValueW(HKEY_LOCAL_MACHINE, PCWSTR::from_raw(reg_path.as_ptr()), PCWSTR::from_raw(key_name.as_ptr())) };
+    if status.is_err() && status != ERROR_FILE_NOT_FOUND {
+        return Err(windows_core::Error::new(HRESULT(0), format!("删除注册表值失败: {}", status.0)));
+    }
+    Ok(())
+}
+
 // 定义凭据提供程序的GUID，用于系统识别
 // 8a7b9c6d-4e5f-89a0-8b7c-6d5e4f3e2d1c
 pub const CLSID_SampleProvider: GUID = GUID::from_u128(0x8a7b9c6d_4e5f_89a0_8b7c_6d5e4f3e2d1c);
 
+// 面容解锁在登录界面上的呈现方式，由 UI 写入注册表 PROVIDER_MODE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderMode {
+    // 单独的面容解锁登录选项（默认）
+    Standalone,
+    // 同上，但作为默认选中的登录选项
+    Default,
+    // 包装系统的密码凭据提供程序，面容解锁和密码输入在同一个磁贴中，过滤器隐藏原来的密码提供程序
+    Wrap,
+}
+
+/// 读取呈现方式，未配置或无法识别时为 Standalone
+pub fn read_provider_mode() -> ProviderMode {
+    match read_facewinunlock_registry("PROVIDER_MODE").as_deref() {
+        Ok("default") => ProviderMode::Default,
+        Ok("wrap") => ProviderMode::Wrap,
+        _ => ProviderMode::Standalone,
+    }
+}
+
+// 系统的密码凭据提供程序
+// 60b78e88-ead8-445c-9cfd-0b87f74ea6cd
+pub const CLSID_PasswordCredentialProvider: GUID = GUID::from_u128(0x60b78e88_ead8_445c_9cfd_0b87f74ea6cd);
+
+// 凭据提供程序过滤器的GUID，包装模式下隐藏系统的密码凭据提供程序
+// 8a7b9c6d-4e5f-89a0-8b7c-6d5e4f3e2d1d
+pub const CLSID_SampleFilter: GUID = GUID::from_u128(0x8a7b9c6d_4e5f_89a0_8b7c_6d5e4f3e2d1d);
+
 // 共享的凭据信息
 pub struct SharedCredentials {
-    pub username: String,
-    pub password: String,
-    pub domain: String,
-    pub is_ready: bool,
+    // 管道收到的面容凭据从待提交到收到登录结果的状态，尝试 ID 即请求 ID
+    pub logon: logon_state::LogonState,
+    // 磁贴上显示的识别状态，由 Unlock 服务通过管道更新，每个磁贴按自己的语言显示
+    pub status: Option<strings::TileText>,
+    // 已注册事件通知的凭据，状态变化时通过 SetFieldString 刷新磁贴
+    pub status_sinks: Vec<CSampleCredential::StatusSink>,
 }
 
-/// 类工厂实现，用于创建凭据提供程序实例
+// 登录界面上的一个用户，由 SetUserArray 传入
+#[derive(Clone, Debug)]
+pub struct TileUser {
+    pub sid: String,
+    // 如 DESKTOP-XXXX\user、MicrosoftAccount\user@outlook.com
+    pub qualified_name: String,
+}
+
+impl TileUser {
+    // 是否是指定的账户，只比较反斜杠后的账户名，不区分大小写
+    // 录入面容时本地账户只填用户名，微软账户填邮箱，管道传来的本地账户带 .\ 前缀
+    pub fn matches(&self, user_name: &str) -> bool {
+        !user_name.is_empty() && self.account_name() == account_name(user_name)
+    }
+
+    // 反斜杠后的账户名（小写），UI 按它命名磁贴头像文件
+    pub fn account_name(&self) -> String {
+        account_name(&self.qualified_name)
+    }
+}
+
+/// 反斜杠后的账户名（小写），用来在录入面容的账户、登录界面的用户和管道传来的用户名之间对应
+pub fn account_name(name: &str) -> String {
+    name.rsplit('\\').next().unwrap_or("").to_lowercase()
+}
+
+/// 读取 UI 写入的日志目录，去掉 \\?\ 前缀
+pub fn read_log_dir() -> windows::core::Result<String> {
+    let path = read_facewinunlock_registry("DLL_LOG_PATH")?;
+    Ok(match path.strip_prefix("\\\\?\\") {
+        Some(path) => path.to_string(),
+        None => path,
+    })
+}
+
+/// 软件安装目录（日志目录的上一级），磁贴图标和头像由 UI 放在这里
+pub fn install_dir() -> Option<PathBuf> {
+    let log_dir = PathBuf::from(read_log_dir().ok()?);
+    log_dir.parent().map(|dir| dir.to_path_buf())
+}
+
+/// 类工厂实现，用于创建凭据提供程序或过滤器实例
 /// COM规范要求通过类工厂来实例化组件
 #[implement(IClassFactory)]
-struct SampleClassFactory;
+struct SampleClassFactory {
+    // DllGetClassObject 请求的CLSID，决定创建哪个组件
+    clsid: GUID,
+}
 
 impl IClassFactory_Impl for SampleClassFactory_Impl {
     /// 创建组件实例
@@ -161,10 +291,14 @@ impl IClassFactory_Impl for SampleClassFactory_Impl {
                 return Err(E_INVALIDARG.into());
             }
             
-            // 实例化凭据提供程序并转换为ICredentialProvider接口
-            let provider: ICredentialProvider = SampleProvider::new().into();
-            // 查询请求的接口并返回
-            let result = provider.query(riid, ppv_object);
+            // 实例化凭据提供程序或过滤器，并查询请求的接口返回
+            let result = if self.clsid == CLSID_SampleFilter {
+                let filter: ICredentialProviderFilter = SampleFilter::new().into();
+                filter.query(riid, ppv_object)
+            } else {
+                let provider: ICredentialProvider = SampleProvider::new().into();
+                provider.query(riid, ppv_object)
+            };
             if result.is_err() {
                 error!("接口查询失败: {:?}", result.message());
                 Err(E_INVALIDARG.into())
@@ -207,10 +341,11 @@ pub unsafe extern "system" fn DllGetClassObject(
         return E_INVALIDARG;
     }
 
-    // 检查请求的CLSID是否为我们的凭据提供程序
-    if unsafe { *rclsid } == CLSID_SampleProvider {
+    // 检查请求的CLSID是否为我们的凭据提供程序或过滤器
+    let clsid = unsafe { *rclsid };
+    if clsid == CLSID_SampleProvider || clsid == CLSID_SampleFilter {
         info!("请求的CLSID匹配，创建类工厂实例");
-        let factory: IClassFactory = SampleClassFactory.into();
+        let factory: IClassFactory = SampleClassFactory { clsid }.into();
         // 查询请求的接口
         unsafe {
             let result = factory.query(riid, ppv);
@@ -258,21 +393,14 @@ pub unsafe extern "system" fn DllMain(
     match dw_reason {
         DLL_PROCESS_ATTACH => {
             // 读取注册表设置
-            let result = read_facewinunlock_registry("DLL_LOG_PATH");
-            let mut log_path = String::from("C:");
-
-            if let Ok(log_path_reg) = result.clone() {
-                log_path = if log_path_reg.starts_with("\\\\?\\") {
-                    log_path_reg["\\\\?\\".len()..].to_string()
-                } else {
-                    log_path_reg
-                };
-            }
+            let result = read_log_dir();
+            let log_path = result.clone().unwrap_or_else(|_| String::from("C:"));
 
             // 初始化日志系统
             if let Ok(file) = File::create(log_path + "\\facewinunlock.log") {
-                // 日志时间太麻烦，不搞了，没有日期影响不大
+                // 日志带上完整日期，方便和 Unlock 服务、UI 的日志按尝试 ID 对照
                 if let Ok(config) = ConfigBuilder::new().set_time_offset_to_local(){
+                    config.set_time_format_rfc3339();
                     match CombinedLogger::init(
                         vec![
                             WriteLogger::new(
@@ -288,7 +416,7 @@ pub unsafe extern "system" fn DllMain(
                 }
             }
             
-            info!("DllMain: 基础框架初始化完成");
+            info!("DllMain: 基础框架初始化完成，连续异常退出次数: {}", crash_guard::unclean_exits());
 
             if let Err(e) = result {
                 warn!("从注册表加载配置失败：{}", e);
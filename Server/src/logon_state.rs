@@ -0,0 +1,310 @@
+//! 面容凭据的自动登录状态机
+//!
+//! 空闲 → 凭据待提交 → 已提交给 LSA → 已收到登录结果 → 空闲。
+//! 管道收到的凭据只在有效期内、且只能被 GetSerialization 取走一次；登录结果只交给同一个尝试 ID 的请求。
+//! 这里只有状态转换，不调用 COM 接口，当前时间由调用方传入。
+
+use std::time::{Duration, Instant};
+use protocol::AttemptTimer;
+
+/// 凭据待提交的有效期，登录界面超过这个时间还没来取就作废，避免迟到或重复的消息在很久之后登录
+pub const PENDING_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+pub enum LogonState {
+    /// 没有待处理的面容凭据
+    #[default]
+    Idle,
+    /// 管道收到了凭据，等待登录界面调用 GetSerialization
+    Pending {
+        attempt: AttemptTimer,
+        user_name: String,
+        password: String,
+        received_at: Instant,
+    },
+    /// 凭据已交给 LSA，密码不再保留，等待 ReportResult
+    Submitted { attempt: AttemptTimer, user_name: String },
+    /// 收到了登录结果，等待管道线程回复给 Unlock 服务
+    Reported {
+        attempt: AttemptTimer,
+        user_name: String,
+        ntstatus: i32,
+        // 账户的连续失败次数达到上限时的次数，回复 Suspended
+        suspended_failures: Option<u32>,
+    },
+}
+
+/// 取凭据失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TakeError {
+    /// 没有待提交的凭据（从未收到，或已经被取走）
+    NotPending,
+    /// 凭据超过了有效期，已作废
+    Expired,
+}
+
+/// 同一个尝试的凭据已经交给 LSA（或已有结果），重复或迟到的消息不能再次提交
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlreadySubmitted;
+
+impl LogonState {
+    /// 收到新的凭据，覆盖之前未完成的其他尝试，返回被覆盖的尝试 ID
+    ///
+    /// 同一个尝试已经提交或已有结果时拒绝，状态不变
+    pub fn receive(
+        &mut self,
+        attempt: AttemptTimer,
+        user_name: String,
+        password: String,
+        now: Instant,
+    ) -> Result<Option<String>, AlreadySubmitted> {
+        if matches!(self, LogonState::Submitted { .. } | LogonState::Reported { .. }) && self.is_current(attempt.id()) {
+            return Err(AlreadySubmitted);
+        }
+        let superseded = self.attempt_id().map(str::to_string);
+        *self = LogonState::Pending { attempt, user_name, password, received_at: now };
+        Ok(superseded)
+    }
+
+    /// 当前尝试的 ID，空闲时为 None
+    pub fn attempt_id(&self) -> Option<&str> {
+        self.attempt().map(AttemptTimer::id)
+    }
+
+    /// 状态是否仍属于这个尝试
+    pub fn is_current(&self, attempt_id: &str) -> bool {
+        self.attempt_id() == Some(attempt_id)
+    }
+
+    fn attempt(&self) -> Option<&AttemptTimer> {
+        match self {
+            LogonState::Idle => None,
+            LogonState::Pending { attempt, .. } | LogonState::Submitted { attempt, .. } | LogonState::Reported { attempt, .. } => Some(attempt),
+        }
+    }
+
+    /// 当前尝试的计时器，记录各阶段的耗时
+    pub fn attempt_mut(&mut self) -> Option<&mut AttemptTimer> {
+        match self {
+            LogonState::Idle => None,
+            LogonState::Pending { attempt, .. } | LogonState::Submitted { attempt, .. } | LogonState::Reported { attempt, .. } => Some(attempt),
+        }
+    }
+
+    /// 当前尝试的用户名，空闲时为 None
+    pub fn user_name(&self) -> Option<&str> {
+        match self {
+            LogonState::Idle => None,
+            LogonState::Pending { user_name, .. } | LogonState::Submitted { user_name, .. } | LogonState::Reported { user_name, .. } => Some(user_name),
+        }
+    }
+
+    /// 有效期内待提交凭据的用户名，登录界面据此选中磁贴并自动登录
+    pub fn pending_user(&self, now: Instant) -> Option<&str> {
+        match self {
+            LogonState::Pending { user_name, received_at, .. } if !expired(*received_at, now) => Some(user_name),
+            _ => None,
+        }
+    }
+
+    /// 已交给 LSA、等待登录结果的用户名
+    pub fn submitted_user(&self) -> Option<&str> {
+        match self {
+            LogonState::Submitted { user_name, .. } => Some(user_name),
+            _ => None,
+        }
+    }
+
+    /// 取走待提交的用户名和密码，只能成功一次；过期的凭据作废并回到空闲
+    pub fn take_credentials(&mut self, now: Instant) -> Result<(String, String), TakeError> {
+        match std::mem::take(self) {
+            LogonState::Pending { attempt, user_name, password, received_at } => {
+                if expired(received_at, now) {
+                    return Err(TakeError::Expired);
+                }
+                *self = LogonState::Submitted { attempt, user_name: user_name.clone() };
+                Ok((user_name, password))
+            }
+            other => {
+                *self = other;
+                Err(TakeError::NotPending)
+            }
+        }
+    }
+
+    /// 记录 LSA 的登录结果，只有已提交的凭据才会记录，返回是否记录
+    pub fn report(&mut self, ntstatus: i32, suspended_failures: Option<u32>) -> bool {
+        match std::mem::take(self) {
+            LogonState::Submitted { attempt, user_name } => {
+                *self = LogonState::Reported { attempt, user_name, ntstatus, suspended_failures };
+                true
+            }
+            other => {
+                *self = other;
+                false
+            }
+        }
+    }
+
+    /// 取走这个尝试的登录结果并回到空闲
+    pub fn take_result(&mut self, attempt_id: &str) -> Option<(i32, Option<u32>)> {
+        match self {
+            LogonState::Reported { attempt, ntstatus, suspended_failures, .. } if attempt.id() == attempt_id => {
+                let result = (*ntstatus, *suspended_failures);
+                *self = LogonState::Idle;
+                Some(result)
+            }
+            _ => None,
+        }
+    }
+
+    /// 待提交的凭据超过有效期时作废，返回是否作废
+    pub fn expire(&mut self, now: Instant) -> bool {
+        match self {
+            LogonState::Pending { received_at, .. } if expired(*received_at, now) => {
+                *self = LogonState::Idle;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// 放弃这个尝试（等待超时、监听器停止），不影响之后的尝试
+    pub fn abandon(&mut self, attempt_id: &str) {
+        if self.is_current(attempt_id) {
+            *self = LogonState::Idle;
+        }
+    }
+}
+
+fn expired(received_at: Instant, now: Instant) -> bool {
+    now.saturating_duration_since(received_at) > PENDING_TTL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attempt(id: &str) -> AttemptTimer {
+        AttemptTimer::resume(id, 0)
+    }
+
+    fn pending(id: &str, now: Instant) -> LogonState {
+        let mut state = LogonState::default();
+        state.receive(attempt(id), String::from("bob"), String::from("pw"), now).unwrap();
+        state
+    }
+
+    #[test]
+    fn receive_supersedes_pending_attempt() {
+        let now = Instant::now();
+        let mut state = pending("a", now);
+        assert_eq!(state.receive(attempt("b"), String::from("alice"), String::from("pw2"), now), Ok(Some(String::from("a"))));
+        assert!(state.is_current("b"));
+        assert_eq!(state.take_credentials(now), Ok((String::from("alice"), String::from("pw2"))));
+
+        // 空闲时没有被覆盖的尝试
+        assert_eq!(LogonState::default().receive(attempt("c"), String::new(), String::new(), now), Ok(None));
+    }
+
+    #[test]
+    fn credentials_are_taken_once() {
+        let now = Instant::now();
+        let mut state = pending("a", now);
+        assert_eq!(state.pending_user(now), Some("bob"));
+        assert_eq!(state.take_credentials(now), Ok((String::from("bob"), String::from("pw"))));
+        assert_eq!(state.submitted_user(), Some("bob"));
+        assert_eq!(state.pending_user(now), None);
+
+        assert_eq!(state.take_credentials(now), Err(TakeError::NotPending));
+        // 第二次失败不影响已提交的状态
+        assert_eq!(state.submitted_user(), Some("bob"));
+        assert!(state.is_current("a"));
+    }
+
+    #[test]
+    fn expired_credentials_leave_idle() {
+        let now = Instant::now();
+        let late = now + PENDING_TTL + Duration::from_millis(1);
+
+        let mut state = pending("a", now);
+        assert_eq!(state.pending_user(now + PENDING_TTL), Some("bob"));
+        assert_eq!(state.pending_user(late), None);
+        assert_eq!(state.take_credentials(late), Err(TakeError::Expired));
+        assert!(matches!(state, LogonState::Idle));
+        assert_eq!(state.take_credentials(late), Err(TakeError::NotPending));
+
+        let mut state = pending("b", now);
+        assert!(!state.expire(now + PENDING_TTL));
+        assert!(state.expire(late));
+        assert!(matches!(state, LogonState::Idle));
+        assert!(!state.expire(late));
+    }
+
+    #[test]
+    fn report_only_from_submitted() {
+        let now = Instant::now();
+        let mut state = LogonState::default();
+        assert!(!state.report(0, None));
+        assert!(matches!(state, LogonState::Idle));
+
+        let mut state = pending("a", now);
+        assert!(!state.report(0, None));
+        assert_eq!(state.pending_user(now), Some("bob"));
+
+        state.take_credentials(now).unwrap();
+        assert!(state.report(-1, Some(3)));
+        // 已有结果时不会被再次覆盖
+        assert!(!state.report(0, None));
+        assert_eq!(state.take_result("a"), Some((-1, Some(3))));
+    }
+
+    #[test]
+    fn take_result_requires_matching_attempt() {
+        let now = Instant::now();
+        let mut state = pending("a", now);
+        state.take_credentials(now).unwrap();
+        state.report(0, None);
+
+        assert_eq!(state.take_result("b"), None);
+        assert!(state.is_current("a"));
+        assert_eq!(state.take_result("a"), Some((0, None)));
+        assert!(matches!(state, LogonState::Idle));
+        assert_eq!(state.take_result("a"), None);
+    }
+
+    #[test]
+    fn abandon_stale_attempt_is_noop() {
+        let now = Instant::now();
+        let mut state = pending("a", now);
+        state.receive(attempt("b"), String::from("bob"), String::from("pw"), now).unwrap();
+
+        state.abandon("a");
+        assert!(state.is_current("b"));
+        assert_eq!(state.pending_user(now), Some("bob"));
+
+        state.abandon("b");
+        assert!(matches!(state, LogonState::Idle));
+    }
+
+    #[test]
+    fn same_attempt_is_not_received_after_submit() {
+        let now = Instant::now();
+        let mut state = pending("a", now);
+        state.take_credentials(now).unwrap();
+
+        // 重复或迟到的同一尝试的凭据被拒绝，不会再次产生凭据
+        assert_eq!(state.receive(attempt("a"), String::from("bob"), String::from("pw"), now), Err(AlreadySubmitted));
+        assert_eq!(state.take_credentials(now), Err(TakeError::NotPending));
+        assert_eq!(state.submitted_user(), Some("bob"));
+
+        // 已有登录结果时同样拒绝，结果仍能被取走
+        state.report(0, None);
+        assert_eq!(state.receive(attempt("a"), String::from("bob"), String::from("pw"), now), Err(AlreadySubmitted));
+        assert_eq!(state.take_result("a"), Some((0, None)));
+
+        // 新的尝试不受影响
+        assert_eq!(state.receive(attempt("b"), String::from("bob"), String::from("pw"), now), Ok(None));
+        assert_eq!(state.take_credentials(now), Ok((String::from("bob"), String::from("pw"))));
+    }
+}
@@ -0,0 +1,248 @@
+//! 磁贴上显示的文字
+//!
+//! 按磁贴用户的显示语言从下面的消息表中取内置文字，UI 可以通过 write_to_registry 覆盖：
+//! TEXT_<名称>_<语言>（如 TEXT_RETRY_EN）只覆盖一种语言，TEXT_<名称> 覆盖所有语言，值为空时不覆盖。
+//! TILE_LANGUAGE 为 "zh" 或 "en" 时所有磁贴都使用这种语言，未配置或为 "auto" 时按用户的显示语言。
+//! 文字中的 {0}、{1} 依次替换为参数。
+
+use windows::Win32::{
+    Globalization::GetUserDefaultUILanguage,
+    System::Registry::{RegGetValueW, HKEY_USERS, RRF_RT_REG_MULTI_SZ},
+};
+use windows_core::HSTRING;
+use protocol::TileStatus;
+use crate::read_facewinunlock_registry;
+
+// LANG_CHINESE
+const PRIMARY_LANG_CHINESE: u16 = 0x04;
+
+/// 磁贴文字的语言，除中文外都显示英文
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Chinese,
+    English,
+}
+
+impl Language {
+    /// 按 "zh-CN"、"en-US" 形式的语言标记选择
+    pub fn from_tag(tag: &str) -> Self {
+        if tag.trim().to_ascii_lowercase().starts_with("zh") { Language::Chinese } else { Language::English }
+    }
+
+    // 注册表覆盖项的后缀
+    fn suffix(&self) -> &'static str {
+        match self {
+            Language::Chinese => "ZH",
+            Language::English => "EN",
+        }
+    }
+}
+
+/// 磁贴用户的显示语言，sid 为空时（没有关联用户的磁贴、字段描述符）使用系统的显示语言
+pub fn user_language(sid: Option<&str>) -> Language {
+    match read_facewinunlock_registry("TILE_LANGUAGE").as_deref().map(str::trim) {
+        Ok("zh") => return Language::Chinese,
+        Ok("en") => return Language::English,
+        _ => {}
+    }
+    if let Some(tag) = sid.and_then(preferred_ui_language) {
+        return Language::from_tag(&tag);
+    }
+    // 低 10 位为主语言
+    if unsafe { GetUserDefaultUILanguage() } & 0x3FF == PRIMARY_LANG_CHINESE { Language::Chinese } else { Language::English }
+}
+
+// 用户在设置中选择的显示语言（HKEY_USERS\<SID>），用户已登录、配置单元已加载时才能读到
+fn preferred_ui_language(sid: &str) -> Option<String> {
+    let mut buf = [0u16; 256];
+    let mut size = std::mem::size_of_val(&buf) as u32;
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_USERS,
+            &HSTRING::from(format!("{}\\Control Panel\\Desktop", sid)),
+            &HSTRING::from("PreferredUILanguages"),
+            RRF_RT_REG_MULTI_SZ,
+            None,
+            Some(buf.as_mut_ptr() as *mut _),
+            Some(&mut size),
+        )
+    };
+    if status.is_err() {
+        return None;
+    }
+    // 多个字符串以 0 分隔，第一个是当前使用的语言
+    let len = (size as usize / 2).min(buf.len());
+    let first = buf[..len].split(|&c| c == 0).next()?;
+    (!first.is_empty()).then(|| String::from_utf16_lossy(first))
+}
+
+/// 消息表中的一条文字
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Text {
+    /// 登录选项和用户磁贴下显示的名称
+    ProviderLabel,
+    /// 没有关联用户时单独磁贴上的文字
+    UnboundTile,
+    StatusLabel,
+    Retry,
+    Password,
+    Submit,
+    Scanning,
+    NoFace,
+    LivenessFailed,
+    /// {0}: 剩余重试次数
+    NotMatched,
+    Unlocking,
+    Paused,
+    Verifying,
+    Retrying,
+    Busy,
+    /// {0}: 最大重试次数
+    LockedOut,
+    /// {0}: Unlock 服务给出的原因
+    Unavailable,
+    ServiceUnreachable,
+    BudgetExhausted,
+    WrongPassword,
+    /// {0}: 连续失败次数
+    SavedPasswordSuspended,
+    SavedPasswordWrongInTile,
+    SavedPasswordWrong,
+}
+
+impl Text {
+    // 注册表覆盖项的名称
+    fn name(&self) -> &'static str {
+        match self {
+            Text::ProviderLabel => "PROVIDER_LABEL",
+            Text::UnboundTile => "UNBOUND_TILE",
+            Text::StatusLabel => "STATUS_LABEL",
+            Text::Retry => "RETRY",
+            Text::Password => "PASSWORD",
+            Text::Submit => "SUBMIT",
+            Text::Scanning => "SCANNING",
+            Text::NoFace => "NO_FACE",
+            Text::LivenessFailed => "LIVENESS_FAILED",
+            Text::NotMatched => "NOT_MATCHED",
+            Text::Unlocking => "UNLOCKING",
+            Text::Paused => "PAUSED",
+            Text::Verifying => "VERIFYING",
+            Text::Retrying => "RETRYING",
+            Text::Busy => "BUSY",
+            Text::LockedOut => "LOCKED_OUT",
+            Text::Unavailable => "UNAVAILABLE",
+            Text::ServiceUnreachable => "SERVICE_UNREACHABLE",
+            Text::BudgetExhausted => "BUDGET_EXHAUSTED",
+            Text::WrongPassword => "WRONG_PASSWORD",
+            Text::SavedPasswordSuspended => "SAVED_PASSWORD_SUSPENDED",
+            Text::SavedPasswordWrongInTile => "SAVED_PASSWORD_WRONG_IN_TILE",
+            Text::SavedPasswordWrong => "SAVED_PASSWORD_WRONG",
+        }
+    }
+
+    // 内置的文字
+    fn builtin(&self, language: Language) -> &'static str {
+        match (self, language) {
+            (Text::ProviderLabel, Language::Chinese) => "面容解锁",
+            (Text::ProviderLabel, Language::English) => "Face Unlock",
+            (Text::UnboundTile, Language::Chinese) => "FaceWinUnlock-Tauri-请勿点击此磁贴",
+            (Text::UnboundTile, Language::English) => "FaceWinUnlock-Tauri - do not click this tile",
+            (Text::StatusLabel, Language::Chinese) => "识别状态",
+            (Text::StatusLabel, Language::English) => "Recognition status",
+            (Text::Retry, Language::Chinese) => "重新识别面容",
+            (Text::Retry, Language::English) => "Scan my face again",
+            (Text::Password, Language::Chinese) => "密码",
+            (Text::Password, Language::English) => "Password",
+            (Text::Submit, Language::Chinese) => "提交",
+            (Text::Submit, Language::English) => "Submit",
+            (Text::Scanning, Language::Chinese) => "正在识别面容…",
+            (Text::Scanning, Language::English) => "Looking for your face…",
+            (Text::NoFace, Language::Chinese) => "未检测到人脸",
+            (Text::NoFace, Language::English) => "No face detected",
+            (Text::LivenessFailed, Language::Chinese) => "活体检测未通过",
+            (Text::LivenessFailed, Language::English) => "Liveness check failed",
+            (Text::NotMatched, Language::Chinese) => "面容不匹配，还可重试 {0} 次",
+            (Text::NotMatched, Language::English) => "Face not recognized, {0} retries left",
+            (Text::Unlocking, Language::Chinese) => "面容匹配成功，正在登录…",
+            (Text::Unlocking, Language::English) => "Face recognized, signing in…",
+            (Text::Paused, Language::Chinese) => "面容解锁已暂停，请使用密码登录",
+            (Text::Paused, Language::English) => "Face unlock is paused, please sign in with your password",
+            (Text::Verifying, Language::Chinese) => "正在验证面容…",
+            (Text::Verifying, Language::English) => "Verifying your face…",
+            (Text::Retrying, Language::Chinese) => "正在重新识别…",
+            (Text::Retrying, Language::English) => "Scanning again…",
+            (Text::Busy, Language::Chinese) => "正在识别中，请稍候",
+            (Text::Busy, Language::English) => "Recognition in progress, please wait",
+            (Text::LockedOut, Language::Chinese) => "已连续失败 {0} 次，请使用密码登录",
+            (Text::LockedOut, Language::English) => "Failed {0} times in a row, please sign in with your password",
+            (Text::Unavailable, Language::Chinese) => "暂时无法识别：{0}",
+            (Text::Unavailable, Language::English) => "Face recognition is unavailable: {0}",
+            (Text::ServiceUnreachable, Language::Chinese) => "无法连接面容解锁服务",
+            (Text::ServiceUnreachable, Language::English) => "Cannot reach the face unlock service",
+            (Text::BudgetExhausted, Language::Chinese) => "登录失败次数过多，面容解锁已暂停，请使用密码登录",
+            (Text::BudgetExhausted, Language::English) => "Too many failed sign-ins, face unlock is paused. Please sign in with your password",
+            (Text::WrongPassword, Language::Chinese) => "密码错误，请重新输入。",
+            (Text::WrongPassword, Language::English) => "The password is incorrect. Try again.",
+            (Text::SavedPasswordSuspended, Language::Chinese) => "面容解锁保存的密码已连续 {0} 次登录失败，已暂停该账户的面容解锁，请手动输入密码登录。",
+            (Text::SavedPasswordSuspended, Language::English) => "The password saved for face unlock failed {0} times in a row. Face unlock is paused for this account, please enter your password.",
+            (Text::SavedPasswordWrongInTile, Language::Chinese) => "面容解锁保存的密码错误，请在下方输入密码进入系统。",
+            (Text::SavedPasswordWrongInTile, Language::English) => "The password saved for face unlock is incorrect. Enter your password below.",
+            (Text::SavedPasswordWrong, Language::Chinese) => "用户名或密码错误，请点击自己账户，手动输入密码进入系统。",
+            (Text::SavedPasswordWrong, Language::English) => "The user name or password is incorrect. Select your account and enter your password.",
+        }
+    }
+
+    /// 这条文字在指定语言下的内容，优先使用注册表中的覆盖项
+    pub fn localize(&self, language: Language) -> String {
+        [format!("TEXT_{}_{}", self.name(), language.suffix()), format!("TEXT_{}", self.name())]
+            .iter()
+            .filter_map(|key| read_facewinunlock_registry(key).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_else(|| self.builtin(language).to_string())
+    }
+}
+
+/// 带参数的文字，按每个磁贴的语言分别生成，识别状态这样由管道线程更新、多个磁贴共享的文字使用它
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileText {
+    text: Text,
+    args: Vec<String>,
+}
+
+impl TileText {
+    pub fn new(text: Text) -> Self {
+        Self { text, args: Vec::new() }
+    }
+
+    pub fn with_arg(mut self, arg: impl ToString) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    pub fn render(&self, language: Language) -> String {
+        self.args
+            .iter()
+            .enumerate()
+            .fold(self.text.localize(language), |text, (index, arg)| text.replace(&format!("{{{}}}", index), arg))
+    }
+}
+
+impl From<Text> for TileText {
+    fn from(text: Text) -> Self {
+        TileText::new(text)
+    }
+}
+
+/// 识别状态对应的磁贴文字，空闲时不显示
+pub fn status_message(status: &TileStatus) -> Option<TileText> {
+    let message = match status {
+        TileStatus::Idle => return None,
+        TileStatus::Scanning => TileText::new(Text::Scanning),
+        TileStatus::NoFace => TileText::new(Text::NoFace),
+        TileStatus::LivenessFailed => TileText::new(Text::LivenessFailed),
+        TileStatus::NotMatched { retries_left } => TileText::new(Text::NotMatched).with_arg(retries_left),
+        TileStatus::Unlocking => TileText::new(Text::Unlocking),
+        TileStatus::Paused => TileText::new(Text::Paused),
+    };
+    Some(message)
+}
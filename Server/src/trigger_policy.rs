@@ -0,0 +1,366 @@
+//! 锁屏界面上开始面容识别的触发策略
+//!
+//! 由 UI 写入注册表：TRIGGER_SOURCE 选择触发来源（任意输入、仅键盘、指定热键、亮屏/唤醒），
+//! TRIGGER_HOTKEY 为热键（如 "Ctrl+Alt+F"），TRIGGER_DEBOUNCE_MS 为鼠标需要持续移动的时间，
+//! TRIGGER_MIN_INTERVAL_MS 为两次触发之间的最短间隔。
+//! 这里只判断一次输入是否应该触发识别，不安装钩子、不调用 COM，当前时间由调用方传入。
+
+use std::time::{Duration, Instant};
+use crate::read_facewinunlock_registry;
+
+// 未配置时鼠标需要持续移动的时间，碰到桌子导致的一下抖动不会触发
+const DEFAULT_DEBOUNCE_MS: u64 = 300;
+// 未配置时两次触发的最短间隔
+const DEFAULT_MIN_INTERVAL_MS: u64 = 3_000;
+// 鼠标移动事件之间超过这个间隔，视为一次新的移动
+const MOUSE_GAP: Duration = Duration::from_millis(250);
+
+// 修饰键
+const MOD_CTRL: u8 = 0b0001;
+const MOD_ALT: u8 = 0b0010;
+const MOD_SHIFT: u8 = 0b0100;
+const MOD_WIN: u8 = 0b1000;
+
+/// 热键：修饰键组合加一个虚拟键码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hotkey {
+    modifiers: u8,
+    vk: u32,
+}
+
+/// 触发识别的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerSource {
+    /// 任意鼠标、键盘操作或亮屏（默认）
+    ///
+    /// 与之前不同，鼠标移动要持续 `debounce` 才触发，两次触发之间至少间隔 `min_interval`，
+    /// 两者都设为 0 时才与之前任意一次输入即触发的行为一致
+    AnyInput,
+    /// 只有按键
+    Keyboard,
+    /// 只有指定的热键
+    Hotkey(Hotkey),
+    /// 只有显示器从关闭变为点亮（包括从睡眠中唤醒）
+    DisplayOn,
+}
+
+impl TriggerSource {
+    /// 是否需要鼠标钩子
+    pub fn wants_mouse(&self) -> bool {
+        matches!(self, TriggerSource::AnyInput)
+    }
+
+    /// 是否需要键盘钩子
+    pub fn wants_keyboard(&self) -> bool {
+        matches!(self, TriggerSource::AnyInput | TriggerSource::Keyboard | TriggerSource::Hotkey(_))
+    }
+
+    /// 是否需要监听显示器状态
+    pub fn wants_display(&self) -> bool {
+        matches!(self, TriggerSource::AnyInput | TriggerSource::DisplayOn)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TriggerPolicy {
+    pub source: TriggerSource,
+    pub debounce: Duration,
+    pub min_interval: Duration,
+}
+
+impl Default for TriggerPolicy {
+    fn default() -> Self {
+        Self {
+            source: TriggerSource::AnyInput,
+            debounce: Duration::from_millis(DEFAULT_DEBOUNCE_MS),
+            min_interval: Duration::from_millis(DEFAULT_MIN_INTERVAL_MS),
+        }
+    }
+}
+
+/// 读取触发策略，未配置或无法识别的项使用默认值
+pub fn read_trigger_policy() -> TriggerPolicy {
+    let default = TriggerPolicy::default();
+    let millis = |key: &str, default: Duration| {
+        read_facewinunlock_registry(key)
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(default)
+    };
+
+    let source = match read_facewinunlock_registry("TRIGGER_SOURCE").as_deref().map(str::trim) {
+        Ok("keyboard") => TriggerSource::Keyboard,
+        Ok("display") => TriggerSource::DisplayOn,
+        Ok("hotkey") => {
+            let text = read_facewinunlock_registry("TRIGGER_HOTKEY").unwrap_or_default();
+            match parse_hotkey(&text) {
+                Ok(hotkey) => TriggerSource::Hotkey(hotkey),
+                Err(e) => {
+                    warn!("TRIGGER_HOTKEY 无效：{}，改为仅键盘触发", e);
+                    TriggerSource::Keyboard
+                }
+            }
+        }
+        _ => TriggerSource::AnyInput,
+    };
+
+    TriggerPolicy {
+        source,
+        debounce: millis("TRIGGER_DEBOUNCE_MS", default.debounce),
+        min_interval: millis("TRIGGER_MIN_INTERVAL_MS", default.min_interval),
+    }
+}
+
+/// 解析 "Ctrl+Alt+F"、"Space"、"Shift+F9" 形式的热键，不区分大小写
+pub fn parse_hotkey(text: &str) -> Result<Hotkey, String> {
+    let mut modifiers = 0;
+    let mut vk = None;
+    for part in text.split('+').map(str::trim) {
+        let upper = part.to_ascii_uppercase();
+        match upper.as_str() {
+            "CTRL" | "CONTROL" => modifiers |= MOD_CTRL,
+            "ALT" => modifiers |= MOD_ALT,
+            "SHIFT" => modifiers |= MOD_SHIFT,
+            "WIN" => modifiers |= MOD_WIN,
+            _ if vk.is_some() => return Err(format!("只能有一个非修饰键：{}", text)),
+            _ => vk = Some(key_code(&upper).ok_or_else(|| format!("无法识别的按键：{}", part))?),
+        }
+    }
+    vk.map(|vk| Hotkey { modifiers, vk }).ok_or_else(|| format!("缺少非修饰键：{}", text))
+}
+
+// 按键名对应的虚拟键码
+fn key_code(name: &str) -> Option<u32> {
+    let bytes = name.as_bytes();
+    match name {
+        "SPACE" => Some(0x20),
+        "ENTER" => Some(0x0D),
+        "ESC" => Some(0x1B),
+        "TAB" => Some(0x09),
+        _ if bytes.len() == 1 && bytes[0].is_ascii_alphanumeric() => Some(bytes[0] as u32),
+        _ if bytes.first() == Some(&b'F') => match name[1..].parse::<u32>() {
+            Ok(n @ 1..=24) => Some(0x70 + n - 1),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// 虚拟键码对应的修饰键，不是修饰键时为 0
+fn modifier_of(vk: u32) -> u8 {
+    match vk {
+        0x11 | 0xA2 | 0xA3 => MOD_CTRL,
+        0x12 | 0xA4 | 0xA5 => MOD_ALT,
+        0x10 | 0xA0 | 0xA1 => MOD_SHIFT,
+        0x5B | 0x5C => MOD_WIN,
+        _ => 0,
+    }
+}
+
+/// 钩子和电源通知收到的输入
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    MouseMove,
+    /// 鼠标按键、滚轮
+    MouseButton,
+    KeyDown(u32),
+    KeyUp(u32),
+    /// 显示器状态，true 为点亮
+    Display(bool),
+}
+
+/// 按触发策略过滤输入，决定何时开始识别
+#[derive(Debug)]
+pub struct TriggerGate {
+    policy: TriggerPolicy,
+    // 当前按下的修饰键
+    modifiers: u8,
+    // 这次连续移动鼠标的开始时间和最后一次移动的时间
+    mouse_since: Option<Instant>,
+    last_mouse: Option<Instant>,
+    // 显示器上一次的状态，第一次通知只记录不触发
+    display_on: Option<bool>,
+    last_fired: Option<Instant>,
+}
+
+impl TriggerGate {
+    pub fn new(policy: TriggerPolicy) -> Self {
+        Self { policy, modifiers: 0, mouse_since: None, last_mouse: None, display_on: None, last_fired: None }
+    }
+
+    pub fn policy(&self) -> &TriggerPolicy {
+        &self.policy
+    }
+
+    /// 处理一次输入，返回是否应该开始识别
+    pub fn on_event(&mut self, event: InputEvent, now: Instant) -> bool {
+        let wanted = match event {
+            InputEvent::KeyDown(vk) => {
+                self.modifiers |= modifier_of(vk);
+                match self.policy.source {
+                    TriggerSource::AnyInput | TriggerSource::Keyboard => true,
+                    TriggerSource::Hotkey(hotkey) => vk == hotkey.vk && self.modifiers == hotkey.modifiers,
+                    TriggerSource::DisplayOn => false,
+                }
+            }
+            InputEvent::KeyUp(vk) => {
+                self.modifiers &= !modifier_of(vk);
+                false
+            }
+            InputEvent::MouseMove => self.policy.source.wants_mouse() && self.mouse_settled(now),
+            InputEvent::MouseButton => self.policy.source.wants_mouse(),
+            InputEvent::Display(on) => {
+                let woke = on && self.display_on == Some(false);
+                self.display_on = Some(on);
+                woke && self.policy.source.wants_display()
+            }
+        };
+        if !wanted {
+            return false;
+        }
+
+        if self.last_fired.is_some_and(|fired| now.saturating_duration_since(fired) < self.policy.min_interval) {
+            return false;
+        }
+        self.last_fired = Some(now);
+        self.mouse_since = None;
+        true
+    }
+
+    // 鼠标是否已经持续移动了 debounce 这么久
+    fn mouse_settled(&mut self, now: Instant) -> bool {
+        let continuing = self.last_mouse.is_some_and(|last| now.saturating_duration_since(last) <= MOUSE_GAP);
+        self.last_mouse = Some(now);
+        let since = match self.mouse_since {
+            Some(since) if continuing => since,
+            _ => *self.mouse_since.insert(now),
+        };
+        now.saturating_duration_since(since) >= self.policy.debounce
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VK_CTRL: u32 = 0xA2;
+    const VK_ALT: u32 = 0xA4;
+    const VK_F: u32 = b'F' as u32;
+
+    fn gate(source: TriggerSource, debounce_ms: u64, min_interval_ms: u64) -> TriggerGate {
+        TriggerGate::new(TriggerPolicy {
+            source,
+            debounce: Duration::from_millis(debounce_ms),
+            min_interval: Duration::from_millis(min_interval_ms),
+        })
+    }
+
+    fn ms(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn mouse_move_needs_debounce() {
+        let start = Instant::now();
+        let mut gate = gate(TriggerSource::AnyInput, 300, 0);
+
+        // 一下抖动不触发，持续移动满 300 毫秒才触发
+        assert!(!gate.on_event(InputEvent::MouseMove, start));
+        assert!(!gate.on_event(InputEvent::MouseMove, ms(start, 100)));
+        assert!(!gate.on_event(InputEvent::MouseMove, ms(start, 200)));
+        assert!(gate.on_event(InputEvent::MouseMove, ms(start, 300)));
+
+        // 移动中断超过 MOUSE_GAP 后重新计时
+        assert!(!gate.on_event(InputEvent::MouseMove, ms(start, 1_000)));
+        assert!(!gate.on_event(InputEvent::MouseMove, ms(start, 1_200)));
+        assert!(!gate.on_event(InputEvent::MouseMove, ms(start, 1_500)));
+        assert!(!gate.on_event(InputEvent::MouseMove, ms(start, 1_700)));
+        assert!(gate.on_event(InputEvent::MouseMove, ms(start, 1_800)));
+
+        // 没有防抖时第一次移动就触发
+        let mut undebounced = TriggerGate::new(TriggerPolicy { debounce: Duration::ZERO, ..TriggerPolicy::default() });
+        assert!(undebounced.on_event(InputEvent::MouseMove, start));
+    }
+
+    #[test]
+    fn min_interval_suppresses_repeats() {
+        let start = Instant::now();
+        let mut gate = gate(TriggerSource::AnyInput, 0, 3_000);
+
+        assert!(gate.on_event(InputEvent::KeyDown(VK_F), start));
+        assert!(!gate.on_event(InputEvent::MouseButton, ms(start, 1_000)));
+        assert!(!gate.on_event(InputEvent::KeyDown(VK_F), ms(start, 2_999)));
+        assert!(gate.on_event(InputEvent::KeyDown(VK_F), ms(start, 3_000)));
+        assert!(!gate.on_event(InputEvent::MouseMove, ms(start, 3_500)));
+    }
+
+    #[test]
+    fn parses_hotkeys() {
+        assert_eq!(parse_hotkey("Ctrl+Alt+F"), Ok(Hotkey { modifiers: MOD_CTRL | MOD_ALT, vk: VK_F }));
+        assert_eq!(parse_hotkey(" control + shift + f9 "), Ok(Hotkey { modifiers: MOD_CTRL | MOD_SHIFT, vk: 0x78 }));
+        assert_eq!(parse_hotkey("Space"), Ok(Hotkey { modifiers: 0, vk: 0x20 }));
+        assert_eq!(parse_hotkey("Win+1"), Ok(Hotkey { modifiers: MOD_WIN, vk: b'1' as u32 }));
+        assert_eq!(parse_hotkey("F24"), Ok(Hotkey { modifiers: 0, vk: 0x87 }));
+
+        for bad in ["", "Ctrl+Alt", "Ctrl+F+G", "Ctrl+Home", "F0", "F25", "Fx", "Ctrl++F"] {
+            assert!(parse_hotkey(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn key_codes() {
+        assert_eq!(key_code("A"), Some(0x41));
+        assert_eq!(key_code("0"), Some(0x30));
+        assert_eq!(key_code("ENTER"), Some(0x0D));
+        assert_eq!(key_code("F1"), Some(0x70));
+        assert_eq!(key_code("F"), Some(VK_F));
+        assert_eq!(key_code("%"), None);
+        assert_eq!(key_code("F-1"), None);
+    }
+
+    #[test]
+    fn hotkey_needs_exact_modifiers() {
+        let start = Instant::now();
+        let hotkey = parse_hotkey("Ctrl+Alt+F").unwrap();
+        let mut gate = gate(TriggerSource::Hotkey(hotkey), 0, 0);
+
+        assert!(!gate.on_event(InputEvent::KeyDown(VK_F), start));
+        assert!(!gate.on_event(InputEvent::KeyDown(VK_CTRL), start));
+        assert!(!gate.on_event(InputEvent::KeyDown(VK_F), start));
+        assert!(!gate.on_event(InputEvent::KeyDown(VK_ALT), start));
+        assert!(gate.on_event(InputEvent::KeyDown(VK_F), start));
+
+        // 松开 Alt 之后不再匹配
+        gate.on_event(InputEvent::KeyUp(VK_ALT), start);
+        assert!(!gate.on_event(InputEvent::KeyDown(VK_F), start));
+        assert!(!gate.on_event(InputEvent::MouseButton, start));
+    }
+
+    #[test]
+    fn display_on_ignores_input() {
+        let start = Instant::now();
+        let mut gate = gate(TriggerSource::DisplayOn, 0, 0);
+
+        assert!(!gate.on_event(InputEvent::KeyDown(VK_F), start));
+        assert!(!gate.on_event(InputEvent::MouseMove, start));
+        assert!(!gate.on_event(InputEvent::MouseButton, start));
+
+        // 第一次通知只记录状态，之后从关闭变为点亮才触发
+        assert!(!gate.on_event(InputEvent::Display(true), start));
+        assert!(!gate.on_event(InputEvent::Display(false), start));
+        assert!(gate.on_event(InputEvent::Display(true), start));
+        assert!(!gate.on_event(InputEvent::Display(true), start));
+    }
+
+    #[test]
+    fn keyboard_source_ignores_mouse_and_display() {
+        let start = Instant::now();
+        let mut gate = gate(TriggerSource::Keyboard, 0, 0);
+        assert!(!gate.on_event(InputEvent::MouseMove, start));
+        assert!(!gate.on_event(InputEvent::MouseButton, start));
+        gate.on_event(InputEvent::Display(false), start);
+        assert!(!gate.on_event(InputEvent::Display(true), start));
+        assert!(gate.on_event(InputEvent::KeyDown(VK_F), start));
+    }
+}
@@ -15,21 +15,21 @@ pub mod modules;
 pub mod proc;
 pub mod utils;
 use modules::faces::{
-    check_face_from_camera, check_face_from_img, save_face_registration, verify_face,
+    check_face_from_camera, check_face_from_img, save_face_registration, sync_tile_pictures, verify_face,
 };
 use modules::init::{
     check_admin_privileges, check_camera_status, deploy_core_components, uninstall_init,
 };
-use modules::options::write_to_registry;
+use modules::options::{ensure_pipe_key, reset_crash_guard, write_to_registry};
 use opencv::{
     core::Ptr,
     objdetect::{FaceDetectorYN, FaceRecognizerSF},
     videoio::VideoCapture,
 };
 use proc::wnd_proc_subclass;
-use tauri_plugin_log::{Target, TargetKind};
+use tauri_plugin_log::{log::warn, Target, TargetKind};
 use utils::api::{
-    add_scheduled_task, check_process_running, check_scheduled_task, close_app,
+    add_scheduled_task, check_process_running, get_service_status, reload_service_options, subscribe_recognition_events, check_scheduled_task, close_app,
     delete_process_running, disable_scheduled_task, get_camera, get_now_username, init_model,
     load_opencv_model, open_camera, open_directory, stop_camera, test_win_logon, unload_model, get_uuid_v4, get_cache_dir, run_scheduled_task,
     check_trigger_via_xml
@@ -123,6 +123,10 @@ pub fn run() {
                     .build(),
             )
             .setup(|app| {
+                // 旧版本升级上来时没有管道密钥，这里补上
+                if let Err(e) = ensure_pipe_key() {
+                    warn!("管道密钥初始化失败：{:?}", e);
+                }
                 let _ = create_system_tray(app.app_handle());
                 let window = app.get_webview_window("main").unwrap();
                 #[cfg(debug_assertions)] // 仅在调试(debug)版本中包含此代码
@@ -178,8 +182,10 @@ pub fn run() {
                 check_face_from_camera,
                 verify_face,
                 save_face_registration,
+                sync_tile_pictures,
                 // 配置模块
                 write_to_registry,
+                reset_crash_guard,
                 // 通用api
                 get_now_username,
                 test_win_logon,
@@ -190,6 +196,9 @@ pub fn run() {
                 open_directory,
                 close_app,
                 check_process_running,
+                get_service_status,
+                reload_service_options,
+                subscribe_recognition_events,
                 delete_process_running,
                 load_opencv_model,
                 add_scheduled_task,
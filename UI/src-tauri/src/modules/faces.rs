@@ -314,6 +314,59 @@ pub fn save_face_registration(
     ))
 }
 
+// 磁贴头像的边长，与 DLL 中的 TILE_IMAGE_SIZE 一致
+const TILE_PICTURE_SIZE: i32 = 192;
+
+#[derive(Deserialize, Debug)]
+pub struct TilePicture {
+    pub user_name: String,
+    pub face_token: String,
+}
+
+// 把每个账户的第一张面容照片转成 BMP，放到 tiles 目录供 DLL 显示在登录界面的磁贴上
+// 文件名为反斜杠后的账户名（小写），与 DLL 中 TileUser::account_name 一致
+#[tauri::command]
+pub fn sync_tile_pictures(pictures: Vec<TilePicture>) -> Result<CustomResult, CustomResult> {
+    let path = ROOT_DIR.join("tiles");
+    // 删除已删除面容的头像
+    if path.exists() {
+        fs::remove_dir_all(&path)
+            .map_err(|e| CustomResult::error(Some(format!("清理 tiles 文件夹失败: {}", e)), None))?;
+    }
+    fs::create_dir_all(&path)
+        .map_err(|e| CustomResult::error(Some(format!("创建 tiles 文件夹失败: {}", e)), None))?;
+
+    for picture in pictures {
+        let account = picture.user_name.rsplit('\\').next().unwrap_or("").to_lowercase();
+        let tile_path = path.join(format!("{}.bmp", account));
+        if account.is_empty() || tile_path.exists() {
+            continue;
+        }
+
+        let img_path = ROOT_DIR.join("faces").join(format!("{}.faceimg", picture.face_token));
+        let img = imgcodecs::imread(&img_path.to_string_lossy(), imgcodecs::IMREAD_COLOR)
+            .map_err(|e| CustomResult::error(Some(format!("读取面容图片失败: {}", e)), None))?;
+        if img.empty() {
+            continue;
+        }
+
+        // 从中间裁出正方形再缩放
+        let size = img.size().map_err(|e| CustomResult::error(Some(format!("获取图片尺寸失败: {}", e)), None))?;
+        let side = size.width.min(size.height);
+        let square = Mat::roi(&img, Rect::new((size.width - side) / 2, (size.height - side) / 2, side, side))
+            .and_then(|roi| roi.try_clone())
+            .map_err(|e| CustomResult::error(Some(format!("图片裁切失败: {}", e)), None))?;
+        let mut tile = Mat::default();
+        imgproc::resize(&square, &mut tile, Size::new(TILE_PICTURE_SIZE, TILE_PICTURE_SIZE), 0.0, 0.0, imgproc::INTER_AREA)
+            .map_err(|e| CustomResult::error(Some(format!("图片缩放失败: {}", e)), None))?;
+
+        imgcodecs::imwrite(&tile_path.to_string_lossy(), &tile, &Vector::new())
+            .map_err(|e| CustomResult::error(Some(format!("保存磁贴头像失败: {}", e)), None))?;
+    }
+
+    Ok(CustomResult::success(None, None))
+}
+
 /// 提取特征点
 /// return (裁切后的图片, 特征点)
 pub fn get_feature(img: &Mat, face_detection_threshold: f32) -> Result<(Mat, Mat, Mat), String> {
@@ -1,4 +1,4 @@
-use crate::modules::options::{write_to_registry, RegistryItem};
+use crate::modules::options::{ensure_pipe_key, write_to_registry, RegistryItem};
 use crate::utils::api::{add_scheduled_task, check_process_running, check_scheduled_task, delete_process_running, disable_scheduled_task};
 use crate::{
     utils::{
@@ -6,7 +6,11 @@ use crate::{
     },
     ROOT_DIR,
 };
-use opencv::videoio::{self, VideoCaptureTraitConst};
+use opencv::{
+    core::Vector,
+    imgcodecs,
+    videoio::{self, VideoCaptureTraitConst},
+};
 use serde_json::json;
 use std::fs;
 use windows::Win32::{
@@ -17,6 +21,9 @@ use windows::Win32::{
 use winreg::enums::*;
 use winreg::RegKey;
 
+// 凭据提供程序过滤器的 CLSID，与 DLL 中的 CLSID_SampleFilter 一致
+const FILTER_CLSID: &str = "{8a7b9c6d-4e5f-89a0-8b7c-6d5e4f3e2d1d}";
+
 // 检查是否具有管理员权限
 #[tauri::command]
 pub fn check_admin_privileges() -> Result<CustomResult, CustomResult> {
@@ -126,37 +133,23 @@ pub fn deploy_core_components() -> Result<CustomResult, CustomResult> {
         .map_err(|e| CustomResult::error(Some(format!("无法设置注册表项(CP): {}", e)), None))?;
 
     // 注册 CLSID
-    let clsid_path = format!("CLSID\\{}", clsid);
-    let (clsid_key, _) = hk_cr
-        .create_subkey(&clsid_path)
-        .map_err(|e| CustomResult::error(Some(format!("无法创建注册表项(CLSID): {}", e)), None))?;
-    clsid_key
-        .set_value("", &"FaceWinUnlock-Tauri")
-        .map_err(|e| CustomResult::error(Some(format!("无法设置注册表项(CLSID): {}", e)), None))?;
+    register_clsid(&hk_cr, clsid, &target_path)?;
 
-    let (inproc_key, _) = hk_cr
-        .create_subkey(format!("{}\\InprocServer32", clsid_path))
-        .map_err(|e| {
-            CustomResult::error(
-                Some(format!("无法创建注册表项(InprocServer32): {}", e)),
-                None,
-            )
-        })?;
+    // 注册凭据提供程序过滤器，只在包装模式下隐藏系统的密码凭据提供程序
+    let filter_path = format!(
+        "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Authentication\\Credential Provider Filters\\{}",
+        FILTER_CLSID
+    );
+    let (filter_key, _) = hk_lm
+        .create_subkey(filter_path)
+        .map_err(|e| CustomResult::error(Some(format!("无法创建注册表项(Filter): {}", e)), None))?;
+    filter_key
+        .set_value("", &"FaceWinUnlock-Tauri Filter")
+        .map_err(|e| CustomResult::error(Some(format!("无法设置注册表项(Filter): {}", e)), None))?;
+    register_clsid(&hk_cr, FILTER_CLSID, &target_path)?;
 
-    inproc_key.set_value("", &target_path).map_err(|e| {
-        CustomResult::error(
-            Some(format!("无法设置注册表项(InprocServer32): {}", e)),
-            None,
-        )
-    })?;
-    inproc_key
-        .set_value("ThreadingModel", &"Apartment")
-        .map_err(|e| {
-            CustomResult::error(
-                Some(format!("无法设置注册表项(ThreadingModel): {}", e)),
-                None,
-            )
-        })?;
+    // 写入磁贴图标，DLL 只解析 BMP
+    write_tile_logo()?;
 
     // 创建dll日志路径
     let path = ROOT_DIR.join("logs");
@@ -178,6 +171,9 @@ pub fn deploy_core_components() -> Result<CustomResult, CustomResult> {
         value: path_str.unwrap().to_string(),
     }])?;
 
+    // 生成管道通信密钥，服务启动前必须存在
+    ensure_pipe_key()?;
+
     let mut is_run = true;
     if let Ok(_) = check_process_running() {
         // 如果正在运行
@@ -193,6 +189,53 @@ pub fn deploy_core_components() -> Result<CustomResult, CustomResult> {
     Ok(CustomResult::success(None, None))
 }
 
+// 把软件图标转成 BMP，作为登录界面上的磁贴图标
+fn write_tile_logo() -> Result<(), CustomResult> {
+    let logo = include_bytes!("../../icons/128x128.png");
+    let img = imgcodecs::imdecode(&Vector::<u8>::from_slice(logo), imgcodecs::IMREAD_COLOR)
+        .map_err(|e| CustomResult::error(Some(format!("解码磁贴图标失败: {}", e)), None))?;
+    let path = ROOT_DIR.join("resources").join("tile_logo.bmp");
+    imgcodecs::imwrite(&path.to_string_lossy(), &img, &Vector::new())
+        .map_err(|e| CustomResult::error(Some(format!("写入磁贴图标失败: {}", e)), None))?;
+    Ok(())
+}
+
+// 在 HKCR\CLSID 下注册 DLL 中的 COM 组件
+fn register_clsid(hk_cr: &RegKey, clsid: &str, target_path: &str) -> Result<(), CustomResult> {
+    let clsid_path = format!("CLSID\\{}", clsid);
+    let (clsid_key, _) = hk_cr
+        .create_subkey(&clsid_path)
+        .map_err(|e| CustomResult::error(Some(format!("无法创建注册表项(CLSID): {}", e)), None))?;
+    clsid_key
+        .set_value("", &"FaceWinUnlock-Tauri")
+        .map_err(|e| CustomResult::error(Some(format!("无法设置注册表项(CLSID): {}", e)), None))?;
+
+    let (inproc_key, _) = hk_cr
+        .create_subkey(format!("{}\\InprocServer32", clsid_path))
+        .map_err(|e| {
+            CustomResult::error(
+                Some(format!("无法创建注册表项(InprocServer32): {}", e)),
+                None,
+            )
+        })?;
+
+    inproc_key.set_value("", &target_path).map_err(|e| {
+        CustomResult::error(
+            Some(format!("无法设置注册表项(InprocServer32): {}", e)),
+            None,
+        )
+    })?;
+    inproc_key
+        .set_value("ThreadingModel", &"Apartment")
+        .map_err(|e| {
+            CustomResult::error(
+                Some(format!("无法设置注册表项(ThreadingModel): {}", e)),
+                None,
+            )
+        })?;
+    Ok(())
+}
+
 // 卸载dll
 #[tauri::command]
 pub fn uninstall_init() -> Result<CustomResult, CustomResult> {
@@ -216,6 +259,14 @@ pub fn uninstall_init() -> Result<CustomResult, CustomResult> {
     hkcr.delete_subkey_all(&clsid_path)
         .map_err(|e| CustomResult::error(Some(format!("删除注册表项(CLSID)失败: {}", e)), None))?;
 
+    // 删除凭据提供程序过滤器，旧版本没有注册过滤器，不存在时忽略
+    let filter_path = format!(
+        "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Authentication\\Credential Provider Filters\\{}",
+        FILTER_CLSID
+    );
+    let _ = hklm.delete_subkey_all(&filter_path);
+    let _ = hkcr.delete_subkey_all(format!("CLSID\\{}", FILTER_CLSID));
+
     // 删除程序DLL设置的注册表
     hklm.delete_subkey_all(MAIN_REG_PATH)
         .map_err(|e| CustomResult::error(Some(format!("删除注册表项(DLL)失败: {}", e)), None))?;
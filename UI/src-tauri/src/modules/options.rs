@@ -1,4 +1,20 @@
 use crate::utils::custom_result::CustomResult;
+use protocol::{crypto, CRASH_GUARD_REG_PATH, PIPE_KEY_NAME, SECURE_REG_PATH};
+use tauri_plugin_log::log::info;
+use windows::{
+    core::{w, BOOL, HSTRING},
+    Win32::{
+        Foundation::{LocalFree, HLOCAL, WIN32_ERROR},
+        Security::{
+            Authorization::{
+                ConvertStringSecurityDescriptorToSecurityDescriptorW, SetNamedSecurityInfoW,
+                SDDL_REVISION_1, SE_REGISTRY_KEY,
+            },
+            GetSecurityDescriptorDacl, ACL, DACL_SECURITY_INFORMATION,
+            PROTECTED_DACL_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR,
+        },
+    },
+};
 use winreg::enums::*;
 use winreg::RegKey;
 
@@ -29,3 +45,95 @@ pub fn write_to_registry(items: Vec<RegistryItem>) -> Result<CustomResult, Custo
 
     Ok(CustomResult::success(None, None))
 }
+
+// 清空 DLL 的崩溃保护记录，连续异常退出被停用的面容解锁在下次锁屏时恢复
+#[tauri::command]
+pub fn reset_crash_guard() -> Result<CustomResult, CustomResult> {
+    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+    match hklm.delete_subkey_all(CRASH_GUARD_REG_PATH) {
+        Ok(_) => {
+            info!("已清空崩溃保护记录");
+            Ok(CustomResult::success(None, None))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(CustomResult::success(None, None)),
+        Err(e) => Err(CustomResult::error(Some(format!("清空崩溃保护记录失败 {}", e)), None)),
+    }
+}
+
+// 确保管道密钥存在，不存在时生成一个
+// DLL、解锁服务、UI 都用这个密钥给管道通信加密，只有 SYSTEM 和管理员能读取
+pub fn ensure_pipe_key() -> Result<CustomResult, CustomResult> {
+    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+    let (secure_key, _) = hklm
+        .create_subkey(SECURE_REG_PATH)
+        .map_err(|e| CustomResult::error(Some(format!("创建密钥子项失败 {}", e)), None))?;
+
+    // 每次都收紧一次权限，防止被其他程序改过
+    restrict_secure_key()?;
+
+    let existing: Result<String, _> = secure_key.get_value(PIPE_KEY_NAME);
+    if let Ok(hex) = existing {
+        if crypto::key_from_hex(&hex).is_ok() {
+            return Ok(CustomResult::success(None, None));
+        }
+    }
+
+    let key = crypto::generate_key()
+        .map_err(|e| CustomResult::error(Some(format!("生成管道密钥失败 {}", e)), None))?;
+    secure_key
+        .set_value(PIPE_KEY_NAME, &crypto::key_to_hex(&key))
+        .map_err(|e| CustomResult::error(Some(format!("写入管道密钥失败 {}", e)), None))?;
+
+    info!("已生成新的管道密钥");
+    Ok(CustomResult::success(None, None))
+}
+
+// 密钥子项只允许 SYSTEM 和管理员访问，并且不继承上级权限
+fn restrict_secure_key() -> Result<(), CustomResult> {
+    unsafe {
+        let mut security_descriptor = PSECURITY_DESCRIPTOR::default();
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            w!("D:P(A;OICI;KA;;;SY)(A;OICI;KA;;;BA)"),
+            SDDL_REVISION_1,
+            &mut security_descriptor,
+            None,
+        )
+        .map_err(|e| CustomResult::error(Some(format!("安全描述符转换失败：{}", e)), None))?;
+
+        let mut dacl_present: BOOL = BOOL::from(false);
+        let mut dacl: *mut ACL = std::ptr::null_mut();
+        let mut dacl_defaulted: BOOL = BOOL::from(false);
+        let result = GetSecurityDescriptorDacl(
+            security_descriptor,
+            &mut dacl_present,
+            &mut dacl,
+            &mut dacl_defaulted,
+        );
+        if result.is_err() || !dacl_present.as_bool() || dacl.is_null() {
+            LocalFree(Some(HLOCAL(security_descriptor.0)));
+            return Err(CustomResult::error(Some(String::from("提取DACL失败")), None));
+        }
+
+        // 注册表对象名需要以 MACHINE\ 开头
+        let object_name = HSTRING::from(format!("MACHINE\\{}", SECURE_REG_PATH));
+        let set_result = SetNamedSecurityInfoW(
+            &object_name,
+            SE_REGISTRY_KEY,
+            DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION,
+            None,
+            None,
+            Some(dacl),
+            None,
+        );
+
+        LocalFree(Some(HLOCAL(security_descriptor.0)));
+
+        if set_result != WIN32_ERROR(0) {
+            return Err(CustomResult::error(
+                Some(format!("设置密钥子项权限失败，错误码: {}", set_result.0)),
+                None,
+            ));
+        }
+    }
+    Ok(())
+}
@@ -1,4 +1,9 @@
-use std::{os::windows::process::CommandExt, process::Command};
+use std::{
+    os::windows::process::CommandExt,
+    process::Command,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
 
 use crate::{
     modules::options::{write_to_registry, RegistryItem},
@@ -10,12 +15,16 @@ use opencv::{
     objdetect::{FaceDetectorYN, FaceRecognizerSF},
     videoio::{self, VideoCapture, VideoCaptureTrait, VideoCaptureTraitConst},
 };
+use protocol::{
+    dispatch::{request_reload, request_status, request_unlock, subscribe_events}, pipe, AttemptTimer, Message, RecognitionEvent, ReloadOutcome, Stage, UnlockOutcome, EVENT_PIPE_NAME,
+    SERVER_PIPE_NAME, UNLOCK_PIPE_NAME,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tauri::{AppHandle, Manager};
+use tauri::{AppHandle, Emitter, Manager};
 use tauri_plugin_log::log::{error, info, warn};
 use windows::{
-    core::{BSTR, HSTRING, PWSTR},
+    core::{BSTR, PWSTR},
     Win32::{
         Foundation::{E_UNEXPECTED, HWND},
         Media::{
@@ -35,8 +44,6 @@ use windows::{
     },
 };
 
-use super::pipe::Client;
-
 #[derive(Debug, Clone, Serialize)]
 struct ValidCameraInfo {
     camera_name: String,
@@ -99,9 +106,22 @@ pub fn test_win_logon(user_name: String, password: String) -> Result<CustomResul
 
         // 等待5秒
         std::thread::sleep(std::time::Duration::from_secs(5));
-        // 解锁
-        unlock(user_name, password)
-            .map_err(|e| CustomResult::error(Some(format!("解锁屏幕失败: {:?}", e)), None))?;
+        // 解锁，管道不通时直接失败
+        let outcome = unlock(user_name, password)
+            .map_err(|e| CustomResult::error(Some(format!("解锁屏幕失败，管道不可用: {:?}", e)), None))?;
+
+        // 管道是通的，但密码不一定正确
+        if !outcome.is_success() {
+            let reason = match &outcome {
+                UnlockOutcome::Rejected { reason } => format!("凭据提供程序拒绝了解锁请求：{}", reason),
+                UnlockOutcome::Logon { ntstatus } => format!("密码验证失败，请检查账户密码（NTSTATUS 0x{:08X}）", *ntstatus as u32),
+                _ => String::from("未收到登录结果，请确认锁屏界面已加载 FaceWinUnlock-Tauri"),
+            };
+            return Err(CustomResult::error(
+                Some(format!("管道连接正常，但解锁失败：{}", reason)),
+                Some(json!({ "pipe": true, "result": outcome.to_string() })),
+            ));
+        }
 
         // 连接成功，允许连接
         write_to_registry(vec![RegistryItem {
@@ -488,7 +508,7 @@ pub fn check_scheduled_task(task_name: String) -> Result<CustomResult, CustomRes
 
 #[tauri::command]
 pub fn check_process_running() -> Result<CustomResult, CustomResult> {
-    let client = Client::new(HSTRING::from(r"\\.\pipe\MansonWindowsUnlockRustUnlock"));
+    let client = pipe::connect(UNLOCK_PIPE_NAME, None);
     if client.is_err() {
         return Err(CustomResult::error(
             Some(format!("pipe错误: {}", client.err().unwrap())),
@@ -496,8 +516,8 @@ pub fn check_process_running() -> Result<CustomResult, CustomResult> {
         ));
     }
 
-    let client = client.unwrap();
-    if let Err(e) = crate::utils::pipe::write(client.handle, String::from("hello server")) {
+    let mut client = client.unwrap();
+    if let Err(e) = client.write(&Message::Ping) {
         return Err(CustomResult::error(
             Some(format!("向客户端写入数据失败: {:?}", e)),
             None,
@@ -507,9 +527,103 @@ pub fn check_process_running() -> Result<CustomResult, CustomResult> {
     Ok(CustomResult::success(None, None))
 }
 
+// 查询解锁服务的运行状态
+#[tauri::command]
+pub fn get_service_status() -> Result<CustomResult, CustomResult> {
+    let client = pipe::connect(UNLOCK_PIPE_NAME, None);
+    if client.is_err() {
+        return Err(CustomResult::error(
+            Some(format!("pipe错误: {}", client.err().unwrap())),
+            None,
+        ));
+    }
+
+    let mut client = client.unwrap();
+    let status = request_status(&mut client).map_err(|e| {
+        CustomResult::error(Some(format!("查询服务状态失败: {}", e)), None)
+    })?;
+
+    let value = serde_json::to_value(status).map_err(|e| {
+        CustomResult::error(Some(format!("序列化服务状态失败: {}", e)), None)
+    })?;
+
+    Ok(CustomResult::success(None, Some(value)))
+}
+
+// 保存配置后让解锁服务重新加载识别配置
+// 有不合法的配置时服务保持原配置，返回错误，data 中是不合法的配置列表
+#[tauri::command]
+pub fn reload_service_options() -> Result<CustomResult, CustomResult> {
+    let client = pipe::connect(UNLOCK_PIPE_NAME, None);
+    if client.is_err() {
+        return Err(CustomResult::error(
+            Some(format!("pipe错误: {}", client.err().unwrap())),
+            None,
+        ));
+    }
+
+    let mut client = client.unwrap();
+    let outcome = request_reload(&mut client).map_err(|e| {
+        CustomResult::error(Some(format!("重新加载配置失败: {}", e)), None)
+    })?;
+
+    match outcome {
+        ReloadOutcome::Applied { options } => Ok(CustomResult::success(None, Some(json!(options)))),
+        ReloadOutcome::Invalid { errors } => Err(CustomResult::error(
+            Some(format!("{} 个配置不合法，解锁服务继续使用原配置", errors.len())),
+            Some(json!(errors)),
+        )),
+        ReloadOutcome::Failed { reason } => Err(CustomResult::error(
+            Some(format!("解锁服务读取配置失败: {}", reason)),
+            None,
+        )),
+    }
+}
+
+// 是否已经在转发识别事件，避免重复订阅
+static EVENTS_SUBSCRIBED: AtomicBool = AtomicBool::new(false);
+
+// 订阅 Unlock 服务的识别事件，收到的事件以 recognition-event 转发给前端
+// 管道断开时发出 recognition-event-closed，前端可以重新调用本命令
+#[tauri::command]
+pub fn subscribe_recognition_events(app_handle: AppHandle) -> Result<CustomResult, CustomResult> {
+    if EVENTS_SUBSCRIBED.swap(true, Ordering::SeqCst) {
+        return Ok(CustomResult::success(None, None));
+    }
+
+    let client = pipe::connect(EVENT_PIPE_NAME, None);
+    if client.is_err() {
+        EVENTS_SUBSCRIBED.store(false, Ordering::SeqCst);
+        return Err(CustomResult::error(
+            Some(format!("pipe错误: {}", client.err().unwrap())),
+            None,
+        ));
+    }
+
+    let mut client = client.unwrap();
+    // 识别事件可能很久才来一次，订阅连接不设读超时
+    client.transport_mut().set_timeout(None);
+    thread::spawn(move || {
+        let result = subscribe_events(&mut client, |event| {
+            // 各阶段耗时也写入 UI 日志，和 DLL、Unlock 服务的日志按尝试 ID 对照
+            if let RecognitionEvent::Timing { span } = &event {
+                info!("{}", span);
+            }
+            app_handle.emit("recognition-event", event).is_ok()
+        });
+        if let Err(e) = result {
+            info!("识别事件订阅已结束: {}", e);
+        }
+        EVENTS_SUBSCRIBED.store(false, Ordering::SeqCst);
+        let _ = app_handle.emit("recognition-event-closed", ());
+    });
+
+    Ok(CustomResult::success(None, None))
+}
+
 #[tauri::command]
 pub fn delete_process_running() -> Result<CustomResult, CustomResult> {
-    let client = Client::new(HSTRING::from(r"\\.\pipe\MansonWindowsUnlockRustUnlock"));
+    let client = pipe::connect(UNLOCK_PIPE_NAME, None);
     if client.is_err() {
         return Err(CustomResult::error(
             Some(format!("pipe错误: {}", client.err().unwrap())),
@@ -517,8 +631,8 @@ pub fn delete_process_running() -> Result<CustomResult, CustomResult> {
         ));
     }
 
-    let client = client.unwrap();
-    if let Err(e) = crate::utils::pipe::write(client.handle, String::from("exit")) {
+    let mut client = client.unwrap();
+    if let Err(e) = client.write(&Message::Exit) {
         return Err(CustomResult::error(
             Some(format!("向客户端写入数据失败: {:?}", e)),
             None,
@@ -820,19 +934,19 @@ fn is_camera_index_valid(index: u32) -> opencv::Result<bool> {
     Ok(is_valid)
 }
 
-// 解锁屏幕
-pub fn unlock(user_name: String, password: String) -> windows::core::Result<()> {
+// 解锁屏幕，返回凭据提供程序回复的最终结果
+pub fn unlock(user_name: String, password: String) -> windows::core::Result<UnlockOutcome> {
     {
         // 先连接服务管道
-        let client = Client::new(HSTRING::from(r"\\.\pipe\MansonWindowsUnlockRustServer"));
+        let client = pipe::connect(SERVER_PIPE_NAME, None);
         if client.is_err() {
             return Err(windows::core::Error::new(
                 E_UNEXPECTED,
                 format!("连接服务管道失败: {:?}", client.err()),
             ));
         }
-        let client = client.unwrap();
-        if let Err(e) = crate::utils::pipe::write(client.handle, format!("test_data")) {
+        let mut client = client.unwrap();
+        if let Err(e) = client.write(&Message::Ping) {
             return Err(windows::core::Error::new(
                 E_UNEXPECTED,
                 format!("向服务管道写入数据失败: {:?}", e),
@@ -841,26 +955,23 @@ pub fn unlock(user_name: String, password: String) -> windows::core::Result<()>
     }
 
     // 连接解锁管道，只要2个管道都存在，并且可以写入数据，就认为服务已启动
-    let client = Client::new(HSTRING::from(r"\\.\pipe\MansonWindowsUnlockRustUnlock"));
+    let client = pipe::connect(UNLOCK_PIPE_NAME, None);
     if client.is_err() {
         return Err(windows::core::Error::new(
             E_UNEXPECTED,
             format!("连接解锁管道失败: {:?}", client.err()),
         ));
     }
-    let client = client.unwrap();
-    if let Err(e) = crate::utils::pipe::write(
-        client.handle,
-        format!(
-            "unlockFromClient::{}::FaceWinUnlock::{}",
-            user_name, password
-        ),
-    ) {
-        return Err(windows::core::Error::new(
-            E_UNEXPECTED,
-            format!("向解锁管道写入数据失败: {:?}", e),
-        ));
-    }
+    let mut client = client.unwrap();
+    // 请求 ID 即尝试 ID，DLL 和 Unlock 服务的日志中也用它记录
+    let mut attempt = AttemptTimer::start();
+    info!("{} 发送解锁请求", attempt.mark(Stage::Trigger));
 
-    Ok(())
+    // 服务会等 DLL 回复登录结果后再转告过来
+    let outcome = request_unlock(&mut client, attempt.id(), user_name, password).map_err(|e| {
+        windows::core::Error::new(E_UNEXPECTED, format!("解锁请求 {} 失败: {}", attempt.id(), e))
+    })?;
+    info!("{} 结果：{}", attempt.mark(Stage::LogonResult), outcome);
+
+    Ok(outcome)
 }
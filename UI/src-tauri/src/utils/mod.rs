@@ -1,3 +1,2 @@
 pub mod api;
 pub mod custom_result;
-pub mod pipe;
\ No newline at end of file
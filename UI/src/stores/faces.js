@@ -2,6 +2,7 @@ import { defineStore } from 'pinia';
 import { select, insert, update, deleteData } from '../utils/sqlite';
 import { formatObjectString, getCurrentDateTime, removeFace } from '../utils/function'
 import { info, error as errorLog, warn } from '@tauri-apps/plugin-log';
+import { invoke } from '@tauri-apps/api/core';
 
 export const useFacesStore = defineStore('faces', {
     actions: {
@@ -12,6 +13,7 @@ export const useFacesStore = defineStore('faces', {
                         const item = result.rows[i];
                         this.addFaceToList(item);
                     }
+                    this.syncEnrolledUsers();
                     resolve();
                 }).catch((error)=>{
                     errorLog(formatObjectString("面容Store初始化失败：", error));
@@ -35,6 +37,7 @@ export const useFacesStore = defineStore('faces', {
                         createTime: getCurrentDateTime(),
                         ...data
                     });
+                    this.syncEnrolledUsers();
                     resolve();
                 }).catch((error)=>{
                     const info = formatObjectString("添加面容到数据库失败：", error);
@@ -43,9 +46,23 @@ export const useFacesStore = defineStore('faces', {
                 })
             });
         },
+        /**
+         * 从数据库读取面容最新的JSON信息
+         * Unlock 服务会直接改写其中的 suspended，本地列表里的可能已经过时
+         * @param {Number} id 面容ID
+         * @returns {Promise<Object>}
+         */
+        readFaceJsonData(id){
+            return select("faces", ["json_data"], "id = ?", [id]).then((result)=>{
+                if(result.rows.length == 0){
+                    return Promise.reject("未找到id: " + id + " 的面容信息");
+                }
+                return JSON.parse(result.rows[0].json_data);
+            });
+        },
         /**
          * 修改面容信息
-         * @param {Object} data 面容数据
+         * @param {Object} data 面容数据，json_data 只传要修改的键，其余键（包括服务写入的 suspended）保持数据库中的值
          * @param {Number} id 面容ID
          * @returns {Promise}
          */
@@ -59,22 +76,26 @@ export const useFacesStore = defineStore('faces', {
                     return;
                 }
 
-                update("faces", 
-                    {
-                        user_name: data.user_name, user_pwd: data.user_pwd, 
-                        account_type: data.account_type, face_token: data.face_token, 
-                        json_data: data.json_data
-                    },
-                    "id = ?",
-                    [id]
-                ).then(()=>{
-                    // 如果解析失败，直接返回失败，后续操作会throw error
-                    this.faceList[faceIndex].json_data = JSON.parse(data.json_data);
+                let json_data = null;
+                this.readFaceJsonData(id).then((current)=>{
+                    json_data = { ...current, ...data.json_data };
+                    return update("faces", 
+                        {
+                            user_name: data.user_name, user_pwd: data.user_pwd, 
+                            account_type: data.account_type, face_token: data.face_token, 
+                            json_data: JSON.stringify(json_data)
+                        },
+                        "id = ?",
+                        [id]
+                    );
+                }).then(()=>{
+                    this.faceList[faceIndex].json_data = json_data;
 
                     this.faceList[faceIndex].user_name = data.user_name;
                     this.faceList[faceIndex].user_pwd = data.user_pwd;
                     this.faceList[faceIndex].account_type = data.account_type;
                     this.faceList[faceIndex].face_token = data.face_token;
+                    this.syncEnrolledUsers();
                     resolve();
                 }).catch((error)=>{
                     const info = formatObjectString("修改面容到数据库失败：", error);
@@ -84,12 +105,12 @@ export const useFacesStore = defineStore('faces', {
             })
         },
         /**
-         * 修改面容JSON信息
-         * @param {String} json_data 面容json信息
+         * 修改面容JSON信息，先读取数据库中最新的JSON再合并，不会覆盖其他键
+         * @param {Object|Function} changes 要修改的键，或者根据最新JSON返回要修改的键的函数
          * @param {Number} id 面容ID
          * @returns {Promise}
          */
-        editFaceJsonData(json_data, id){
+        editFaceJsonData(changes, id){
             return new Promise((resolve, reject) => {
                 const faceIndex = this.faceList.findIndex(item => item.id == id);
                 if(faceIndex == -1){
@@ -99,8 +120,12 @@ export const useFacesStore = defineStore('faces', {
                     return;
                 }
 
-                update("faces", {json_data: json_data}, "id = ?", [id]).then(()=>{
-                    this.faceList[faceIndex].json_data = JSON.parse(json_data);
+                let json_data = null;
+                this.readFaceJsonData(id).then((current)=>{
+                    json_data = { ...current, ...(typeof changes == "function" ? changes(current) : changes) };
+                    return update("faces", {json_data: JSON.stringify(json_data)}, "id = ?", [id]);
+                }).then(()=>{
+                    this.faceList[faceIndex].json_data = json_data;
                     resolve();
                 }).catch((error)=>{
                     const info = formatObjectString("修改面容JSON数据到数据库失败：", error);
@@ -109,6 +134,31 @@ export const useFacesStore = defineStore('faces', {
                 })
             })
         },
+        /**
+         * 给面容添加一个模板（其他角度、戴眼镜等），只修改JSON信息，不需要重新输入密码
+         * @param {Number} id 面容ID
+         * @param {String} face_token 模板的特征和图片文件名
+         * @returns {Promise}
+         */
+        addFaceTemplate(id, face_token){
+            return this.editFaceJsonData((current)=>({
+                templates: [...(current.templates || []), face_token]
+            }), id);
+        },
+        /**
+         * 删除面容的一个模板，并删除模板的特征和图片
+         * @param {Number} id 面容ID
+         * @param {String} face_token 模板的特征和图片文件名
+         * @returns {Promise}
+         */
+        removeFaceTemplate(id, face_token){
+            return this.editFaceJsonData((current)=>({
+                templates: (current.templates || []).filter(item => item != face_token)
+            }), id).then(()=>{
+                // 删除失败不影响系统运行
+                removeFace(face_token, "删除模板");
+            });
+        },
         /**
          * 传入面容ID 获取面容信息
          * @param {Number} id 面容id
@@ -136,6 +186,26 @@ export const useFacesStore = defineStore('faces', {
             }
             return '无';
         },
+        /**
+         * 把已录入面容的账户写入注册表，DLL 据此把面容解锁放到这些用户的磁贴下
+         * 同时生成磁贴头像，写入失败不影响面容数据，DLL 会退回到单独的磁贴和软件图标
+         */
+        syncEnrolledUsers(){
+            const names = [...new Set(this.faceList.map(item => item.user_name).filter(name => name))];
+            invoke("write_to_registry", {items: [
+                { key: "ENROLLED_USERS", value: names.join("|") }
+            ]}).catch((error)=>{
+                warn(formatObjectString("写入已录入面容的账户失败：", error));
+            });
+            // 同步登录界面磁贴上显示的头像
+            const pictures = this.faceList.filter(item => item.user_name && item.face_token).map(item => ({
+                user_name: item.user_name,
+                face_token: item.face_token
+            }));
+            invoke("sync_tile_pictures", {pictures}).catch((error)=>{
+                warn(formatObjectString("同步磁贴头像失败：", error));
+            });
+        },
         /**
          * 添加面容信息到本地列表
          * @param {Object} data 面容数据（与数据库数据一致）
@@ -168,7 +238,11 @@ export const useFacesStore = defineStore('faces', {
                 deleteData("faces", "id = ?", [id]).then(()=>{
                     // 面容特征和图片删除失败不影响系统运行
                     removeFace(this.faceList[faceIndex].face_token);
+                    for(const token of this.faceList[faceIndex].json_data.templates || []){
+                        removeFace(token, "删除模板");
+                    }
                     this.faceList.splice(faceIndex, 1);
+                    this.syncEnrolledUsers();
                     resolve();
                 }).catch((error)=>{
                     const info = formatObjectString("从数据库删除面容失败：", error);
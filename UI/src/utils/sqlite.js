@@ -54,6 +54,10 @@ const databseTable = [
             { name: 'is_unlock', type: 'INTEGER', notNull: true },
             // 解锁失败时的截图
             { name: 'block_img', type: 'TEXT' },
+            // 解锁请求ID，与 DLL、服务日志对应
+            { name: 'request_id', type: 'TEXT' },
+            // 凭据提供程序回复的结果（accepted / rejected / ntstatus / timeout）
+            { name: 'result', type: 'TEXT' },
             // 上次更新时间
             { name: 'lastTime', type: 'TEXT', defaultValue: "datetime('now', 'localtime')" }
         ]
@@ -0,0 +1,107 @@
+use std::{sync::{Arc, Mutex}, time::Duration};
+
+use log::{error, info, warn};
+use protocol::{
+    auth::EVENT_PIPE_CLIENTS, dispatch::Flow, pipe::{PipeListener, PipeStream, PIPE_INSTANCES}, AttemptTimer, Channel, Message,
+    RecognitionEvent, Role, Stage, EVENT_PIPE_NAME,
+};
+
+use crate::global::SHUTDOWN;
+
+// 推送事件的写超时（毫秒），订阅者卡住时不能拖慢识别
+const EVENT_WRITE_TIMEOUT_MS: u64 = 200;
+
+// 同时保留的订阅者上限，每个订阅者占用一个管道实例，至少留出两个实例接受新的连接
+const MAX_SUBSCRIBERS: usize = PIPE_INSTANCES as usize - 2;
+
+type Subscriber = Arc<Mutex<Channel<PipeStream>>>;
+
+lazy_static::lazy_static! {
+    // 当前订阅识别事件的连接
+    static ref SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+}
+
+// 推送一条识别事件，没有订阅者时什么都不做
+//
+// 只在锁内复制订阅者列表，写入在锁外进行，卡住的订阅者不会阻塞新的订阅和其他线程的推送
+pub fn publish(event: RecognitionEvent) {
+    let subscribers = SUBSCRIBERS.lock().unwrap().clone();
+    if subscribers.is_empty() {
+        return;
+    }
+
+    let message = Message::Event { event };
+    let mut failed = Vec::new();
+    for subscriber in subscribers {
+        // 同一个连接上的写入必须串行，计数器才能对上
+        let result = subscriber.lock().unwrap().write(&message);
+        if let Err(e) = result {
+            // 写失败说明订阅者已经断开或卡住，丢掉这个连接
+            info!("识别事件订阅者已断开：{}", e);
+            failed.push(subscriber);
+        }
+    }
+
+    if !failed.is_empty() {
+        SUBSCRIBERS.lock().unwrap().retain(|subscriber| !failed.iter().any(|f| Arc::ptr_eq(f, subscriber)));
+    }
+}
+
+// 加入一个订阅者，超过上限时断开最早的订阅者（通常是 UI 重新订阅前留下的旧连接）
+fn subscribe(channel: Channel<PipeStream>) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    while subscribers.len() >= MAX_SUBSCRIBERS {
+        subscribers.remove(0);
+        info!("识别事件订阅者超过 {} 个，已断开最早的订阅者", MAX_SUBSCRIBERS);
+    }
+    subscribers.push(Arc::new(Mutex::new(channel)));
+}
+
+// 记录本次尝试到达某个阶段，写入日志并推送给订阅者
+pub fn mark(attempt: &mut AttemptTimer, stage: Stage) {
+    let span = attempt.mark(stage);
+    info!("{}", span);
+    publish(RecognitionEvent::Timing { span });
+}
+
+// 事件管道线程：接受订阅，连接保存在 SUBSCRIBERS 中由 publish 使用
+pub fn event_pipe_loop() {
+    let listener = match PipeListener::bind(EVENT_PIPE_NAME, &SHUTDOWN) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("事件管道创建失败：{:?}", e);
+            return;
+        }
+    };
+
+    let result = listener.serve(|stream| {
+        if let Err(e) = stream.authorize(EVENT_PIPE_CLIENTS) {
+            warn!("事件管道客户端校验失败，已拒绝连接：{:?}", e);
+            return Flow::Continue;
+        }
+        let mut channel = match stream.handshake(Role::Server) {
+            Ok(channel) => channel,
+            Err(e) => {
+                warn!("事件管道握手失败，已拒绝连接：{:?}", e);
+                return Flow::Continue;
+            }
+        };
+
+        match channel.read() {
+            Ok(Message::Subscribe) => {
+                info!("UI 已订阅识别事件");
+                channel.transport_mut().set_timeout(Some(Duration::from_millis(EVENT_WRITE_TIMEOUT_MS)));
+                subscribe(channel);
+            }
+            Ok(_) => warn!("事件管道上收到了非订阅消息，已断开"),
+            Err(e) => warn!("读取订阅请求失败：{:?}", e),
+        }
+        Flow::Continue
+    });
+    if let Err(e) = result {
+        error!("事件管道监听失败：{:?}", e);
+    }
+
+    SUBSCRIBERS.lock().unwrap().clear();
+    info!("事件管道线程安全卸载完成");
+}
@@ -1,15 +1,16 @@
-use std::{io::Read, path::PathBuf, sync::atomic::Ordering, thread::sleep, time::Duration};
+use std::{collections::HashMap, io::Read, path::PathBuf, sync::atomic::Ordering, thread::sleep, time::Duration};
 
 use log::{error, info, warn};
 use opencv::{
     core::{Mat, MatTrait, MatTraitConst, MatTraitConstManual, Point2f, Ptr, Scalar, Size, Vector}, dnn::{NetTrait, NetTraitConst}, imgproc, objdetect::{FaceDetectorYN, FaceRecognizerSF, FaceRecognizerSF_DisType}, prelude::{FaceDetectorYNTrait, FaceRecognizerSFTrait, FaceRecognizerSFTraitConst}, videoio::{self, VideoCapture, VideoCaptureTrait, VideoCaptureTraitConst}
 };
+use protocol::{dispatch::{request_credentials, send_tile_status}, options::parse_options, pipe, AttemptTimer, Health, LivenessScore, RecognitionEvent, RecognitionOptions, ReloadOutcome, Stage, TemplateScore, TileStatus, UnlockOutcome, LSA_FAILURE_REG_PATH, SERVER_PIPE_NAME};
 use serde::{Deserialize, Serialize};
-use windows::{core::HSTRING, Win32::Foundation::E_UNEXPECTED};
+use windows::Win32::Foundation::E_UNEXPECTED;
 
 use crate::{global::{
-    get_face_aligned_mode, get_global_log_path, set_face_aligned_mode, set_face_recognition_mode, CAMERA_INDEX, DB_POOL, FACE_RECOG_DELAY, IS_RUN, LIVENESS_ENABLE, LIVENESS_THRESHOLD, MATCH_FAIL_COUNT, MAX_FAIL, MAX_SUCCESS, NOT_FACE_DELAY, RETRY_DELAY
-}, pipe::Client, utils::{save_mat_as_faceimg, set_last_send_time}};
+    get_db_pool, get_global_log_path, get_options, set_camera_state, set_options, FACE_UNLOCKED, IS_RUN, MATCH_FAIL_COUNT, MAX_FAIL, MAX_RETRY, MAX_SUCCESS
+}, events::{mark, publish}, utils::{delete_registry_tree, save_mat_as_faceimg, set_last_send_time}};
 
 // 定义摄像头后端类型枚举
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -43,8 +44,54 @@ pub struct FaceExtraData {
     // 是否锁定面容？为true时不参与判定
     #[serde(default)] // 0.2.0 以下版本的用户没有这一项，默认为false
     pub lock: bool,
+    // 保存的密码连续登录失败被暂停，用户手动登录成功后恢复
+    #[serde(default)]
+    pub suspended: bool,
     /// 人脸检测置信度阈值
     pub face_detection_threshold: f32,
+    /// 同一个面容其他角度、戴眼镜等情况下录入的模板，face_token 为第一个模板
+    #[serde(default)]
+    pub templates: Vec<String>,
+    /// 多个模板的得分如何合并
+    #[serde(default)]
+    pub aggregation: Aggregation,
+    /// aggregation 为 topk 时取最高的几个得分求平均
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+}
+
+fn default_top_k() -> usize {
+    2
+}
+
+/// 多个模板的得分合并为面容得分的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregation {
+    /// 取最高的得分（默认，任意一个模板匹配即可）
+    #[default]
+    Max,
+    /// 最高的 top_k 个得分的平均值，单个模板偶然的高分不会直接通过
+    TopK,
+    /// 与所有模板特征的平均值（质心）比较
+    Centroid,
+}
+
+impl Aggregation {
+    // 合并各模板的得分，质心只有一个得分，与 Max 相同；没有得分时为 0，不会匹配
+    fn combine(&self, mut scores: Vec<f64>, top_k: usize) -> f64 {
+        if scores.is_empty() {
+            return 0.0;
+        }
+        match self {
+            Aggregation::Max | Aggregation::Centroid => scores.into_iter().fold(f64::MIN, f64::max),
+            Aggregation::TopK => {
+                scores.sort_by(|a, b| b.total_cmp(a));
+                scores.truncate(top_k.max(1));
+                scores.iter().sum::<f64>() / scores.len() as f64
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -85,12 +132,10 @@ impl FaceDescriptor {
 
 // 刚锁屏时的预处理
 pub fn prepare_before() -> Result<(), String> {
-    let pool_guard = DB_POOL.lock().unwrap();
-    let pool = pool_guard.as_ref();
-    if pool.is_none() {
+    let Some(pool) = get_db_pool() else {
         return Err(String::from("数据库连接池未初始化，无法进行面容识别"));
-    }
-    let conn = pool.unwrap().get().map_err(|e| e.to_string())?;
+    };
+    let conn = pool.get().map_err(|e| e.to_string())?;
     let result = conn
         .query_row("SELECT COUNT(id) as count FROM faces;", [], |row| {
             row.get::<&str, i32>("count")
@@ -114,145 +159,148 @@ pub fn prepare_before() -> Result<(), String> {
             return Err("程序未初始化，无法进行面容识别".to_string());
         }
 
-        // 获取面容识别的类型
-        let face_recog_type = conn
-            .query_row(
-                "SELECT val FROM options WHERE key = 'faceRecogType'",
-                (),
-                |row| row.get::<&str, String>("val"),
-            )
-            .unwrap_or(String::from("operation"));
-
-        if face_recog_type != "operation" {
-            // 如果是按延迟时间，获取延迟时间
-            let time = conn
-                .query_row(
-                    "SELECT val FROM options WHERE key = 'faceRecogDelay';",
-                    [],
-                    |row| row.get::<&str, String>("val"),
-                )
-                .unwrap_or(String::from("10.0"));
-
-            let time_ms: f32 = match time.parse::<f32>() {
-                Ok(seconds) => seconds * 1000.0,
-                Err(e) => {
-                    warn!("秒数字符串转换失败: {}，使用默认值 10000 毫秒", e);
-                    10.0 * 1000.0
+        // 读取识别配置，有不合法的值时保持原配置，并在日志中指出
+        match apply_options(&conn) {
+            ReloadOutcome::Applied { .. } => {}
+            ReloadOutcome::Invalid { errors } => {
+                for e in errors {
+                    warn!("配置 {} 的值 {:?} 不合法（{}），继续使用原配置", e.key, e.value, e.reason);
                 }
-            };
-
-            FACE_RECOG_DELAY.store(time_ms as u32, Ordering::SeqCst);
-        }
-
-        // 设置识别模式
-        set_face_recognition_mode(face_recog_type);
-
-        // 读取摄像头索引
-        let camera_index = conn
-            .query_row("SELECT val FROM options WHERE key = 'camera';", [], |row| {
-                row.get::<&str, String>("val")
-            })
-            .unwrap_or(String::from("0"));
-
-        CAMERA_INDEX.store(camera_index.parse().unwrap_or(0), Ordering::SeqCst);
-
-        // 获取未检测到人脸时多少秒停止面容识别
-        let time = conn
-            .query_row(
-                "SELECT val FROM options WHERE key = 'notFaceDelay';",
-                [],
-                |row| row.get::<&str, String>("val"),
-            )
-            .unwrap_or(String::from("3"));
-        NOT_FACE_DELAY.store((time.parse().unwrap_or(3)) * 2, Ordering::SeqCst);
-
-        // 获取重试时间
-        let time = conn
-            .query_row(
-                "SELECT val FROM options WHERE key = 'retryDelay';",
-                [],
-                |row| row.get::<&str, String>("val"),
-            )
-            .unwrap_or(String::from("10.0"));
-
-        let time_ms: f32 = match time.parse::<f32>() {
-            Ok(seconds) => seconds * 1000.0,
-            Err(e) => {
-                warn!("秒数字符串转换失败: {}，使用默认值 10000 毫秒", e);
-                10.0 * 1000.0
             }
-        };
-        RETRY_DELAY.store(time_ms as i32, Ordering::SeqCst);
-
-        // 是否进行活体检测
-        let liveness_enabled = conn
-            .query_row("SELECT val FROM options WHERE key = 'livenessEnabled';", [], |row| {
-                row.get::<&str, String>("val")
-            })
-            .unwrap_or(String::from("false"));
-        LIVENESS_ENABLE.store(liveness_enabled == "true", Ordering::SeqCst);
-
-        // 活体检测阈值
-        let liveness_threshold = conn
-            .query_row(
-                "SELECT val FROM options WHERE key = 'livenessThreshold';",
-                [],
-                |row| row.get::<&str, String>("val"),
-            )
-            .unwrap_or(String::from("0.50"));
-        LIVENESS_THRESHOLD.store((liveness_threshold.parse().unwrap_or(0.5) * 100.0) as u32, Ordering::SeqCst);
-
-        // 获取面容对齐模式
-        let face_aligned_type = conn
-            .query_row(
-                "SELECT val FROM options WHERE key = 'faceAlignedType'",
-                (),
-                |row| row.get::<&str, String>("val"),
-            )
-            .unwrap_or(String::from("default"));
-        set_face_aligned_mode(face_aligned_type);
+            ReloadOutcome::Failed { reason } => warn!("读取识别配置失败：{}，继续使用原配置", reason),
+        }
     }
 
     Ok(())
 }
 
+// 读取 options 表中的识别配置，全部合法时整体替换当前配置
+fn apply_options(conn: &r2d2_sqlite::rusqlite::Connection) -> ReloadOutcome {
+    let values = conn
+        .prepare("SELECT key, val FROM options;")
+        .and_then(|mut stmt| {
+            let rows = stmt.query_map([], |row| Ok((row.get::<&str, String>("key")?, row.get::<&str, String>("val")?)))?;
+            rows.collect::<Result<HashMap<String, String>, _>>()
+        });
+    let values = match values {
+        Ok(values) => values,
+        Err(e) => return ReloadOutcome::Failed { reason: format!("查询数据库失败：{:?}", e) },
+    };
+
+    match parse_options(|key| values.get(key).cloned()) {
+        Ok(options) => {
+            set_options(options.clone());
+            ReloadOutcome::Applied { options }
+        }
+        Err(errors) => ReloadOutcome::Invalid { errors },
+    }
+}
+
+// UI 修改配置后重新加载，不用等到下次锁屏
+pub fn reload_options() -> ReloadOutcome {
+    let Some(pool) = get_db_pool() else {
+        return ReloadOutcome::Failed { reason: String::from("数据库连接池未初始化") };
+    };
+    match pool.get() {
+        Ok(conn) => apply_options(&conn),
+        Err(e) => ReloadOutcome::Failed { reason: e.to_string() },
+    }
+}
+
 // 开始面容识别
-pub fn run_before() {
+pub fn run_before(mut attempt: AttemptTimer) {
+    // 整个识别过程使用同一份配置，中途重新加载不影响本次识别
+    let options = get_options();
     // 先打开摄像头
-    match open_camera(None, CAMERA_INDEX.load(Ordering::SeqCst)) {
+    match open_camera(None, options.camera_index) {
         Ok(camera) => {
             // 摄像头成功打开
-            if let Err(e) = run(camera) {
-                error!("运行面容解锁失败: {:?}", e);
+            mark(&mut attempt, Stage::CameraOpened);
+            show_tile_status(TileStatus::Scanning);
+            set_camera_state(Health::ok(format!("摄像头 {}", options.camera_index)));
+            publish(RecognitionEvent::Started { attempt_id: attempt.id().to_string(), camera_index: options.camera_index });
+            let (matched, message) = match run(camera, &options, &mut attempt) {
+                Ok(RunOutcome::Matched(outcome)) if outcome.is_success() => (true, String::from("面容匹配成功")),
+                Ok(RunOutcome::Matched(outcome)) => (false, format!("面容匹配成功，但未能登录：{}", outcome)),
+                Ok(RunOutcome::NotMatched) => (false, String::from("面容匹配失败")),
+                Ok(RunOutcome::NoEligibleFaces) => (false, String::from("没有可参与识别的面容（未录入或已全部暂停）")),
+                Err(e) => {
+                    error!("[attempt {}] 运行面容解锁失败: {:?}", attempt.id(), e);
+                    (false, e)
+                }
             };
+            mark(&mut attempt, Stage::Finished);
+            publish(RecognitionEvent::Finished { matched, message });
             set_last_send_time();
             IS_RUN.store(false, Ordering::SeqCst);
         }
         Err(e) => {
-            error!("打开摄像头失败 {}", e);
+            error!("[attempt {}] 打开摄像头失败 {}", attempt.id(), e);
+            set_camera_state(Health::failed(format!("打开摄像头失败 {}", e)));
+            mark(&mut attempt, Stage::Finished);
+            publish(RecognitionEvent::Finished { matched: false, message: format!("打开摄像头失败 {}", e) });
             IS_RUN.store(false, Ordering::SeqCst);
         }
     }
 }
 
-// 解锁屏幕
-pub fn unlock(user_name: String, password: String) -> windows::core::Result<()> {
-    let client = Client::new(HSTRING::from(r"\\.\pipe\MansonWindowsUnlockRustServer"));
-    if client.is_err() {
+// 解锁屏幕，返回 DLL 回复的最终结果，尝试 ID 作为请求 ID
+pub fn unlock(attempt: &mut AttemptTimer, user_name: String, password: String) -> windows::core::Result<UnlockOutcome> {
+    let channel = pipe::connect(SERVER_PIPE_NAME, None);
+    if channel.is_err() {
         return Err(windows::core::Error::new(E_UNEXPECTED, "管道不存在"));
     }
-    let client = client.unwrap();
-    if let Err(e) = crate::pipe::write(client.handle, format!("{}::FaceWinUnlock::{}", user_name, password)) {
-        println!("向客户端写入数据失败: {:?}", e);
+    let mut channel = channel.unwrap();
+
+    // 先收到是否受理，受理后再收到 LSA 的登录结果
+    let outcome = request_credentials(&mut channel, attempt, user_name, password)
+        .map_err(|e| windows::core::Error::new(E_UNEXPECTED, e.to_string()))?;
+    mark(attempt, Stage::LogonResult);
+    Ok(outcome)
+}
+
+// 更新锁屏磁贴上的识别状态，DLL 不在时（未锁屏）忽略
+pub fn show_tile_status(status: TileStatus) {
+    let result = pipe::connect(SERVER_PIPE_NAME, None)
+        .and_then(|mut channel| send_tile_status(&mut channel, status.clone()));
+    if let Err(e) = result {
+        warn!("更新磁贴状态 {:?} 失败：{}", status, e);
     }
+}
 
-    Ok(())
+// 补全账户名的前缀，DLL 据此拆出域名和用户名
+// 已经带了前缀（DOMAIN\user、AzureAD\user）的原样发送
+fn qualified_user_name(account_type: &str, user_name: String) -> String {
+    if user_name.contains('\\') {
+        return user_name;
+    }
+    match account_type {
+        // 微软账户
+        "online" => format!("MicrosoftAccount\\{}", user_name),
+        // 本地账户；user@domain 形式的 UPN 交给 LSA 解析
+        _ if user_name.contains('@') => user_name,
+        _ => format!(".\\{}", user_name),
+    }
+}
+
+// 一轮识别的结果
+enum RunOutcome {
+    // 有面容匹配成功，附带 DLL 回复的登录结果，登录不一定成功
+    Matched(UnlockOutcome),
+    // 所有面容都判定为不匹配
+    NotMatched,
+    // 没有可参与判定的面容，没有进行识别
+    NoEligibleFaces,
 }
 
 // 面容识别主程序
-fn run(mut camera: VideoCapture) -> Result<bool, String> {
+fn run(mut camera: VideoCapture, options: &RecognitionOptions, attempt: &mut AttemptTimer) -> Result<RunOutcome, String> {
     // 未检测到人脸的次数
     let mut not_face_count = 0;
+    // 是否已经检测到过人脸，只记录第一次
+    let mut face_seen = false;
+    // 最近一帧是否因为活体检测失败而判定为失败
+    let mut liveness_failed = false;
     // 加载模型
     let resource_path = get_global_log_path()
         .join("resources")
@@ -282,191 +330,198 @@ fn run(mut camera: VideoCapture) -> Result<bool, String> {
     let mut liveness_net = opencv::dnn::read_net_from_onnx(resource_path.to_str().unwrap_or(""))
             .map_err(|e| format!("初始化活体检测模型失败: {:?}", e))?;
 
-    let pool_guard = DB_POOL.lock().unwrap();
-    let pool = pool_guard.as_ref();
-    if pool.is_none() {
+    // 只拿连接池的副本，识别期间不占用 DB_POOL 的锁
+    let Some(pool) = get_db_pool() else {
         return Err(String::from("数据库连接池未初始化，无法进行面容识别"));
+    };
+    let conn = pool.get().map_err(|e| e.to_string())?;
+
+    // 所有参与判定的面容，每一帧只检测、提取一次特征，再与它们逐一比较
+    let mut gallery = load_gallery(&conn)?;
+    if gallery.is_empty() {
+        // 没有面容可比较，不算一次匹配失败，也不用通知 DLL 解锁失败
+        warn!("[attempt {}] 没有可参与识别的面容", attempt.id());
+        show_tile_status(TileStatus::Idle);
+        return Ok(RunOutcome::NoEligibleFaces);
     }
-    let conn = pool.unwrap().get().map_err(|e| e.to_string())?;
-
-    // 获取面容数据
-    let mut faces = conn
-        .prepare("SELECT * FROM faces;")
-        .map_err(|e| format!("准备查询面容数据失败：{:?}", e))?;
-    let rows = faces
-        .query_map([], |row| {
-            // 读取基础字段
-            let id = row.get::<&str, i32>("id")?;
-            let user_name = row.get::<&str, String>("user_name")?;
-            let user_pwd = row.get::<&str, String>("user_pwd")?;
-            let account_type = row.get::<&str, String>("account_type")?;
-            let face_token = row.get::<&str, String>("face_token")?;
-            let json_data_str = row.get::<&str, String>("json_data")?;
-            let create_time = row.get::<&str, String>("createTime")?;
-
-            // 解析 JSON 字符串为结构体
-            let json_data: FaceExtraData = serde_json::from_str(&json_data_str)
-                .map_err(|_e| r2d2_sqlite::rusqlite::Error::ExecuteReturnedResults)?;
-
-            // 返回
-            Ok((
-                id,
-                user_name,
-                user_pwd,
-                account_type,
-                face_token,
-                json_data,
-                create_time,
-            ))
-        })
-        .map_err(|e| format!("查询面容数据失败：{:?}", e))?;
+    // 人脸检测按最宽松的阈值进行，再按各面容自己的阈值过滤
+    let detection_threshold = gallery
+        .iter()
+        .map(|face| face.extra.face_detection_threshold)
+        .fold(f32::INFINITY, f32::min);
 
     let mut frame = Mat::default();
 
-    'face: for row in rows {
-        let (id, user_name, user_pwd, account_type, mut face_token, json_data, _create_time) =
-            row.map_err(|e| format!("获取1条面容数据失败：{:?}", e))?;
-
-        if json_data.lock {
-            // 锁定了账户，直接跳过
-            continue;
-        }
-
-        // 加载数据
-        face_token.push_str(".face");
-        let path = get_global_log_path().join("faces").join(face_token);
-        // 解析面容数据
-        let face = load_face_data(&path);
-        if face.is_err() {
-            error!("加载面容数据失败：{:?}", path);
-            continue;
-        }
-
-        let face = face.unwrap();
-        // 参考面容转换失败，跳过当前用户
-        let dst_feature = face.to_mat();
-        if dst_feature.is_err() {
-            error!("{}, 转换参考面容数据失败：{:?}", json_data.alias, path);
-            continue;
-        }
-        let dst_feature = dst_feature.unwrap();
-
-        let mut success_count = 0;
-        let mut fail_count = 0;
-
-        loop {
-            // 读取一帧，摄像头的操作一旦失败，必须退出函数
-            frame =
-                read_mat_from_camera(&mut camera).map_err(|e| format!("摄像头读取失败: {}", e))?;
-            // 提取特征点
-            let (aligned, cur_feature, face_range) = match get_feature(
-                &frame,
-                json_data.face_detection_threshold,
-                &mut detector,
-                &mut recognizer,
-            ) {
-                Ok(feature) => feature,
-                Err(e) => {
-                    let err_msg = format!("特征提取失败: {}", e);
-                    if err_msg.contains("未检测到人脸") {
-                        // 未检测到人脸不动
-                        sleep(Duration::from_millis(500));
-                        not_face_count += 1;
-                        if not_face_count >= NOT_FACE_DELAY.load(Ordering::SeqCst) {
-                            // 未检测到人脸超过指定时间，退出整个函数
-                            return Err(String::from("未检测到人脸超过指定时间, 停止面容识别"));
-                        }
-                        continue;
-                    } else {
-                        // 其他错误退出整个函数
-                        return Err(err_msg);
+    // 所有面容都连续失败 MAX_FAIL 次时结束
+    while gallery.iter().any(|face| face.fail_count < MAX_FAIL) {
+        // 读取一帧，摄像头的操作一旦失败，必须退出函数
+        frame =
+            read_mat_from_camera(&mut camera).map_err(|e| format!("摄像头读取失败: {}", e))?;
+        // 提取特征点
+        let (aligned, cur_feature, face_range) = match get_feature(
+            &frame,
+            detection_threshold,
+            &mut detector,
+            &mut recognizer,
+        ) {
+            Ok(feature) => feature,
+            Err(e) => {
+                let err_msg = format!("特征提取失败: {}", e);
+                if err_msg.contains("未检测到人脸") {
+                    // 未检测到人脸不动
+                    sleep(Duration::from_millis(500));
+                    not_face_count += 1;
+                    publish(RecognitionEvent::NoFace { count: not_face_count, limit: options.not_face_limit });
+                    if not_face_count >= options.not_face_limit {
+                        // 未检测到人脸超过指定时间，退出整个函数
+                        show_tile_status(TileStatus::NoFace);
+                        return Err(String::from("未检测到人脸超过指定时间, 停止面容识别"));
                     }
-                }
-            };
-
-            // 如果启用了活体检测，进行活体检测
-            if LIVENESS_ENABLE.load(Ordering::SeqCst) {
-                // 图像预处理
-                let face_data = face_range.at_row::<f32>(0).unwrap();
-                let aligned_face = if get_face_aligned_mode() == "default" {
-                    align_face(&frame, face_data).map_err(|e| format!("对齐人脸失败: {}", e))?
+                    continue;
                 } else {
-                    aligned
-                };
-
-                let blob = opencv::dnn::blob_from_image(&aligned_face, 1.0/255.0, Size::new(128, 128), Scalar::all(0.0), true, false, opencv::core::CV_32F).map_err(|e| format!("创建 Blob 失败: {:?}", e))?;
-                liveness_net.set_input(&blob, "", 1.0, Scalar::default()).map_err(|e| format!("设置输入失败: {:?}", e))?;
-                let out_layer_names = liveness_net.get_unconnected_out_layers_names().map_err(|e| format!("获取输出层失败: {:?}", e))?;
-                let mut output_blobs = Vector::<Mat>::new();
-                liveness_net.forward(&mut output_blobs, &out_layer_names).map_err(|e| format!("执行推理失败: {:?}", e))?; 
-
-                let mut is_real = false;
-                let mut real_score = 0.0;
-
-                if !output_blobs.is_empty() {
-                    let liveness_threshold = LIVENESS_THRESHOLD.load(Ordering::SeqCst) as f32 / 100.0;
-                    let p = liveness_threshold.max(1e-6).min(1.0 - 1e-6);
-                    let logit_threshold = (p / (1.0 - p)).ln();
-
-                    let output = output_blobs.get(0).map_err(|_| format!("无输出"))?;
-                    let logits = output.at_row::<f32>(0).map_err(|e| format!("获取输出行失败: {:?}", e))?;
-                    real_score = logits[0] - logits[1];
-                    is_real = real_score >= logit_threshold;
+                    // 其他错误退出整个函数
+                    return Err(err_msg);
                 }
+            }
+        };
+        if !face_seen {
+            face_seen = true;
+            mark(attempt, Stage::FirstFace);
+        }
+        // 第 15 列为人脸检测的置信度
+        let face_data = face_range.at_row::<f32>(0).map_err(|e| format!("获取人脸数据失败: {:?}", e))?;
+        let detection_score = face_data[14];
+
+        // 如果启用了活体检测，进行活体检测，与比较哪个面容无关，每帧只做一次
+        let mut liveness = None;
+        if options.liveness_enabled {
+            // 图像预处理
+            let aligned_face = if options.aligned_mode == "default" {
+                align_face(&frame, face_data).map_err(|e| format!("对齐人脸失败: {}", e))?
+            } else {
+                aligned
+            };
 
-                if !is_real {
-                    // 活体检测失败，可以直接退出外层循环，因为在往下匹配面容，也是失败的
-                    error!("活体检测失败，真实概率: {:.2}%", real_score * 100.0);
-                    break 'face;
+            let blob = opencv::dnn::blob_from_image(&aligned_face, 1.0/255.0, Size::new(128, 128), Scalar::all(0.0), true, false, opencv::core::CV_32F).map_err(|e| format!("创建 Blob 失败: {:?}", e))?;
+            liveness_net.set_input(&blob, "", 1.0, Scalar::default()).map_err(|e| format!("设置输入失败: {:?}", e))?;
+            let out_layer_names = liveness_net.get_unconnected_out_layers_names().map_err(|e| format!("获取输出层失败: {:?}", e))?;
+            let mut output_blobs = Vector::<Mat>::new();
+            liveness_net.forward(&mut output_blobs, &out_layer_names).map_err(|e| format!("执行推理失败: {:?}", e))?; 
+
+            let mut is_real = false;
+            let mut real_score = 0.0;
+            let liveness_threshold = options.liveness_threshold as f32 / 100.0;
+            let p = liveness_threshold.max(1e-6).min(1.0 - 1e-6);
+            let logit_threshold = (p / (1.0 - p)).ln();
+
+            if !output_blobs.is_empty() {
+                let output = output_blobs.get(0).map_err(|_| format!("无输出"))?;
+                let logits = output.at_row::<f32>(0).map_err(|e| format!("获取输出行失败: {:?}", e))?;
+                real_score = logits[0] - logits[1];
+                is_real = real_score >= logit_threshold;
+            }
+            liveness = Some(LivenessScore { score: real_score, threshold: logit_threshold, passed: is_real });
+
+            liveness_failed = !is_real;
+            if !is_real {
+                // 活体检测失败，与哪个面容比较都是失败的，本帧对所有面容计一次失败，继续识别
+                for face in gallery.iter_mut().filter(|face| face.fail_count < MAX_FAIL) {
+                    face.success_count = 0;
+                    face.fail_count += 1;
                 }
+                let (success_count, fail_count) = leading_counts(&gallery);
+                publish(RecognitionEvent::Frame {
+                    scores: Vec::new(),
+                    liveness,
+                    success_count,
+                    fail_count,
+                });
+                warn!("活体检测失败，真实概率: {:.2}%", real_score * 100.0);
+                sleep(Duration::from_millis(50));
+                continue;
             }
+        }
 
-            let score = {
-                recognizer
-                    .match_(
-                        &dst_feature,
-                        &cur_feature,
-                        FaceRecognizerSF_DisType::FR_COSINE.into(),
-                    )
-                    .map_err(|e| format!("特征匹配失败: {}", e))?
-            };
+        // 与每个还在判定中的面容比较，同时更新各自的连续成功 / 失败次数
+        let mut scores = Vec::new();
+        // 本帧达到成功次数的面容中得分最高的一个
+        let mut winner: Option<(usize, f64)> = None;
+        for (index, face) in gallery.iter_mut().enumerate() {
+            if face.fail_count >= MAX_FAIL || detection_score < face.extra.face_detection_threshold {
+                // 已经判定为不匹配，或人脸置信度达不到这个面容的要求，本帧不参与比较
+                continue;
+            }
+            let mut template_scores = Vec::with_capacity(face.features.len());
+            for feature in &face.features {
+                template_scores.push(
+                    recognizer
+                        .match_(
+                            feature,
+                            &cur_feature,
+                            FaceRecognizerSF_DisType::FR_COSINE.into(),
+                        )
+                        .map_err(|e| format!("特征匹配失败: {}", e))?,
+                );
+            }
+            let score = face.extra.aggregation.combine(template_scores, face.extra.top_k);
 
-            if score * 100.0 >= json_data.threshold.into() {
-                // 匹配成功，次数+1
-                success_count += 1;
-                if success_count >= MAX_SUCCESS {
-                    // 大于3次，算面容匹配成功
-                    let user_name = if account_type == "local" {
-                        format!(".\\{}", user_name)
-                    } else {
-                        user_name
-                    };
-
-                    if let Err(e) = unlock(user_name, user_pwd) {
-                        return Err(format!("调用解锁函数失败：{}", e));
-                    } else {
-                        if let Err(e) = insert_unlock_log(&conn, id, true, "") {
-                            warn!("插入解锁日志失败：{}", e);
-                        };
-                        info!("面容匹配成功，发送用户名密码");
-                        return Ok(true);
-                    }
+            let matched = score * 100.0 >= face.extra.threshold.into();
+            if matched {
+                face.success_count += 1;
+                if face.success_count >= MAX_SUCCESS && winner.is_none_or(|(_, best)| score > best) {
+                    winner = Some((index, score));
                 }
             } else {
-                success_count = 0;
-                fail_count += 1;
-                if fail_count >= MAX_FAIL {
-                    break;
+                face.success_count = 0;
+                face.fail_count += 1;
+            }
+            scores.push(TemplateScore {
+                face_id: face.id,
+                alias: face.extra.alias.clone(),
+                score: score as f32,
+                threshold: face.extra.threshold,
+                matched,
+            });
+        }
+        let (success_count, fail_count) = leading_counts(&gallery);
+        publish(RecognitionEvent::Frame { scores, liveness, success_count, fail_count });
+
+        if let Some((index, _)) = winner {
+            // 连续 MAX_SUCCESS 帧匹配，算面容匹配成功
+            let face = gallery.swap_remove(index);
+            let qualified_name = qualified_user_name(&face.account_type, face.user_name.clone());
+
+            mark(attempt, Stage::Matched);
+            show_tile_status(TileStatus::Unlocking);
+            info!("[attempt {}] 面容 {} 匹配成功，发送用户名密码", attempt.id(), face.extra.alias);
+            let outcome = unlock(attempt, qualified_name, face.user_pwd)
+                .map_err(|e| format!("调用解锁函数失败：{}", e))?;
+
+            if let UnlockOutcome::Suspended { failures, .. } = outcome {
+                // 保存的密码多半已经过期，暂停这个账户的面容，等用户手动登录
+                warn!("[attempt {}] 账户 {} 连续 {} 次登录失败，暂停它的面容", attempt.id(), face.user_name, failures);
+                if let Err(e) = set_faces_suspended(&conn, Some(&face.user_name), true) {
+                    error!("暂停面容失败：{}", e);
                 }
             }
+            if outcome.is_success() {
+                FACE_UNLOCKED.store(true, Ordering::SeqCst);
+            }
 
-            sleep(Duration::from_millis(50));
+            if let Err(e) = insert_unlock_log(&conn, face.id, outcome.is_success(), "", Some(attempt.id()), Some(&outcome.to_string())) {
+                warn!("插入解锁日志失败：{}", e);
+            };
+            if outcome.is_success() {
+                info!("[attempt {}] 登录成功", attempt.id());
+            } else {
+                // 登录被拒绝同样计入失败次数，避免反复提交；磁贴上的提示由 DLL 根据登录结果显示
+                warn!("[attempt {}] 未能登录：{}", attempt.id(), outcome);
+                MATCH_FAIL_COUNT.fetch_add(1, Ordering::SeqCst);
+            }
+            return Ok(RunOutcome::Matched(outcome));
         }
-    }
 
-    // 发个假的用户名密码，通知用户解锁失败
-    if let Err(e) = unlock(String::from("null"), String::from("null")) {
-        return Err(format!("调用解锁函数失败：{}", e));
+        sleep(Duration::from_millis(50));
     }
 
     let mut save_file = true;
@@ -490,15 +545,24 @@ fn run(mut camera: VideoCapture) -> Result<bool, String> {
         }
     }
 
-    if let Err(e) = insert_unlock_log(&conn, -1, false, if save_file { &img_name } else { "" }) {
+    if let Err(e) = insert_unlock_log(&conn, -1, false, if save_file { &img_name } else { "" }, Some(attempt.id()), None) {
         warn!("插入解锁日志失败：{}", e);
     };
-    warn!("面容匹配失败");
+    warn!("[attempt {}] 面容匹配失败", attempt.id());
     // 匹配失败，次数+1
     let now_count = MATCH_FAIL_COUNT.load(Ordering::SeqCst);
     MATCH_FAIL_COUNT.store(now_count + 1, Ordering::SeqCst);
 
-    Ok(false)
+    // 不匹配的结果只通过磁贴状态和识别事件（run_before 推送 Finished）告知用户，不向登录界面提交凭据
+    show_tile_status(if now_count + 1 >= MAX_RETRY {
+        TileStatus::Paused
+    } else if liveness_failed {
+        TileStatus::LivenessFailed
+    } else {
+        TileStatus::NotMatched { retries_left: (MAX_RETRY - now_count - 1) as u32 }
+    });
+
+    Ok(RunOutcome::NotMatched)
 }
 
 fn open_camera(backend: Option<CameraBackend>, camear_index: i32) -> Result<VideoCapture, String> {
@@ -638,14 +702,164 @@ fn get_feature(
     }
 }
 
+// 参与判定的一个面容，以及它在本次识别中的连续成功 / 失败次数
+struct GalleryFace {
+    id: i32,
+    user_name: String,
+    user_pwd: String,
+    account_type: String,
+    extra: FaceExtraData,
+    // 各模板的特征，合并方式为质心时只有一个
+    features: Vec<Mat>,
+    success_count: usize,
+    fail_count: usize,
+}
+
+// 读取所有未锁定、未暂停的面容，加载失败的面容跳过
+fn load_gallery(conn: &r2d2_sqlite::rusqlite::Connection) -> Result<Vec<GalleryFace>, String> {
+    let mut faces = conn
+        .prepare("SELECT * FROM faces;")
+        .map_err(|e| format!("准备查询面容数据失败：{:?}", e))?;
+    let rows = faces
+        .query_map([], |row| {
+            // 读取基础字段
+            let id = row.get::<&str, i32>("id")?;
+            let user_name = row.get::<&str, String>("user_name")?;
+            let user_pwd = row.get::<&str, String>("user_pwd")?;
+            let account_type = row.get::<&str, String>("account_type")?;
+            let face_token = row.get::<&str, String>("face_token")?;
+            let json_data_str = row.get::<&str, String>("json_data")?;
+
+            // 解析 JSON 字符串为结构体
+            let json_data: FaceExtraData = serde_json::from_str(&json_data_str)
+                .map_err(|_e| r2d2_sqlite::rusqlite::Error::ExecuteReturnedResults)?;
+
+            Ok((id, user_name, user_pwd, account_type, face_token, json_data))
+        })
+        .map_err(|e| format!("查询面容数据失败：{:?}", e))?;
+
+    let mut gallery = Vec::new();
+    for row in rows {
+        let (id, user_name, user_pwd, account_type, face_token, json_data) =
+            row.map_err(|e| format!("获取1条面容数据失败：{:?}", e))?;
+
+        if json_data.lock || json_data.suspended {
+            // 锁定或暂停了账户，直接跳过
+            continue;
+        }
+
+        // 加载所有模板，单个模板加载失败不影响其他模板
+        let mut descriptors = Vec::new();
+        for token in std::iter::once(&face_token).chain(json_data.templates.iter()) {
+            let path = get_global_log_path().join("faces").join(format!("{}.face", token));
+            match load_face_data(&path) {
+                Ok(descriptor) => descriptors.push(descriptor),
+                Err(_) => error!("加载面容数据失败：{:?}", path),
+            }
+        }
+        if json_data.aggregation == Aggregation::Centroid && !descriptors.is_empty() {
+            descriptors = vec![centroid(&json_data.alias, &descriptors)];
+        }
+
+        let mut features = Vec::with_capacity(descriptors.len());
+        for descriptor in &descriptors {
+            match descriptor.to_mat() {
+                Ok(feature) => features.push(feature),
+                Err(_) => error!("{}, 转换参考面容数据失败：{}", json_data.alias, descriptor.name),
+            }
+        }
+        if features.is_empty() {
+            // 没有可用的模板，跳过当前面容
+            continue;
+        }
+
+        gallery.push(GalleryFace {
+            id,
+            user_name,
+            user_pwd,
+            account_type,
+            extra: json_data,
+            features,
+            success_count: 0,
+            fail_count: 0,
+        });
+    }
+    Ok(gallery)
+}
+
+// 各模板特征归一化后的平均值，余弦相似度只看方向，归一化后每个模板的权重相同，结果同样归一化
+// 长度与第一个模板不同的模板（如换过识别模型）不参与计算
+fn centroid(name: &str, descriptors: &[FaceDescriptor]) -> FaceDescriptor {
+    let len = descriptors.first().map_or(0, |d| d.feature.len());
+    let mut feature = vec![0.0f32; len];
+    for descriptor in descriptors {
+        if descriptor.feature.len() != len {
+            warn!("{}：模板 {} 的特征长度为 {}，与其他模板（{}）不同，不参与质心计算", name, descriptor.name, descriptor.feature.len(), len);
+            continue;
+        }
+        let norm = l2_norm(&descriptor.feature);
+        for (sum, value) in feature.iter_mut().zip(&descriptor.feature) {
+            *sum += value / norm;
+        }
+    }
+    let norm = l2_norm(&feature);
+    feature.iter_mut().for_each(|value| *value /= norm);
+    FaceDescriptor { name: name.to_string(), feature }
+}
+
+fn l2_norm(values: &[f32]) -> f32 {
+    values.iter().map(|v| v * v).sum::<f32>().sqrt().max(f32::EPSILON)
+}
+
+// 最接近匹配的面容（连续成功次数最多）的连续成功 / 失败次数，用于识别事件
+fn leading_counts(gallery: &[GalleryFace]) -> (u32, u32) {
+    gallery
+        .iter()
+        .filter(|face| face.fail_count < MAX_FAIL)
+        .max_by_key(|face| (face.success_count, std::cmp::Reverse(face.fail_count)))
+        .map(|face| (face.success_count as u32, face.fail_count as u32))
+        .unwrap_or((0, MAX_FAIL as u32))
+}
+
+// 设置面容的暂停状态，user_name 为 None 时修改所有面容
+fn set_faces_suspended(conn: &r2d2_sqlite::rusqlite::Connection, user_name: Option<&str>, suspended: bool) -> Result<usize, String> {
+    conn.execute(
+        "UPDATE faces SET json_data = json_set(json_data, '$.suspended', json(?1)) WHERE ?2 IS NULL OR user_name = ?2",
+        r2d2_sqlite::rusqlite::params![if suspended { "true" } else { "false" }, user_name],
+    )
+    .map_err(|e| format!("更新面容暂停状态失败：{:?}", e))
+}
+
+// 用户手动登录成功，恢复所有被暂停的面容并清空 DLL 记录的失败次数
+pub fn resume_suspended_faces() {
+    if let Err(e) = delete_registry_tree(LSA_FAILURE_REG_PATH) {
+        error!("清空登录失败次数失败：{}", e.message());
+    }
+
+    let Some(pool) = get_db_pool() else {
+        return;
+    };
+    let result = pool
+        .get()
+        .map_err(|e| e.to_string())
+        .and_then(|conn| set_faces_suspended(&conn, None, false));
+    match result {
+        Ok(count) if count > 0 => info!("手动登录成功，已恢复 {} 个面容", count),
+        Ok(_) => {}
+        Err(e) => error!("恢复面容失败：{}", e),
+    }
+}
+
 fn insert_unlock_log(
     conn: &r2d2_sqlite::rusqlite::Connection,
     face_id: i32,
     is_unlock: bool,
-    img_path: &str
+    img_path: &str,
+    request_id: Option<&str>,
+    result: Option<&str>
 ) -> Result<(), String> {
     let mut insert_stmt = conn
-        .prepare("INSERT INTO unlock_log (face_id, is_unlock, block_img) VALUES (?1, ?2, ?3)")
+        .prepare("INSERT INTO unlock_log (face_id, is_unlock, block_img, request_id, result) VALUES (?1, ?2, ?3, ?4, ?5)")
         .map_err(|e| format!("准备插入解锁日志语句失败：{:?}", e))?;
 
     // 插入数据
@@ -653,7 +867,9 @@ fn insert_unlock_log(
         .execute(r2d2_sqlite::rusqlite::params![
             face_id,
             if is_unlock { 1 } else { 0 },
-            if img_path.is_empty() { None } else { Some(img_path) }
+            if img_path.is_empty() { None } else { Some(img_path) },
+            request_id,
+            result
         ])
         .map_err(|e| format!("插入解锁日志失败：{:?}", e))?;
     Ok(())
@@ -724,4 +940,65 @@ fn align_face(frame: &Mat, face_data: &[f32]) -> opencv::Result<Mat> {
     )?;
 
     Ok(aligned_face)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(name: &str, feature: &[f32]) -> FaceDescriptor {
+        FaceDescriptor { name: name.to_string(), feature: feature.to_vec() }
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn combine_max_and_centroid_take_the_best() {
+        let scores = vec![0.3, 0.9, 0.5];
+        assert_eq!(Aggregation::Max.combine(scores, 2), 0.9);
+        assert_eq!(Aggregation::Centroid.combine(vec![0.42], 2), 0.42);
+        assert_eq!(Aggregation::Max.combine(vec![-0.2, -0.1], 2), -0.1);
+    }
+
+    #[test]
+    fn combine_top_k_averages_the_best() {
+        assert!((Aggregation::TopK.combine(vec![0.3, 0.9, 0.5], 2) - 0.7).abs() < 1e-9);
+        // top_k 大于模板数时取全部模板的平均
+        assert!((Aggregation::TopK.combine(vec![0.3, 0.9], 5) - 0.6).abs() < 1e-9);
+        // top_k 为 0 时按 1 处理
+        assert_eq!(Aggregation::TopK.combine(vec![0.3, 0.9], 0), 0.9);
+    }
+
+    #[test]
+    fn combine_without_scores_never_matches() {
+        for aggregation in [Aggregation::Max, Aggregation::TopK, Aggregation::Centroid] {
+            assert_eq!(aggregation.combine(Vec::new(), 2), 0.0);
+        }
+    }
+
+    #[test]
+    fn centroid_is_normalized_and_weights_templates_equally() {
+        let c = centroid("a", &[descriptor("t1", &[3.0, 4.0]), descriptor("t2", &[0.0, 10.0])]);
+        // [0.6, 0.8] + [0, 1] 再归一化
+        let norm = (0.6f32 * 0.6 + 1.8 * 1.8).sqrt();
+        assert_close(&c.feature, &[0.6 / norm, 1.8 / norm]);
+        assert!((l2_norm(&c.feature) - 1.0).abs() < 1e-6);
+
+        // 模板的长度不影响权重
+        let scaled = centroid("a", &[descriptor("t1", &[30.0, 40.0]), descriptor("t2", &[0.0, 0.1])]);
+        assert_close(&scaled.feature, &c.feature);
+    }
+
+    #[test]
+    fn centroid_skips_templates_of_other_lengths() {
+        let c = centroid("a", &[descriptor("t1", &[1.0, 0.0]), descriptor("t2", &[0.0, 1.0, 5.0]), descriptor("t3", &[1.0, 0.0])]);
+        assert_close(&c.feature, &[1.0, 0.0]);
+
+        assert!(centroid("a", &[]).feature.is_empty());
+    }
+}
@@ -1,26 +1,29 @@
 use std::{
     path::PathBuf,
-    sync::{atomic::{AtomicBool, AtomicI32, AtomicU32}, Mutex},
+    sync::{atomic::{AtomicBool, AtomicI32, Ordering}, Arc, Mutex},
 };
 
 use log::info;
+use protocol::{pipe::Shutdown, DbPoolStatus, Health, RecognitionOptions, ServiceStatus, PROTOCOL_VERSION};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use windows::Win32::Foundation::HWND;
 
+use crate::utils::get_last_send_time;
+
 pub static EXIT: AtomicBool = AtomicBool::new(false);
 pub const LOOP_MILLIS: u64 = 50;
 // 是否正在运行面容识别？
 pub static IS_RUN: AtomicBool = AtomicBool::new(false);
 // 计时器，确定何时调用面容识别代码
 pub const TIMER_ID_LOCK_CHECK: usize = 1001;
-pub static FACE_RECOG_DELAY: AtomicU32 = AtomicU32::new(10000);
 
 // 是否允许调用面容识别代码？
 pub static ALLOW_UNLOCK: AtomicBool = AtomicBool::new(false);
 
-// 全局摄像头索引
-pub static CAMERA_INDEX: AtomicI32 = AtomicI32::new(0);
+// 本次锁屏是否由面容解锁，解锁事件据此判断用户是否手动输入了密码
+pub static FACE_UNLOCKED: AtomicBool = AtomicBool::new(false);
+
 // 面容不匹配时，当前的尝试次数
 pub static MATCH_FAIL_COUNT: AtomicI32 = AtomicI32::new(0);
 
@@ -30,14 +33,6 @@ pub const MAX_SUCCESS: usize = 3;
 pub const MAX_FAIL: usize = 3;
 // 最大重试次数，这不能让用户自己输入，如果错误次数太多，微软会锁定账户的，很危险
 pub const MAX_RETRY: i32 = 3;
-// 多长时间进行重试？
-pub static RETRY_DELAY: AtomicI32 = AtomicI32::new(10000);
-// 是否启用活体检测
-pub static LIVENESS_ENABLE: AtomicBool = AtomicBool::new(false);
-// 活体检测阈值
-pub static LIVENESS_THRESHOLD: AtomicU32 = AtomicU32::new(50);
-// 未检测到人脸时多少秒停止面容识别
-pub static NOT_FACE_DELAY: AtomicU32 = AtomicU32::new(3);
 
 #[derive(Debug, Clone, Copy)]
 pub struct SafeHWND(HWND);
@@ -59,8 +54,11 @@ lazy_static::lazy_static! {
     pub static ref DB_POOL: Mutex<Option<Pool<SqliteConnectionManager>>> = Mutex::new(None);
     static ref ROOT_DIR: Mutex<PathBuf> = Mutex::new(PathBuf::new());
     static ref GLOBAL_HWND: Mutex<Option<SafeHWND>> = Mutex::new(None);
-    static ref FACE_RECOG_TYPE: Mutex<String> = Mutex::new(String::from("operation"));
-    static ref FACE_ALIGNED_TYPE: Mutex<String> = Mutex::new(String::from("default"));
+    // 识别配置，整体替换，读取方拿到的总是同一份完整配置
+    static ref OPTIONS: Mutex<Arc<RecognitionOptions>> = Mutex::new(Arc::new(RecognitionOptions::default()));
+    static ref CAMERA_STATE: Mutex<Option<Health>> = Mutex::new(None);
+    // 管道关闭信号，触发后所有管道线程退出
+    pub static ref SHUTDOWN: Shutdown = Shutdown::new().expect("创建管道关闭事件失败");
 }
 
 // 获取全局路径
@@ -92,26 +90,72 @@ pub fn get_global_hwnd() -> Option<SafeHWND> {
     global_hwnd.clone()
 }
 
-// 设置面容识别模式
-pub fn set_face_recognition_mode(mode: String) {
-    let mut global_face_recognition_mode = FACE_RECOG_TYPE.lock().unwrap();
-    *global_face_recognition_mode = mode;
+// 替换识别配置
+pub fn set_options(options: RecognitionOptions) {
+    let mut global_options = OPTIONS.lock().unwrap();
+    *global_options = Arc::new(options);
+}
+
+// 获取当前识别配置，一次识别过程中应只取一次
+pub fn get_options() -> Arc<RecognitionOptions> {
+    let global_options = OPTIONS.lock().unwrap();
+    global_options.clone()
+}
+
+// 记录最近一次打开摄像头的结果
+pub fn set_camera_state(state: Health) {
+    let mut camera_state = CAMERA_STATE.lock().unwrap();
+    *camera_state = Some(state);
 }
 
-// 获取面容识别模式
-pub fn get_face_recognition_mode() -> String {
-    let global_face_recognition_mode = FACE_RECOG_TYPE.lock().unwrap();
-    global_face_recognition_mode.clone()
+// 检查模型文件是否齐全，活体检测模型只在启用时需要
+fn check_models() -> Health {
+    let resources = get_global_log_path().join("resources");
+    let mut models = vec!["face_detection_yunet_2023mar.onnx", "face_recognition_sface_2021dec.onnx"];
+    if get_options().liveness_enabled {
+        models.push("face_liveness.onnx");
+    }
+
+    let missing: Vec<&str> = models.into_iter().filter(|name| !resources.join(name).exists()).collect();
+    if missing.is_empty() {
+        Health::ok("OpenCV")
+    } else {
+        Health::failed(format!("缺少模型文件：{}", missing.join("、")))
+    }
 }
 
-// 设置面容对齐模式
-pub fn set_face_aligned_mode(mode: String) {
-    let mut global_face_aligned_mode = FACE_ALIGNED_TYPE.lock().unwrap();
-    *global_face_aligned_mode = mode;
+// 获取数据库连接池，Pool 内部是 Arc，副本与 DB_POOL 共用同一组连接
+// 取到后立即释放 DB_POOL 的锁，长时间使用连接时不会阻塞其他线程
+pub fn get_db_pool() -> Option<Pool<SqliteConnectionManager>> {
+    DB_POOL.lock().unwrap().clone()
 }
 
-// 获取面容对齐模式
-pub fn get_face_aligned_mode() -> String {
-    let global_face_aligned_mode = FACE_ALIGNED_TYPE.lock().unwrap();
-    global_face_aligned_mode.clone()
+// 获取服务运行状态快照
+pub fn status_snapshot() -> ServiceStatus {
+    let db_pool = match DB_POOL.lock().unwrap().as_ref() {
+        Some(pool) => {
+            let state = pool.state();
+            DbPoolStatus { initialized: true, connections: state.connections, idle_connections: state.idle_connections }
+        }
+        None => DbPoolStatus { initialized: false, connections: 0, idle_connections: 0 },
+    };
+
+    let last_attempt = get_last_send_time();
+    let options = get_options();
+
+    ServiceStatus {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: PROTOCOL_VERSION,
+        is_run: IS_RUN.load(Ordering::SeqCst),
+        allow_unlock: ALLOW_UNLOCK.load(Ordering::SeqCst),
+        match_fail_count: MATCH_FAIL_COUNT.load(Ordering::SeqCst),
+        max_retry: MAX_RETRY,
+        recognition_mode: options.recognition_mode.clone(),
+        aligned_mode: options.aligned_mode.clone(),
+        camera_index: options.camera_index,
+        camera: CAMERA_STATE.lock().unwrap().clone(),
+        models: check_models(),
+        db_pool,
+        last_attempt_ms: if last_attempt == 0 { None } else { Some(last_attempt as u64) },
+    }
 }
@@ -1,6 +1,7 @@
 use std::{fs::File, thread as std_thread};
 
-use global::{get_global_log_path, set_global_hwnd};
+use events::event_pipe_loop;
+use global::{get_global_log_path, set_global_hwnd, SHUTDOWN};
 use log::{info, LevelFilter};
 use simplelog::{CombinedLogger, ConfigBuilder, TermLogger, WriteLogger};
 use thread::{connect_sqlite, get_install_path, pipe_message_loop};
@@ -27,8 +28,8 @@ pub mod global;
 pub mod utils;
 pub mod proc;
 pub mod thread;
-pub mod pipe;
 pub mod face;
+pub mod events;
 
 // 注册窗口类并创建窗口
 fn create_message_window() -> windows::core::Result<HWND> {
@@ -90,6 +91,8 @@ fn create_message_window() -> windows::core::Result<HWND> {
 fn main() -> windows::core::Result<()> {
     println!("正在初始化...");
     let pipe_thread = std_thread::spawn(pipe_message_loop);
+    // 识别事件推送线程
+    let event_thread = std_thread::spawn(event_pipe_loop);
     println!("获取软件安装目录...");
     let thread = std_thread::spawn(get_install_path);
     thread.join().unwrap();
@@ -97,8 +100,9 @@ fn main() -> windows::core::Result<()> {
     
     // 初始化日志系统
     if let Ok(file) = File::create(get_global_log_path().join("logs").join("unlock.log")) {
-        // 日志时间太麻烦，不搞了，没有日期影响不大
+        // 日志带上完整日期，方便和 DLL、UI 的日志按尝试 ID 对照
         if let Ok(config) = ConfigBuilder::new().set_time_offset_to_local(){
+            config.set_time_format_rfc3339();
             match CombinedLogger::init(
                 vec![
                     TermLogger::new(
@@ -156,7 +160,10 @@ fn main() -> windows::core::Result<()> {
     }
 
     info!("程序退出，等待线程退出");
+    // 窗口可能不是因为 Exit 指令关闭的，这里再通知一次管道线程
+    SHUTDOWN.signal();
     pipe_thread.join().unwrap();
+    event_thread.join().unwrap();
 
     Ok(())
 }
\ No newline at end of file
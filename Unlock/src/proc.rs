@@ -1,6 +1,7 @@
 use std::sync::atomic::Ordering;
 
 use log::{error, info};
+use protocol::{AttemptTimer, Stage};
 use windows::
     Win32::{
         Foundation::{HWND, LPARAM, LRESULT, WPARAM},
@@ -15,19 +16,21 @@ use windows::
     }
 ;
 
-use crate::{face::{prepare_before, run_before}, global::{get_face_recognition_mode, ALLOW_UNLOCK, FACE_RECOG_DELAY, IS_RUN, MATCH_FAIL_COUNT, TIMER_ID_LOCK_CHECK}};
+use crate::{events::mark, face::{prepare_before, resume_suspended_faces, run_before}, global::{get_options, ALLOW_UNLOCK, FACE_UNLOCKED, IS_RUN, MATCH_FAIL_COUNT, TIMER_ID_LOCK_CHECK}};
 
 pub fn lock(hwnd: HWND){
     MATCH_FAIL_COUNT.store(0, Ordering::SeqCst);
+    FACE_UNLOCKED.store(false, Ordering::SeqCst);
     match prepare_before() {
         Ok(_) => {
             ALLOW_UNLOCK.store(true, Ordering::SeqCst);
-            if get_face_recognition_mode() != "operation" { 
+            let options = get_options();
+            if options.recognition_mode != "operation" { 
                 // 如果是按延迟时间，这里启动定时器
                 IS_RUN.store(true, Ordering::SeqCst);
                 // 设置一个定时器
                 // 当时间到达时，系统会发送 WM_TIMER 消息
-                let time_ms = FACE_RECOG_DELAY.load(Ordering::SeqCst);
+                let time_ms = options.recognition_delay_ms;
                 unsafe {
                     SetTimer(
                         Some(hwnd),
@@ -65,6 +68,12 @@ pub unsafe extern "system" fn window_proc(
                 WTS_SESSION_UNLOCK => {
                     ALLOW_UNLOCK.store(false, Ordering::SeqCst);
                     IS_RUN.store(false, Ordering::SeqCst);
+                    // 用户已经解锁，锁屏时的失败次数不再影响 UAC 对话框中的面容验证
+                    MATCH_FAIL_COUNT.store(0, Ordering::SeqCst);
+                    // 不是面容解锁的，说明用户手动输入了密码，恢复被暂停的面容
+                    if !FACE_UNLOCKED.swap(false, Ordering::SeqCst) {
+                        resume_suspended_faces();
+                    }
                     // 解锁取消计时器
                     unsafe {
                         let _ = KillTimer(Some(hwnd), TIMER_ID_LOCK_CHECK);
@@ -84,7 +93,10 @@ pub unsafe extern "system" fn window_proc(
     
                 // 二次检查状态
                 if IS_RUN.load(Ordering::SeqCst) {
-                    run_before();
+                    // 延迟模式下由计时器触发，尝试从这里开始计时
+                    let mut attempt = AttemptTimer::start();
+                    mark(&mut attempt, Stage::Trigger);
+                    run_before(attempt);
                 }
             }
             LRESULT(0)
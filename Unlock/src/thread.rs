@@ -1,74 +1,142 @@
-use std::{sync::atomic::Ordering, thread::sleep, time::Duration};
+use std::{sync::atomic::Ordering, thread::{self, sleep}, time::Duration};
 
 use log::{error, info, warn};
+use protocol::{
+    auth::UNLOCK_PIPE_CLIENTS, dispatch::{serve_unlock, Flow, UnlockService}, pipe::PipeListener, trace::now_ms, AttemptTimer, ReloadOutcome, RetryOutcome, Role, ServiceStatus, Stage, UnlockOutcome,
+    UNLOCK_PIPE_NAME,
+};
 use r2d2::Pool;
 use r2d2_sqlite::rusqlite;
-use windows::{core::HSTRING, Win32::UI::WindowsAndMessaging::{SendMessageW, WM_CLOSE}};
+use windows::Win32::UI::WindowsAndMessaging::{SendMessageW, WM_CLOSE};
 
-use crate::{face::{run_before, unlock}, global::{get_face_recognition_mode, get_global_hwnd, get_global_log_path, set_global_log_path, DB_POOL, EXIT, IS_RUN, LOOP_MILLIS, MATCH_FAIL_COUNT, MAX_RETRY}, pipe::Server, utils::{can_retry, read_facewinunlock_registry}};
+use crate::{events::mark, face::{prepare_before, reload_options, run_before, unlock}, global::{get_options, get_global_hwnd, ALLOW_UNLOCK, status_snapshot, get_global_log_path, set_global_log_path, DB_POOL, EXIT, IS_RUN, LOOP_MILLIS, MATCH_FAIL_COUNT, MAX_RETRY, SHUTDOWN}, utils::{can_retry, read_facewinunlock_registry}};
 
-// 管道消息处理
-pub fn pipe_message_loop() {
-    let mut server = Server::new(HSTRING::from(r"\\.\pipe\MansonWindowsUnlockRustUnlock"));
-    loop {
-        if EXIT.load(Ordering::SeqCst) {
-            break;
+// Unlock 管道上的指令处理
+struct UnlockHandler;
+
+impl UnlockService for UnlockHandler {
+    fn run(&mut self, mut attempt: AttemptTimer) {
+        // info!("IS_RUN: {}, MATCH_FAIL_COUNT: {}, {}", IS_RUN.load(Ordering::SeqCst), MATCH_FAIL_COUNT.load(Ordering::SeqCst), get_options().recognition_mode);
+        if !IS_RUN.load(Ordering::SeqCst) && MATCH_FAIL_COUNT.load(Ordering::SeqCst) < MAX_RETRY && get_options().recognition_mode == "operation"{
+            // 多个连接可能同时收到 Run，只让一个开始识别
+            if can_retry() && IS_RUN.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                info!("运行面容识别代码");
+                mark(&mut attempt, Stage::Received);
+                run_before(attempt);
+            }
+        }
+    }
+
+    fn retry(&mut self, mut attempt: AttemptTimer) -> RetryOutcome {
+        // 用户主动点击，不检查 can_retry 的间隔，但失败次数上限仍然有效
+        if !ALLOW_UNLOCK.load(Ordering::SeqCst) {
+            return RetryOutcome::Unavailable { reason: String::from("识别配置未就绪") };
+        }
+        if MATCH_FAIL_COUNT.load(Ordering::SeqCst) >= MAX_RETRY {
+            return RetryOutcome::LockedOut { max_retry: MAX_RETRY as u32 };
         }
-        let f_connected = server.connect();
-        if f_connected.is_err() {
-            error!("管道连接失败：{:?}", f_connected.err());
+        if IS_RUN.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            return RetryOutcome::Busy;
+        }
+
+        info!("[attempt {}] 用户请求重新识别", attempt.id());
+        mark(&mut attempt, Stage::Received);
+        // 先回复 DLL，识别放到单独的线程中
+        thread::spawn(move || run_before(attempt));
+        RetryOutcome::Started
+    }
+
+    fn verify(&mut self, mut attempt: AttemptTimer) -> RetryOutcome {
+        // UAC 对话框不在锁屏状态，需要自己检查录入情况并读取识别配置
+        if let Err(e) = prepare_before() {
+            return RetryOutcome::Unavailable { reason: e };
+        }
+        if MATCH_FAIL_COUNT.load(Ordering::SeqCst) >= MAX_RETRY {
+            return RetryOutcome::LockedOut { max_retry: MAX_RETRY as u32 };
+        }
+        if IS_RUN.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            return RetryOutcome::Busy;
+        }
+
+        info!("[attempt {}] CredUI 请求验证面容", attempt.id());
+        mark(&mut attempt, Stage::Received);
+        thread::spawn(move || run_before(attempt));
+        RetryOutcome::Started
+    }
+
+    fn exit(&mut self) {
+        info!("收到退出指令");
+        EXIT.store(true, Ordering::SeqCst);
+        SHUTDOWN.signal();
+        if let Some(safe_hwnd) = get_global_hwnd() {
+            let hwnd = safe_hwnd.get();
+            unsafe {
+                SendMessageW(
+                    hwnd,        // 目标 HWND
+                    WM_CLOSE,    // 关闭窗口消息
+                    None,
+                    None
+                );
+            }
+            info!("已向全局 HWND 发送 WM_CLOSE 关闭指令");
         } else {
-            match crate::pipe::read(server.handle) {
-                Ok(content) => {
-                    if content == "exit" {
-                        info!("收到退出指令");
-                        EXIT.store(true, Ordering::SeqCst);
-                        if let Some(safe_hwnd) = get_global_hwnd() {
-                            let hwnd = safe_hwnd.get();
-                            unsafe {
-                                SendMessageW(
-                                    hwnd,        // 目标 HWND
-                                    WM_CLOSE,    // 关闭窗口消息
-                                    None,
-                                    None
-                                );
-                            }
-                            info!("已向全局 HWND 发送 WM_CLOSE 关闭指令");
-                        } else {
-                            warn!("全局 HWND 未初始化，无法发送关闭指令");
-                        }
-                        break;
-                    } else if content == "run" {
-                        // info!("IS_RUN: {}, MATCH_FAIL_COUNT: {}, {}", IS_RUN.load(Ordering::SeqCst), MATCH_FAIL_COUNT.load(Ordering::SeqCst), get_face_recognition_mode());
-                        if !IS_RUN.load(Ordering::SeqCst) && MATCH_FAIL_COUNT.load(Ordering::SeqCst) < MAX_RETRY && get_face_recognition_mode() == "operation"{
-                            if can_retry() {
-                                IS_RUN.store(true, Ordering::SeqCst);
-                                info!("运行面容识别代码");
-                                run_before();
-                            }
-                        }
-                    } else if content.contains("unlockFromClient::") {
-                        let parts: Vec<&str> = content.split("::").collect();
-                        if parts.len() == 4 {
-                            let username = parts[1].trim().to_string();
-                            let password = parts[3].trim().to_string();
-
-                            if let Err(e) = unlock(username, password) {
-                                error!("解锁失败: {:?}", e);
-                            }
-                        } else {
-                            error!("无效的字符串格式，期望 4 段（:: 分隔），实际 {} 段: {}", parts.len(), content);
-                        }
-                    }
-                }
-                Err(_e) => {
-                    // 先不记了
-                    // error!("读取管道数据失败：{:?}", e);
-                }
+            warn!("全局 HWND 未初始化，无法发送关闭指令");
+        }
+    }
+
+    fn unlock(&mut self, request_id: &str, user_name: String, password: String) -> UnlockOutcome {
+        // UI 发起的解锁，请求 ID 即尝试 ID，从收到请求开始计时
+        let mut attempt = AttemptTimer::resume(request_id, now_ms());
+        match unlock(&mut attempt, user_name, password) {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                error!("解锁失败: {:?}", e);
+                UnlockOutcome::Rejected { reason: format!("无法连接凭据提供程序：{}", e.message()) }
             }
-            let _ = server.disconnect();
         }
     }
+
+    fn status(&mut self) -> ServiceStatus {
+        status_snapshot()
+    }
+
+    fn reload(&mut self) -> ReloadOutcome {
+        let outcome = reload_options();
+        info!("重新加载识别配置：{:?}", outcome);
+        outcome
+    }
+}
+
+// 管道消息处理，每个连接在单独的线程中处理，识别过程不会卡住其他请求
+pub fn pipe_message_loop() {
+    let listener = match PipeListener::bind(UNLOCK_PIPE_NAME, &SHUTDOWN) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("管道创建失败：{:?}", e);
+            return;
+        }
+    };
+
+    let result = listener.serve(|stream| {
+        // 只接受 DLL 所在的 LogonUI 和 UI 的连接
+        if let Err(e) = stream.authorize(UNLOCK_PIPE_CLIENTS) {
+            warn!("管道客户端校验失败，已拒绝连接：{:?}", e);
+            return Flow::Continue;
+        }
+        // 握手失败说明对方没有 PSK，拒绝这个连接
+        let mut channel = match stream.handshake(Role::Server) {
+            Ok(channel) => channel,
+            Err(e) => {
+                warn!("管道握手失败，已拒绝连接：{:?}", e);
+                return Flow::Continue;
+            }
+        };
+        // 读取失败先不记了，Ping 之类的探测连接读完就断开
+        serve_unlock(&mut channel, &mut UnlockHandler).unwrap_or(Flow::Continue)
+    });
+    if let Err(e) = result {
+        error!("管道监听失败：{:?}", e);
+    }
     info!("管道线程安全卸载完成");
 }
 // 获取软件安装目录
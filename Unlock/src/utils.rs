@@ -1,17 +1,17 @@
-use std::{ffi::OsStr, fs, os::windows::ffi::OsStrExt, sync::atomic::Ordering, time::{SystemTime, UNIX_EPOCH}};
+use std::{ffi::OsStr, fs, os::windows::ffi::OsStrExt, time::{SystemTime, UNIX_EPOCH}};
 
 use log::{info, warn};
 use opencv::{core::{Mat, Vector}, imgcodecs::imencode};
 use windows::{
     core::{Error, PCWSTR, PWSTR}, Win32::{
-        Foundation::E_UNEXPECTED,
+        Foundation::{E_UNEXPECTED, ERROR_FILE_NOT_FOUND},
         System::{Registry::{
-            RegCloseKey, RegOpenKeyExW, RegQueryValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_READ, REG_SZ, REG_VALUE_TYPE
+            RegCloseKey, RegDeleteTreeW, RegOpenKeyExW, RegQueryValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_READ, REG_SZ, REG_VALUE_TYPE
         }, RemoteDesktop::{WTSFreeMemory, WTSGetActiveConsoleSessionId, WTSQuerySessionInformationW, WTSSessionInfoEx, WTSINFOEXW}},
     }
 };
 
-use crate::global::RETRY_DELAY;
+use crate::global::get_options;
 
 // 记录上一次发送管道消息的时间戳（毫秒）
 static mut LAST_SEND_TIME: u128 = 0;
@@ -30,19 +30,17 @@ pub fn set_last_send_time() {
     }
 }
 
+// 上一次识别的时间戳（毫秒），从未识别过为 0
+pub fn get_last_send_time() -> u128 {
+    unsafe { LAST_SEND_TIME }
+}
+
 pub fn can_retry() -> bool {
     unsafe {
         // 获取当前时间戳（毫秒）
         let now = get_system_time();
 
-        let delay: u128 = match RETRY_DELAY.load(Ordering::SeqCst).try_into() {
-            Ok(interval) => {
-                interval
-            },
-            Err(_) => {
-                10000
-            }
-        };
+        let delay = get_options().retry_delay_ms as u128;
 
         // 如果距离上次发送超过最小间隔，更新时间并允许发送
         if now - LAST_SEND_TIME >= delay {
@@ -56,7 +54,21 @@ pub fn can_retry() -> bool {
 
 /// 读取注册表数据
 pub fn read_facewinunlock_registry(key_name: &str) -> windows::core::Result<String> {
-    let reg_path = "SOFTWARE\\facewinunlock-tauri";
+    read_registry_string("SOFTWARE\\facewinunlock-tauri", key_name)
+}
+
+/// 删除HKLM下的子项及其所有值，子项不存在时视为成功
+pub fn delete_registry_tree(reg_path: &str) -> windows::core::Result<()> {
+    let reg_path_ptr: Vec<u16> = OsStr::new(reg_path).encode_wide().chain(std::iter::once(0)).collect();
+    let status = unsafe { RegDeleteTreeW(HKEY_LOCAL_MACHINE, PCWSTR::from_raw(reg_path_ptr.as_ptr())) };
+    if status.is_err() && status != ERROR_FILE_NOT_FOUND {
+        return Err(Error::new(E_UNEXPECTED, format!("删除注册表项失败: {}", status.0)));
+    }
+    Ok(())
+}
+
+/// 读取HKLM下指定子项中的字符串值
+pub fn read_registry_string(reg_path: &str, key_name: &str) -> windows::core::Result<String> {
     // 打开HKLM下的注册表项
     let mut hkey: HKEY = HKEY::default();
 